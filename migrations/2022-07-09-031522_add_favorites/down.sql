@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS favorite_list_location_rels;
+DROP TRIGGER IF EXISTS update_favorite_lists_update_on ON favorite_lists;
+DROP TABLE IF EXISTS favorite_lists;
+DROP INDEX IF EXISTS favorites_location;
+DROP TABLE IF EXISTS favorites;
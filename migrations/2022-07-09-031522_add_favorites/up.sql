@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS favorites (
+	id SERIAL NOT NULL,
+	"user" INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	location INT NOT NULL REFERENCES locations (id) ON DELETE CASCADE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_favorite_user_location UNIQUE ("user", location)
+);
+
+CREATE INDEX IF NOT EXISTS favorites_location ON favorites USING BTREE (location);
+
+CREATE TABLE IF NOT EXISTS favorite_lists (
+	id SERIAL NOT NULL,
+	name VARCHAR NOT NULL,
+	owner INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	share_code VARCHAR,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_favorite_list_share_code UNIQUE (share_code)
+);
+
+CREATE TRIGGER update_favorite_lists_update_on BEFORE UPDATE ON favorite_lists FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+CREATE TABLE IF NOT EXISTS favorite_list_location_rels (
+	id SERIAL NOT NULL,
+	list INT NOT NULL REFERENCES favorite_lists (id) ON DELETE CASCADE,
+	location INT NOT NULL REFERENCES locations (id) ON DELETE CASCADE,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_list_location UNIQUE (list, location)
+);
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS comments_search_trgm;
+DROP INDEX IF EXISTS comments_search;
+DROP INDEX IF EXISTS memories_search_trgm;
+DROP INDEX IF EXISTS memories_search;
+DROP INDEX IF EXISTS locations_search_trgm;
+DROP INDEX IF EXISTS locations_search;
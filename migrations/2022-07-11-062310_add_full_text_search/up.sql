@@ -0,0 +1,11 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- 'simple' 配置不做分词, 中文依靠下面的 trigram 索引匹配
+CREATE INDEX IF NOT EXISTS locations_search ON locations USING GIN (to_tsvector('simple', (name || ' ' || description)));
+CREATE INDEX IF NOT EXISTS locations_search_trgm ON locations USING GIN ((name || ' ' || description) gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS memories_search ON memories USING GIN (to_tsvector('simple', (title || ' ' || content)));
+CREATE INDEX IF NOT EXISTS memories_search_trgm ON memories USING GIN ((title || ' ' || content) gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS comments_search ON comments USING GIN (to_tsvector('simple', content));
+CREATE INDEX IF NOT EXISTS comments_search_trgm ON comments USING GIN (content gin_trgm_ops);
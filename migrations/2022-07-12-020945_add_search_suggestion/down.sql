@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS equipments_name_trgm;
+DROP INDEX IF EXISTS locations_name_trgm;
+DROP TRIGGER IF EXISTS update_categories_update_on ON categories;
+DROP TABLE IF EXISTS categories;
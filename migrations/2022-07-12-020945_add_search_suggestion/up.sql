@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS categories (
+	id SERIAL NOT NULL,
+	name VARCHAR NOT NULL,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_category_name UNIQUE (name)
+);
+
+CREATE TRIGGER update_categories_update_on BEFORE UPDATE ON categories FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+CREATE INDEX IF NOT EXISTS locations_name_trgm ON locations USING GIN (name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS equipments_name_trgm ON equipments USING GIN (name gin_trgm_ops);
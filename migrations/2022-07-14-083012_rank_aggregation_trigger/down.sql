@@ -0,0 +1,15 @@
+DROP INDEX IF EXISTS rank_aggregations_score;
+DROP TRIGGER IF EXISTS comments_rank_aggregation ON comments;
+DROP FUNCTION IF EXISTS recompute_rank_aggregations;
+DROP FUNCTION IF EXISTS maintain_rank_aggregation;
+DROP FUNCTION IF EXISTS apply_comment_rank;
+DROP FUNCTION IF EXISTS bayesian_rank;
+ALTER TABLE users DROP COLUMN is_admin;
+ALTER TABLE rank_aggregations
+DROP COLUMN rank_1,
+DROP COLUMN rank_2,
+DROP COLUMN rank_3,
+DROP COLUMN rank_4,
+DROP COLUMN rank_5,
+DROP COLUMN score;
+ALTER TABLE comments DROP CONSTRAINT chk_comment_rank;
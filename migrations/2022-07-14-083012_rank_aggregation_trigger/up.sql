@@ -0,0 +1,96 @@
+ALTER TABLE comments ADD CONSTRAINT chk_comment_rank CHECK (rank BETWEEN 1 AND 5) NOT VALID;
+
+ALTER TABLE rank_aggregations
+ADD COLUMN rank_1 BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN rank_2 BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN rank_3 BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN rank_4 BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN rank_5 BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN score DOUBLE PRECISION NOT NULL DEFAULT 3.0;
+
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- 贝叶斯平均: 相当于每个地点预先有 5 条 3 分的评论, 评论越少越接近 3 分
+CREATE FUNCTION bayesian_rank(total BIGINT, count BIGINT)
+RETURNS DOUBLE PRECISION AS $$
+	SELECT (3.0 * 5 + total) / (5 + count);
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE FUNCTION apply_comment_rank(loc INT, r INT, delta INT)
+RETURNS VOID AS $$
+begin
+	INSERT INTO rank_aggregations (total, count, location_id) VALUES (0, 0, loc) ON CONFLICT (location_id) DO NOTHING;
+	UPDATE rank_aggregations SET
+		total = total + r * delta,
+		count = count + delta,
+		rank_1 = rank_1 + CASE WHEN r = 1 THEN delta ELSE 0 END,
+		rank_2 = rank_2 + CASE WHEN r = 2 THEN delta ELSE 0 END,
+		rank_3 = rank_3 + CASE WHEN r = 3 THEN delta ELSE 0 END,
+		rank_4 = rank_4 + CASE WHEN r = 4 THEN delta ELSE 0 END,
+		rank_5 = rank_5 + CASE WHEN r = 5 THEN delta ELSE 0 END,
+		score = bayesian_rank(total + r * delta, count + delta)
+	WHERE location_id = loc;
+end
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION maintain_rank_aggregation()
+RETURNS TRIGGER AS $$
+begin
+	IF TG_OP IN ('UPDATE', 'DELETE') THEN
+		PERFORM apply_comment_rank(OLD.location, OLD.rank, -1);
+	END IF;
+	IF TG_OP IN ('INSERT', 'UPDATE') THEN
+		PERFORM apply_comment_rank(NEW.location, NEW.rank, 1);
+	END IF;
+	RETURN NULL;
+end
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER comments_rank_aggregation
+	AFTER INSERT OR DELETE OR UPDATE OF rank, location
+	ON comments
+	FOR EACH ROW
+	EXECUTE PROCEDURE maintain_rank_aggregation();
+
+CREATE INDEX IF NOT EXISTS rank_aggregations_score ON rank_aggregations USING BTREE (score);
+
+-- 按评论重新计算聚合, loc 为 NULL 时重算全部地点, 返回更新的行数
+CREATE FUNCTION recompute_rank_aggregations(loc INT)
+RETURNS BIGINT AS $$
+declare
+	affected BIGINT;
+begin
+	INSERT INTO rank_aggregations (total, count, location_id)
+	SELECT 0, 0, id FROM locations WHERE loc IS NULL OR id = loc
+	ON CONFLICT (location_id) DO NOTHING;
+	UPDATE rank_aggregations AS ra SET
+		total = s.total,
+		count = s.count,
+		rank_1 = s.rank_1,
+		rank_2 = s.rank_2,
+		rank_3 = s.rank_3,
+		rank_4 = s.rank_4,
+		rank_5 = s.rank_5,
+		score = bayesian_rank(s.total, s.count)
+	FROM (
+		SELECT
+			l.id AS location_id,
+			COALESCE(SUM(c.rank), 0) AS total,
+			COUNT(c.id) AS count,
+			COUNT(c.id) FILTER (WHERE c.rank = 1) AS rank_1,
+			COUNT(c.id) FILTER (WHERE c.rank = 2) AS rank_2,
+			COUNT(c.id) FILTER (WHERE c.rank = 3) AS rank_3,
+			COUNT(c.id) FILTER (WHERE c.rank = 4) AS rank_4,
+			COUNT(c.id) FILTER (WHERE c.rank = 5) AS rank_5
+		FROM locations AS l LEFT JOIN comments AS c ON c.location = l.id
+		WHERE loc IS NULL OR l.id = loc
+		GROUP BY l.id
+	) AS s
+	WHERE ra.location_id = s.location_id;
+	GET DIAGNOSTICS affected = ROW_COUNT;
+	RETURN affected;
+end
+$$ LANGUAGE plpgsql;
+
+-- 修正之前 handlers::comment::create 没有维护聚合造成的偏差
+SELECT recompute_rank_aggregations(NULL);
@@ -0,0 +1,75 @@
+DROP TRIGGER IF EXISTS comments_rank_aggregation ON comments;
+CREATE OR REPLACE FUNCTION maintain_rank_aggregation()
+RETURNS TRIGGER AS $$
+begin
+	IF TG_OP IN ('UPDATE', 'DELETE') THEN
+		PERFORM apply_comment_rank(OLD.location, OLD.rank, -1);
+	END IF;
+	IF TG_OP IN ('INSERT', 'UPDATE') THEN
+		PERFORM apply_comment_rank(NEW.location, NEW.rank, 1);
+	END IF;
+	RETURN NULL;
+end
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER comments_rank_aggregation
+	AFTER INSERT OR DELETE OR UPDATE OF rank, location
+	ON comments
+	FOR EACH ROW
+	EXECUTE PROCEDURE maintain_rank_aggregation();
+
+CREATE OR REPLACE FUNCTION recompute_rank_aggregations(loc INT)
+RETURNS BIGINT AS $$
+declare
+	affected BIGINT;
+begin
+	INSERT INTO rank_aggregations (total, count, location_id)
+	SELECT 0, 0, id FROM locations WHERE loc IS NULL OR id = loc
+	ON CONFLICT (location_id) DO NOTHING;
+	UPDATE rank_aggregations AS ra SET
+		total = s.total,
+		count = s.count,
+		rank_1 = s.rank_1,
+		rank_2 = s.rank_2,
+		rank_3 = s.rank_3,
+		rank_4 = s.rank_4,
+		rank_5 = s.rank_5,
+		score = bayesian_rank(s.total, s.count)
+	FROM (
+		SELECT
+			l.id AS location_id,
+			COALESCE(SUM(c.rank), 0) AS total,
+			COUNT(c.id) AS count,
+			COUNT(c.id) FILTER (WHERE c.rank = 1) AS rank_1,
+			COUNT(c.id) FILTER (WHERE c.rank = 2) AS rank_2,
+			COUNT(c.id) FILTER (WHERE c.rank = 3) AS rank_3,
+			COUNT(c.id) FILTER (WHERE c.rank = 4) AS rank_4,
+			COUNT(c.id) FILTER (WHERE c.rank = 5) AS rank_5
+		FROM locations AS l LEFT JOIN comments AS c ON c.location = l.id
+		WHERE loc IS NULL OR l.id = loc
+		GROUP BY l.id
+	) AS s
+	WHERE ra.location_id = s.location_id;
+	GET DIAGNOSTICS affected = ROW_COUNT;
+	RETURN affected;
+end
+$$ LANGUAGE plpgsql;
+
+DROP FUNCTION IF EXISTS apply_comment_dimensions;
+ALTER TABLE rank_aggregations
+DROP COLUMN cleanliness_total,
+DROP COLUMN cleanliness_count,
+DROP COLUMN space_total,
+DROP COLUMN space_count,
+DROP COLUMN staff_total,
+DROP COLUMN staff_count,
+DROP COLUMN accessibility_total,
+DROP COLUMN accessibility_count,
+DROP COLUMN noise_total,
+DROP COLUMN noise_count;
+ALTER TABLE comments
+DROP COLUMN cleanliness,
+DROP COLUMN space,
+DROP COLUMN staff,
+DROP COLUMN accessibility,
+DROP COLUMN noise;
@@ -0,0 +1,118 @@
+-- noise 分数越高表示越安静, 所有维度都是越高越好
+ALTER TABLE comments
+ADD COLUMN cleanliness INT CHECK (cleanliness BETWEEN 1 AND 5),
+ADD COLUMN space INT CHECK (space BETWEEN 1 AND 5),
+ADD COLUMN staff INT CHECK (staff BETWEEN 1 AND 5),
+ADD COLUMN accessibility INT CHECK (accessibility BETWEEN 1 AND 5),
+ADD COLUMN noise INT CHECK (noise BETWEEN 1 AND 5);
+
+ALTER TABLE rank_aggregations
+ADD COLUMN cleanliness_total BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN cleanliness_count BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN space_total BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN space_count BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN staff_total BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN staff_count BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN accessibility_total BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN accessibility_count BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN noise_total BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN noise_count BIGINT NOT NULL DEFAULT 0;
+
+-- 单个维度的增减, 评论没有给该维度打分 (NULL) 时不计入
+CREATE FUNCTION apply_comment_dimensions(loc INT, c INT, sp INT, st INT, a INT, n INT, delta INT)
+RETURNS VOID AS $$
+begin
+	UPDATE rank_aggregations SET
+		cleanliness_total = cleanliness_total + COALESCE(c, 0) * delta,
+		cleanliness_count = cleanliness_count + CASE WHEN c IS NULL THEN 0 ELSE delta END,
+		space_total = space_total + COALESCE(sp, 0) * delta,
+		space_count = space_count + CASE WHEN sp IS NULL THEN 0 ELSE delta END,
+		staff_total = staff_total + COALESCE(st, 0) * delta,
+		staff_count = staff_count + CASE WHEN st IS NULL THEN 0 ELSE delta END,
+		accessibility_total = accessibility_total + COALESCE(a, 0) * delta,
+		accessibility_count = accessibility_count + CASE WHEN a IS NULL THEN 0 ELSE delta END,
+		noise_total = noise_total + COALESCE(n, 0) * delta,
+		noise_count = noise_count + CASE WHEN n IS NULL THEN 0 ELSE delta END
+	WHERE location_id = loc;
+end
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION maintain_rank_aggregation()
+RETURNS TRIGGER AS $$
+begin
+	IF TG_OP IN ('UPDATE', 'DELETE') THEN
+		PERFORM apply_comment_rank(OLD.location, OLD.rank, -1);
+		PERFORM apply_comment_dimensions(OLD.location, OLD.cleanliness, OLD.space, OLD.staff, OLD.accessibility, OLD.noise, -1);
+	END IF;
+	IF TG_OP IN ('INSERT', 'UPDATE') THEN
+		PERFORM apply_comment_rank(NEW.location, NEW.rank, 1);
+		PERFORM apply_comment_dimensions(NEW.location, NEW.cleanliness, NEW.space, NEW.staff, NEW.accessibility, NEW.noise, 1);
+	END IF;
+	RETURN NULL;
+end
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS comments_rank_aggregation ON comments;
+CREATE TRIGGER comments_rank_aggregation
+	AFTER INSERT OR DELETE OR UPDATE OF rank, location, cleanliness, space, staff, accessibility, noise
+	ON comments
+	FOR EACH ROW
+	EXECUTE PROCEDURE maintain_rank_aggregation();
+
+-- 按评论重新计算聚合, loc 为 NULL 时重算全部地点, 返回更新的行数
+CREATE OR REPLACE FUNCTION recompute_rank_aggregations(loc INT)
+RETURNS BIGINT AS $$
+declare
+	affected BIGINT;
+begin
+	INSERT INTO rank_aggregations (total, count, location_id)
+	SELECT 0, 0, id FROM locations WHERE loc IS NULL OR id = loc
+	ON CONFLICT (location_id) DO NOTHING;
+	UPDATE rank_aggregations AS ra SET
+		total = s.total,
+		count = s.count,
+		rank_1 = s.rank_1,
+		rank_2 = s.rank_2,
+		rank_3 = s.rank_3,
+		rank_4 = s.rank_4,
+		rank_5 = s.rank_5,
+		score = bayesian_rank(s.total, s.count),
+		cleanliness_total = s.cleanliness_total,
+		cleanliness_count = s.cleanliness_count,
+		space_total = s.space_total,
+		space_count = s.space_count,
+		staff_total = s.staff_total,
+		staff_count = s.staff_count,
+		accessibility_total = s.accessibility_total,
+		accessibility_count = s.accessibility_count,
+		noise_total = s.noise_total,
+		noise_count = s.noise_count
+	FROM (
+		SELECT
+			l.id AS location_id,
+			COALESCE(SUM(c.rank), 0) AS total,
+			COUNT(c.id) AS count,
+			COUNT(c.id) FILTER (WHERE c.rank = 1) AS rank_1,
+			COUNT(c.id) FILTER (WHERE c.rank = 2) AS rank_2,
+			COUNT(c.id) FILTER (WHERE c.rank = 3) AS rank_3,
+			COUNT(c.id) FILTER (WHERE c.rank = 4) AS rank_4,
+			COUNT(c.id) FILTER (WHERE c.rank = 5) AS rank_5,
+			COALESCE(SUM(c.cleanliness), 0) AS cleanliness_total,
+			COUNT(c.cleanliness) AS cleanliness_count,
+			COALESCE(SUM(c.space), 0) AS space_total,
+			COUNT(c.space) AS space_count,
+			COALESCE(SUM(c.staff), 0) AS staff_total,
+			COUNT(c.staff) AS staff_count,
+			COALESCE(SUM(c.accessibility), 0) AS accessibility_total,
+			COUNT(c.accessibility) AS accessibility_count,
+			COALESCE(SUM(c.noise), 0) AS noise_total,
+			COUNT(c.noise) AS noise_count
+		FROM locations AS l LEFT JOIN comments AS c ON c.location = l.id
+		WHERE loc IS NULL OR l.id = loc
+		GROUP BY l.id
+	) AS s
+	WHERE ra.location_id = s.location_id;
+	GET DIAGNOSTICS affected = ROW_COUNT;
+	RETURN affected;
+end
+$$ LANGUAGE plpgsql;
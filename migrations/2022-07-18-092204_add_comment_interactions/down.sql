@@ -0,0 +1,7 @@
+DROP TRIGGER IF EXISTS comment_votes_helpful_count ON comment_votes;
+DROP FUNCTION IF EXISTS maintain_helpful_count;
+ALTER TABLE comments DROP COLUMN helpful_count;
+DROP TABLE IF EXISTS comment_votes;
+DROP TRIGGER IF EXISTS update_comment_replies_update_on ON comment_replies;
+DROP INDEX IF EXISTS comment_replies_comment;
+DROP TABLE IF EXISTS comment_replies;
@@ -0,0 +1,40 @@
+CREATE TABLE IF NOT EXISTS comment_replies (
+	id SERIAL NOT NULL,
+	comment INT NOT NULL REFERENCES comments (id) ON DELETE CASCADE,
+	"user" INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	parent INT REFERENCES comment_replies (id) ON DELETE CASCADE,
+	content TEXT NOT NULL,
+	is_official BOOLEAN NOT NULL DEFAULT FALSE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS comment_replies_comment ON comment_replies USING BTREE (comment);
+
+CREATE TRIGGER update_comment_replies_update_on BEFORE UPDATE ON comment_replies FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+CREATE TABLE IF NOT EXISTS comment_votes (
+	id SERIAL NOT NULL,
+	comment INT NOT NULL REFERENCES comments (id) ON DELETE CASCADE,
+	"user" INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_comment_vote_user UNIQUE (comment, "user")
+);
+
+ALTER TABLE comments ADD COLUMN helpful_count BIGINT NOT NULL DEFAULT 0;
+
+CREATE FUNCTION maintain_helpful_count()
+RETURNS TRIGGER AS $$
+begin
+	IF TG_OP = 'INSERT' THEN
+		UPDATE comments SET helpful_count = helpful_count + 1 WHERE id = NEW.comment;
+	ELSIF TG_OP = 'DELETE' THEN
+		UPDATE comments SET helpful_count = helpful_count - 1 WHERE id = OLD.comment;
+	END IF;
+	RETURN NULL;
+end
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER comment_votes_helpful_count AFTER INSERT OR DELETE ON comment_votes FOR EACH ROW EXECUTE PROCEDURE maintain_helpful_count();
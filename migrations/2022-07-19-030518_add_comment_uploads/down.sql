@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS comments_user;
+DROP TABLE IF EXISTS comment_upload_rels;
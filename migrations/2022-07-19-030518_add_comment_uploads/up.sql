@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS comment_upload_rels (
+	id SERIAL NOT NULL,
+	comment INT NOT NULL REFERENCES comments (id) ON DELETE CASCADE,
+	upload INT NOT NULL REFERENCES uploads (id) ON DELETE CASCADE,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_comment_upload UNIQUE (comment, upload)
+);
+
+CREATE INDEX IF NOT EXISTS comments_user ON comments USING BTREE ("user");
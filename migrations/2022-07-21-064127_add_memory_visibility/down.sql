@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS memories_owner;
+ALTER TABLE memories DROP COLUMN IF EXISTS visibility;
+DROP TABLE IF EXISTS family_members;
+DROP TABLE IF EXISTS families;
@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS families (
+	id SERIAL NOT NULL,
+	name VARCHAR NOT NULL,
+	owner INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE TRIGGER update_families_update_on BEFORE UPDATE ON families FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+-- 一个用户只能属于一个家庭
+CREATE TABLE IF NOT EXISTS family_members (
+	id SERIAL NOT NULL,
+	family INT NOT NULL REFERENCES families (id) ON DELETE CASCADE,
+	"user" INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_family_member_user UNIQUE ("user")
+);
+
+CREATE INDEX IF NOT EXISTS family_members_family ON family_members USING BTREE (family);
+
+CREATE TRIGGER update_family_members_update_on BEFORE UPDATE ON family_members FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+-- 已有的回忆之前对所有人可见, 保持公开; 新建的回忆默认私有
+ALTER TABLE memories ADD COLUMN visibility VARCHAR NOT NULL DEFAULT 'public' CHECK (visibility IN ('private', 'family', 'public'));
+ALTER TABLE memories ALTER COLUMN visibility SET DEFAULT 'private';
+
+CREATE INDEX IF NOT EXISTS memories_owner ON memories USING BTREE (owner);
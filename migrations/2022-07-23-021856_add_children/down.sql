@@ -0,0 +1,5 @@
+ALTER TABLE locations DROP COLUMN IF EXISTS max_age_months;
+ALTER TABLE locations DROP COLUMN IF EXISTS min_age_months;
+DROP TABLE IF EXISTS memory_child_rels;
+DROP TABLE IF EXISTS children;
+DROP TABLE IF EXISTS family_invitations;
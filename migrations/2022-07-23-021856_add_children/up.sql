@@ -0,0 +1,42 @@
+CREATE TABLE IF NOT EXISTS family_invitations (
+	id SERIAL NOT NULL,
+	family INT NOT NULL REFERENCES families (id) ON DELETE CASCADE,
+	inviter INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	invitee INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	status VARCHAR NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'declined')),
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS family_invitations_invitee ON family_invitations USING BTREE (invitee);
+
+CREATE TRIGGER update_family_invitations_update_on BEFORE UPDATE ON family_invitations FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+CREATE TABLE IF NOT EXISTS children (
+	id SERIAL NOT NULL,
+	family INT NOT NULL REFERENCES families (id) ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	birth_date DATE NOT NULL,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS children_family ON children USING BTREE (family);
+
+CREATE TRIGGER update_children_update_on BEFORE UPDATE ON children FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+CREATE TABLE IF NOT EXISTS memory_child_rels (
+	id SERIAL NOT NULL,
+	memory INT NOT NULL REFERENCES memories (id) ON DELETE CASCADE,
+	child INT NOT NULL REFERENCES children (id) ON DELETE CASCADE,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_memory_child UNIQUE (memory, child)
+);
+
+CREATE INDEX IF NOT EXISTS memory_child_rels_child ON memory_child_rels USING BTREE (child);
+
+-- 适合的月龄范围, 为空表示不限
+ALTER TABLE locations ADD COLUMN min_age_months INT CHECK (min_age_months >= 0);
+ALTER TABLE locations ADD COLUMN max_age_months INT CHECK (max_age_months >= 0);
@@ -0,0 +1,22 @@
+-- 动态只追加, 不允许修改
+CREATE TABLE IF NOT EXISTS activities (
+	id SERIAL NOT NULL,
+	actor INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	kind VARCHAR NOT NULL CHECK (kind IN ('location', 'memory', 'comment', 'photo')),
+	location INT NOT NULL REFERENCES locations (id) ON DELETE CASCADE,
+	target INT NOT NULL,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS activities_actor ON activities USING BTREE (actor, id);
+CREATE INDEX IF NOT EXISTS activities_location ON activities USING BTREE (location);
+
+CREATE FUNCTION reject_activity_update()
+RETURNS TRIGGER AS $$
+begin
+	RAISE EXCEPTION 'activities are append-only';
+end;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER reject_activities_update BEFORE UPDATE ON activities FOR EACH ROW EXECUTE PROCEDURE reject_activity_update();
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS follows (
+	id SERIAL NOT NULL,
+	follower INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	followee INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	CONSTRAINT uni_follower_followee UNIQUE (follower, followee),
+	CONSTRAINT chk_follow_self CHECK (follower <> followee)
+);
+
+CREATE INDEX IF NOT EXISTS follows_followee ON follows USING BTREE (followee);
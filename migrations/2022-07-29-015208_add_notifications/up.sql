@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS notifications (
+	id SERIAL NOT NULL,
+	recipient INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	actor INT REFERENCES users (id) ON DELETE CASCADE,
+	kind VARCHAR NOT NULL CHECK (kind IN ('comment', 'reply', 'memory')),
+	location INT REFERENCES locations (id) ON DELETE CASCADE,
+	target INT,
+	content TEXT NOT NULL DEFAULT '',
+	is_read BOOLEAN NOT NULL DEFAULT FALSE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS notifications_recipient ON notifications USING BTREE (recipient, is_read);
+
+CREATE TRIGGER update_notifications_update_on BEFORE UPDATE ON notifications FOR EACH ROW EXECUTE PROCEDURE update_update_on();
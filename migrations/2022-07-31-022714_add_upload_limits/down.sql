@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS uploads_owner;
+
+ALTER TABLE uploads
+	DROP COLUMN IF EXISTS mime,
+	DROP COLUMN IF EXISTS size;
@@ -0,0 +1,5 @@
+ALTER TABLE uploads
+	ADD COLUMN IF NOT EXISTS mime VARCHAR NOT NULL DEFAULT '',
+	ADD COLUMN IF NOT EXISTS size BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS uploads_owner ON uploads USING BTREE (owner);
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS upload_renditions;
+
+DROP INDEX IF EXISTS uploads_pending;
+
+ALTER TABLE uploads DROP COLUMN IF EXISTS status;
@@ -0,0 +1,21 @@
+-- 已有的上传同样需要去除元数据, 全部交给后台重新处理
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS status VARCHAR NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'ready', 'failed'));
+
+CREATE INDEX IF NOT EXISTS uploads_pending ON uploads USING BTREE (id) WHERE status = 'pending';
+
+CREATE TABLE IF NOT EXISTS upload_renditions (
+	id SERIAL NOT NULL,
+	upload INT NOT NULL REFERENCES uploads (id) ON DELETE CASCADE,
+	variant VARCHAR NOT NULL CHECK (variant IN ('thumbnail', 'medium', 'original')),
+	fetch_code VARCHAR NOT NULL,
+	mime VARCHAR NOT NULL,
+	width INT NOT NULL,
+	height INT NOT NULL,
+	size BIGINT NOT NULL,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	UNIQUE (upload, variant)
+);
+
+CREATE TRIGGER update_upload_renditions_update_on BEFORE UPDATE ON upload_renditions FOR EACH ROW EXECUTE PROCEDURE update_update_on();
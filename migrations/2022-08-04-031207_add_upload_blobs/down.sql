@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS uploads_hash;
+
+ALTER TABLE uploads DROP COLUMN IF EXISTS hash;
+
+DROP TABLE IF EXISTS blobs;
@@ -0,0 +1,18 @@
+-- 内容相同的上传共享同一个存储对象, refs 为引用它的上传数量
+CREATE TABLE IF NOT EXISTS blobs (
+	id SERIAL NOT NULL,
+	hash VARCHAR NOT NULL UNIQUE,
+	storage_key VARCHAR NOT NULL,
+	size BIGINT NOT NULL,
+	refs INT NOT NULL DEFAULT 0 CHECK (refs >= 0),
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE TRIGGER update_blobs_update_on BEFORE UPDATE ON blobs FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+-- 已有的上传没有记录内容摘要, 各自独占存储对象
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS hash VARCHAR REFERENCES blobs (hash);
+
+CREATE INDEX IF NOT EXISTS uploads_hash ON uploads USING BTREE (hash);
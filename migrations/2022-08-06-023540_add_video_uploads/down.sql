@@ -0,0 +1,5 @@
+ALTER TABLE uploads
+	DROP COLUMN IF EXISTS media_type,
+	DROP COLUMN IF EXISTS width,
+	DROP COLUMN IF EXISTS height,
+	DROP COLUMN IF EXISTS duration;
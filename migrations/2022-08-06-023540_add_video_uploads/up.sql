@@ -0,0 +1,6 @@
+-- 视频的尺寸和时长 (秒) 由后台处理时读取, 图片只有尺寸
+ALTER TABLE uploads
+	ADD COLUMN IF NOT EXISTS media_type VARCHAR NOT NULL DEFAULT 'image' CHECK (media_type IN ('image', 'video')),
+	ADD COLUMN IF NOT EXISTS width INT,
+	ADD COLUMN IF NOT EXISTS height INT,
+	ADD COLUMN IF NOT EXISTS duration DOUBLE PRECISION;
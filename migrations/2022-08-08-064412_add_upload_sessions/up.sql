@@ -0,0 +1,28 @@
+-- 可续传的上传, 每次追加的数据单独存储, 全部收到之后按顺序合并为一个上传
+CREATE TABLE IF NOT EXISTS upload_sessions (
+	id SERIAL NOT NULL,
+	owner INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	size BIGINT NOT NULL CHECK (size > 0),
+	received BIGINT NOT NULL DEFAULT 0 CHECK (received <= size),
+	-- 完成之后的会话随上传一起删除, 不能重新变为进行中
+	upload INT REFERENCES uploads (id) ON DELETE CASCADE,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id)
+);
+
+CREATE TRIGGER update_upload_sessions_update_on BEFORE UPDATE ON upload_sessions FOR EACH ROW EXECUTE PROCEDURE update_update_on();
+
+CREATE TABLE IF NOT EXISTS upload_session_parts (
+	id SERIAL NOT NULL,
+	session INT NOT NULL REFERENCES upload_sessions (id) ON DELETE CASCADE,
+	position BIGINT NOT NULL,
+	size BIGINT NOT NULL,
+	fetch_code VARCHAR NOT NULL,
+	create_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	update_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (id),
+	UNIQUE (session, position)
+);
+
+CREATE TRIGGER update_upload_session_parts_update_on BEFORE UPDATE ON upload_session_parts FOR EACH ROW EXECUTE PROCEDURE update_update_on();
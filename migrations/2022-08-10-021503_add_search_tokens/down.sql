@@ -0,0 +1,10 @@
+DROP INDEX IF EXISTS comments_search;
+DROP INDEX IF EXISTS memories_search;
+DROP INDEX IF EXISTS locations_search;
+CREATE INDEX IF NOT EXISTS locations_search ON locations USING GIN (to_tsvector('simple', (name || ' ' || description)));
+CREATE INDEX IF NOT EXISTS memories_search ON memories USING GIN (to_tsvector('simple', (title || ' ' || content)));
+CREATE INDEX IF NOT EXISTS comments_search ON comments USING GIN (to_tsvector('simple', content));
+CREATE INDEX IF NOT EXISTS locations_search_trgm ON locations USING GIN ((name || ' ' || description) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS memories_search_trgm ON memories USING GIN ((title || ' ' || content) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS comments_search_trgm ON comments USING GIN (content gin_trgm_ops);
+DROP FUNCTION IF EXISTS search_tokens(TEXT, BOOLEAN);
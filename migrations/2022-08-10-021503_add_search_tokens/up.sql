@@ -0,0 +1,36 @@
+-- 'simple' 配置只按空格和标点分词, 中日韩文字整段会成为一个词, 无法匹配其中的一两个字;
+-- 把这些文字切分成单字和相邻两字 (bigram), 其余文字保持原样交给 'simple' 分词
+-- 查询时连续两个以上的字只使用 bigram, 单独的一个字使用单字, 所有词都需要命中
+CREATE FUNCTION search_tokens(doc TEXT, query BOOLEAN)
+RETURNS TEXT AS $$
+DECLARE
+	run TEXT;
+	tokens TEXT[];
+	i INT;
+begin
+	tokens := ARRAY[regexp_replace(doc, '[぀-ヿ㐀-鿿가-힯豈-﫿]+', ' ', 'g')];
+	FOR run IN SELECT m[1] FROM regexp_matches(doc, '([぀-ヿ㐀-鿿가-힯豈-﫿]+)', 'g') AS m LOOP
+		IF NOT query OR char_length(run) = 1 THEN
+			FOR i IN 1..char_length(run) LOOP
+				tokens := tokens || substr(run, i, 1);
+			END LOOP;
+		END IF;
+		FOR i IN 1..char_length(run) - 1 LOOP
+			tokens := tokens || substr(run, i, 2);
+		END LOOP;
+	END LOOP;
+	RETURN array_to_string(tokens, ' ');
+end
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+-- 使用表达式索引而不是单独的 tsvector 列: diesel 1.4 没有 tsvector 类型, 列会导致 print_schema 生成的 schema.rs 无法编译
+DROP INDEX IF EXISTS locations_search;
+DROP INDEX IF EXISTS memories_search;
+DROP INDEX IF EXISTS comments_search;
+-- 中文依靠 bigram 匹配之后不再需要整段文本的 trigram 索引
+DROP INDEX IF EXISTS locations_search_trgm;
+DROP INDEX IF EXISTS memories_search_trgm;
+DROP INDEX IF EXISTS comments_search_trgm;
+CREATE INDEX locations_search ON locations USING GIN (to_tsvector('simple', search_tokens(name || ' ' || description, FALSE)));
+CREATE INDEX memories_search ON memories USING GIN (to_tsvector('simple', search_tokens(title || ' ' || content, FALSE)));
+CREATE INDEX comments_search ON comments USING GIN (to_tsvector('simple', search_tokens(content, FALSE)));
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS uni_family_invitations_pending;
@@ -0,0 +1,5 @@
+-- 同一家庭对同一用户重复的待处理邀请只保留最早的一个
+DELETE FROM family_invitations a USING family_invitations b
+WHERE a.status = 'pending' AND b.status = 'pending' AND a.family = b.family AND a.invitee = b.invitee AND a.id > b.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS uni_family_invitations_pending ON family_invitations (family, invitee) WHERE status = 'pending';
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS activities_target;
+DROP TRIGGER IF EXISTS delete_photo_activities ON uploads;
+DROP TRIGGER IF EXISTS delete_comment_activities ON comments;
+DROP TRIGGER IF EXISTS delete_memory_activities ON memories;
+DROP FUNCTION IF EXISTS delete_target_activities();
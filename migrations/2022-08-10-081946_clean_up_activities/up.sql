@@ -0,0 +1,19 @@
+-- 动态的 target 没有外键, 删除回忆, 评论或照片时通过触发器删除对应的动态
+CREATE FUNCTION delete_target_activities()
+RETURNS TRIGGER AS $$
+begin
+	DELETE FROM activities WHERE kind = TG_ARGV[0] AND target = OLD.id;
+	RETURN OLD;
+end;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER delete_memory_activities AFTER DELETE ON memories FOR EACH ROW EXECUTE PROCEDURE delete_target_activities('memory');
+CREATE TRIGGER delete_comment_activities AFTER DELETE ON comments FOR EACH ROW EXECUTE PROCEDURE delete_target_activities('comment');
+CREATE TRIGGER delete_photo_activities AFTER DELETE ON uploads FOR EACH ROW EXECUTE PROCEDURE delete_target_activities('photo');
+
+-- 照片动态的 target 由地点改为照片, 之前的照片动态无法对应到照片, 直接删除
+DELETE FROM activities WHERE kind = 'photo';
+DELETE FROM activities WHERE kind = 'memory' AND target NOT IN (SELECT id FROM memories);
+DELETE FROM activities WHERE kind = 'comment' AND target NOT IN (SELECT id FROM comments);
+
+CREATE INDEX IF NOT EXISTS activities_target ON activities USING BTREE (kind, target);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS uploads_fetch_code;
+DROP INDEX IF EXISTS upload_renditions_fetch_code;
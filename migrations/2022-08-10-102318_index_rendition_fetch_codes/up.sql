@@ -0,0 +1,3 @@
+-- 内容相同的上传共享各个尺寸, 回收时按 fetch code 检查是否仍被引用
+CREATE INDEX IF NOT EXISTS upload_renditions_fetch_code ON upload_renditions USING BTREE (fetch_code);
+CREATE INDEX IF NOT EXISTS uploads_fetch_code ON uploads USING BTREE (fetch_code);
@@ -0,0 +1,2 @@
+-- 分类建议按每个分类的地点数量排序
+CREATE INDEX IF NOT EXISTS locations_category ON locations USING BTREE (category);
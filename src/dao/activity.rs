@@ -0,0 +1,85 @@
+use super::follow;
+use crate::models::{Activity, ActivityInsert, ActivityKind, Location, User};
+use crate::schema::*;
+use anyhow::{Context, Error};
+use diesel::{dsl::sql, insert_into, pg::Pg, sql_types::Bool, Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+#[derive(Debug, Default)]
+pub struct Query {
+    // (纬度, 经度, 半径), 只返回范围内地点上的动态
+    pub area: Option<(f64, f64, f64)>,
+    pub actor: Option<i32>,
+    // 只返回该用户关注的人的动态
+    pub followed_by: Option<i32>,
+    // 游标, 只返回 id 小于该值的动态
+    pub before: Option<i32>,
+    pub limit: i64,
+}
+
+pub fn insert<T>(conn: &T, ins: ActivityInsert) -> Result<i32, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(activities::table)
+        .values(ins)
+        .returning(activities::id)
+        .get_result(conn)
+        .context("failed to insert activity")
+}
+
+// 照片动态的 target 为新增的第一张照片, 没有新增照片时不产生动态
+pub fn insert_photo<T>(conn: &T, actor: i32, location: i32, before: &Vec<i32>, after: &Vec<i32>) -> Result<Option<i32>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    match after.iter().find(|img| !before.contains(img)) {
+        Some(&upload) => insert(
+            conn,
+            ActivityInsert {
+                actor: actor,
+                kind: ActivityKind::Photo,
+                location: location,
+                target: upload,
+            },
+        )
+        .map(Some),
+        None => Ok(None),
+    }
+}
+
+// 回忆之后可能被改为非公开, 照片之后可能被移除, 所以在读取时再过滤一次
+pub fn query<T>(conn: &T, query: Query) -> Result<Vec<(Activity, Location, User)>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let mut q = activities::table
+        .inner_join(locations::table)
+        .inner_join(users::table)
+        .select((activities::all_columns, locations::all_columns, users::all_columns))
+        .filter(sql::<Bool>(
+            "(activities.kind <> 'memory' OR activities.target IN (SELECT id FROM memories WHERE visibility = 'public'))",
+        ))
+        .filter(sql::<Bool>(
+            "(activities.kind <> 'photo' OR activities.target IN (SELECT upload_id FROM location_upload_rels WHERE location_upload_rels.location_id = activities.location)
+            OR activities.target IN (SELECT memory_upload_rels.upload FROM memory_upload_rels JOIN memories ON memories.id = memory_upload_rels.memory WHERE memories.visibility = 'public'))",
+        ))
+        .order_by(activities::id.desc())
+        .limit(query.limit)
+        .into_boxed();
+    if let Some((latitude, longitude, radius)) = query.area {
+        q = q.filter(sql::<Bool>(&format!(
+            "earth_box(ll_to_earth({}, {}), {}) @> ll_to_earth(locations.latitude, locations.longitude)",
+            latitude, longitude, radius
+        )));
+    }
+    if let Some(actor) = query.actor {
+        q = q.filter(activities::actor.eq(actor));
+    }
+    if let Some(user) = query.followed_by {
+        q = q.filter(follow::followed_by("activities.actor", user));
+    }
+    if let Some(before) = query.before {
+        q = q.filter(activities::id.lt(before));
+    }
+    q.load(conn).context("failed to query activities")
+}
@@ -0,0 +1,22 @@
+use crate::schema::*;
+use anyhow::{Context, Error};
+use diesel::{dsl::sql_query, insert_into, pg::Pg, Connection, ExpressionMethods, RunQueryDsl};
+
+// 编号由客户端的地点分类决定, 已经存在时更新名称
+pub fn save<T>(conn: &T, id: i32, name: &str) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let affected = insert_into(categories::table)
+        .values((categories::id.eq(id), categories::name.eq(name)))
+        .on_conflict(categories::id)
+        .do_update()
+        .set(categories::name.eq(name))
+        .execute(conn)
+        .context("failed to save category")?;
+    // 指定了编号插入时序列不会前进
+    sql_query("SELECT setval('categories_id_seq', (SELECT MAX(id) FROM categories))")
+        .execute(conn)
+        .context("failed to save category")?;
+    Ok(affected)
+}
@@ -1,6 +1,8 @@
-use crate::models::{Comment, CommentInsert, CommentUpdate};
-use crate::schema::comments;
-use diesel::{insert_into, pg::Pg, result::Error, select, BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use super::search;
+use crate::models::{Comment, CommentInsert, CommentReply, CommentReplyInsert, CommentUpdate, CommentUploadRel, Upload};
+use crate::schema::{comment_replies, comment_upload_rels, comment_votes, comments, uploads};
+use diesel::{delete, insert_into, pg::Pg, result::Error, BelongingToDsl, BoolExpressionMethods, Connection, ExpressionMethods, GroupedBy, OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
 use std::default::Default;
 
 #[derive(Debug, Default)]
@@ -8,8 +10,26 @@ pub struct Query {
     pub rank_gt: Option<i32>,
     pub rank_lt: Option<i32>,
     pub location: Option<i32>,
+    pub user: Option<i32>,
+    pub keyword: Option<String>,
     pub limit: i64,
     pub offset: i64,
+    pub order_by: OrderBy,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBy {
+    Recent,
+    Helpful,
+    RankDesc,
+    RankAsc,
+}
+
+impl Default for OrderBy {
+    fn default() -> Self {
+        Self::Recent
+    }
 }
 
 pub fn query<T>(conn: &T, query: Query) -> Result<(Vec<Comment>, i64), Error>
@@ -30,11 +50,32 @@ where
         q = q.filter(comments::location.eq(location));
         c = c.filter(comments::location.eq(location));
     }
+    if let Some(user) = query.user {
+        q = q.filter(comments::user.eq(user));
+        c = c.filter(comments::user.eq(user));
+    }
+    if let Some(keyword) = &query.keyword {
+        q = q.filter(search::matches(search::COMMENT_DOCUMENT, keyword));
+        c = c.filter(search::matches(search::COMMENT_DOCUMENT, keyword));
+    }
+    match query.order_by {
+        OrderBy::Recent => q = q.order((comments::update_on.desc(), comments::id.desc())),
+        OrderBy::Helpful => q = q.order((comments::helpful_count.desc(), comments::update_on.desc())),
+        OrderBy::RankDesc => q = q.order((comments::rank.desc(), comments::update_on.desc())),
+        OrderBy::RankAsc => q = q.order((comments::rank, comments::update_on.desc())),
+    }
     let total = c.count().get_result(conn)?;
-    let list = q.order(comments::update_on.desc()).load(conn)?;
+    let list = q.load(conn)?;
     Ok((list, total))
 }
 
+pub fn get<T>(conn: &T, id: i32) -> Result<Comment, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    comments::table.find(id).get_result(conn)
+}
+
 pub fn insert<T>(conn: &T, ins: CommentInsert) -> Result<i32, Error>
 where
     T: Connection<Backend = Pg>,
@@ -62,3 +103,85 @@ where
 {
     comments::table.filter(comments::user.eq(user).and(comments::location.eq(loc))).for_update().get_result(conn).optional()
 }
+
+pub fn clear_images<T>(conn: &T, id: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(comment_upload_rels::table).filter(comment_upload_rels::comment.eq(id)).execute(conn)
+}
+
+pub fn add_images<T>(conn: &T, id: i32, images: Vec<i32>) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(comment_upload_rels::table)
+        .values(
+            images
+                .into_iter()
+                .map(|img| (comment_upload_rels::comment.eq(id), comment_upload_rels::upload.eq(img)))
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)
+}
+
+pub fn query_images<T>(conn: &T, comments: &Vec<Comment>) -> Result<Vec<Vec<Upload>>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let upload_set: Vec<Vec<(CommentUploadRel, Upload)>> = CommentUploadRel::belonging_to(comments).inner_join(uploads::table).load(conn)?.grouped_by(comments);
+    Ok(upload_set.into_iter().map(|upls| upls.into_iter().map(|(_, u)| u).collect()).collect())
+}
+
+pub fn insert_reply<T>(conn: &T, ins: CommentReplyInsert) -> Result<i32, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(comment_replies::table).values(ins).returning(comment_replies::id).get_result(conn)
+}
+
+pub fn get_reply<T>(conn: &T, id: i32) -> Result<CommentReply, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    comment_replies::table.find(id).get_result(conn)
+}
+
+pub fn delete_reply<T>(conn: &T, id: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(comment_replies::table.filter(comment_replies::id.eq(id))).execute(conn)
+}
+
+// 回复按时间正序返回, 客户端根据 parent 组装成树
+pub fn replies_of_comment<T>(conn: &T, comment: i32, limit: i64, offset: i64) -> Result<(Vec<CommentReply>, i64), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let total = comment_replies::table.filter(comment_replies::comment.eq(comment)).count().get_result(conn)?;
+    let list = comment_replies::table
+        .filter(comment_replies::comment.eq(comment))
+        .order((comment_replies::create_on, comment_replies::id))
+        .limit(limit)
+        .offset(offset)
+        .load(conn)?;
+    Ok((list, total))
+}
+
+pub fn vote<T>(conn: &T, comment: i32, user: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(comment_votes::table)
+        .values((comment_votes::comment.eq(comment), comment_votes::user.eq(user)))
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+pub fn unvote<T>(conn: &T, comment: i32, user: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(comment_votes::table.filter(comment_votes::comment.eq(comment).and(comment_votes::user.eq(user)))).execute(conn)
+}
@@ -0,0 +1,163 @@
+use crate::models::{Child, ChildCommand, Family, FamilyInvitation, User};
+use crate::schema::*;
+use anyhow::{Context, Error};
+use diesel::{delete, insert_into, pg::Pg, Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+
+pub fn family_of_user<T>(conn: &T, user: i32) -> Result<Option<Family>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    families::table
+        .inner_join(family_members::table)
+        .select(families::all_columns)
+        .filter(family_members::user.eq(user))
+        .get_result(conn)
+        .optional()
+        .context("failed to get family of user")
+}
+
+// 创建者同时成为家庭成员
+pub fn insert<T>(conn: &T, name: String, owner: i32) -> Result<i32, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let id = insert_into(families::table)
+        .values((families::name.eq(name), families::owner.eq(owner)))
+        .returning(families::id)
+        .get_result(conn)
+        .context("failed to insert family")?;
+    add_member(conn, id, owner)?;
+    Ok(id)
+}
+
+pub fn delete_family<T>(conn: &T, id: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(families::table.filter(families::id.eq(id))).execute(conn).context("failed to delete family")
+}
+
+pub fn members<T>(conn: &T, family: i32) -> Result<Vec<User>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    users::table
+        .inner_join(family_members::table)
+        .select(users::all_columns)
+        .filter(family_members::family.eq(family))
+        .order_by(family_members::create_on)
+        .load(conn)
+        .context("failed to query family members")
+}
+
+pub fn add_member<T>(conn: &T, family: i32, user: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(family_members::table)
+        .values((family_members::family.eq(family), family_members::user.eq(user)))
+        .execute(conn)
+        .context("failed to add family member")
+}
+
+pub fn remove_member<T>(conn: &T, family: i32, user: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(family_members::table.filter(family_members::family.eq(family)).filter(family_members::user.eq(user)))
+        .execute(conn)
+        .context("failed to remove family member")
+}
+
+// 同一家庭对同一用户只保留一个待处理的邀请, 重复邀请不会插入
+pub fn invite<T>(conn: &T, family: i32, inviter: i32, invitee: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(family_invitations::table)
+        .values((family_invitations::family.eq(family), family_invitations::inviter.eq(inviter), family_invitations::invitee.eq(invitee)))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context("failed to invite family member")
+}
+
+pub fn pending_invitations<T>(conn: &T, invitee: i32) -> Result<Vec<(FamilyInvitation, Family)>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    family_invitations::table
+        .inner_join(families::table)
+        .filter(family_invitations::invitee.eq(invitee))
+        .filter(family_invitations::status.eq("pending"))
+        .order_by(family_invitations::create_on.desc())
+        .load(conn)
+        .context("failed to query pending invitations")
+}
+
+pub fn get_invitation<T>(conn: &T, id: i32) -> Result<FamilyInvitation, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    family_invitations::table.find(id).get_result(conn).context("failed to get family invitation")
+}
+
+pub fn set_invitation_status<T>(conn: &T, id: i32, status: &str) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    diesel::update(family_invitations::table.filter(family_invitations::id.eq(id)))
+        .set(family_invitations::status.eq(status))
+        .execute(conn)
+        .context("failed to update family invitation")
+}
+
+pub fn children_of_family<T>(conn: &T, family: i32) -> Result<Vec<Child>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    children::table
+        .filter(children::family.eq(family))
+        .order_by(children::birth_date)
+        .load(conn)
+        .context("failed to query children of family")
+}
+
+pub fn count_children_in_family<T>(conn: &T, family: i32, children: &Vec<i32>) -> Result<i64, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    children::table
+        .filter(children::family.eq(family))
+        .filter(children::id.eq_any(children))
+        .count()
+        .get_result(conn)
+        .context("failed to count children in family")
+}
+
+pub fn get_child<T>(conn: &T, id: i32) -> Result<Child, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    children::table.find(id).get_result(conn).context("failed to get child")
+}
+
+pub fn insert_child<T>(conn: &T, ins: ChildCommand) -> Result<i32, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(children::table).values(ins).returning(children::id).get_result(conn).context("failed to insert child")
+}
+
+pub fn update_child<T>(conn: &T, id: i32, upd: ChildCommand) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    diesel::update(children::table.filter(children::id.eq(id))).set(upd).execute(conn).context("failed to update child")
+}
+
+pub fn delete_child<T>(conn: &T, id: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(children::table.filter(children::id.eq(id))).execute(conn).context("failed to delete child")
+}
@@ -0,0 +1,161 @@
+use crate::models::{FavoriteList, FavoriteListCommand, Location};
+use crate::schema::*;
+use anyhow::{Context, Error};
+use diesel::{delete, dsl::sql, insert_into, pg::Pg, sql_types::Double, BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+#[derive(Debug, Default)]
+pub struct Query {
+    pub user: Option<i32>,
+    pub list: Option<i32>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+pub fn locations<T>(conn: &T, query: Query) -> Result<((Vec<Location>, Vec<f64>), i64), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    if query.user.is_none() && query.list.is_none() {
+        return Err(Error::msg("invalid query").context("failed to find favorite locations"));
+    }
+    let mut q = locations::table
+        .select((
+            locations::all_columns,
+            sql::<Double>(&format!(
+                "earth_distance(ll_to_earth({}, {}), ll_to_earth(locations.latitude, locations.longitude)) as distance",
+                query.latitude, query.longitude
+            )),
+        ))
+        .order_by(sql::<Double>("distance"))
+        .limit(query.limit)
+        .offset(query.offset)
+        .into_boxed();
+    let mut c = locations::table.into_boxed();
+    if let Some(user) = query.user {
+        q = q.filter(locations::id.eq_any(favorites::table.filter(favorites::user.eq(user)).select(favorites::location)));
+        c = c.filter(locations::id.eq_any(favorites::table.filter(favorites::user.eq(user)).select(favorites::location)));
+    }
+    if let Some(list) = query.list {
+        q = q.filter(
+            locations::id.eq_any(
+                favorite_list_location_rels::table
+                    .filter(favorite_list_location_rels::list.eq(list))
+                    .select(favorite_list_location_rels::location),
+            ),
+        );
+        c = c.filter(
+            locations::id.eq_any(
+                favorite_list_location_rels::table
+                    .filter(favorite_list_location_rels::list.eq(list))
+                    .select(favorite_list_location_rels::location),
+            ),
+        );
+    }
+    let total = c.count().get_result(conn).context("failed to find favorite locations")?;
+    let rows: Vec<(Location, f64)> = q.load(conn).context("failed to find favorite locations")?;
+    let (locs, dists) = rows.into_iter().unzip();
+    Ok(((locs, dists), total))
+}
+
+pub fn add<T>(conn: &T, user: i32, location: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(favorites::table)
+        .values((favorites::user.eq(user), favorites::location.eq(location)))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context("failed to add favorite")
+}
+
+pub fn remove<T>(conn: &T, user: i32, location: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(favorite_list_location_rels::table)
+        .filter(favorite_list_location_rels::location.eq(location))
+        .filter(favorite_list_location_rels::list.eq_any(favorite_lists::table.filter(favorite_lists::owner.eq(user)).select(favorite_lists::id)))
+        .execute(conn)
+        .context("failed to remove favorite")?;
+    delete(favorites::table.filter(favorites::user.eq(user).and(favorites::location.eq(location))))
+        .execute(conn)
+        .context("failed to remove favorite")
+}
+
+pub fn lists_of_user<T>(conn: &T, user: i32) -> Result<Vec<FavoriteList>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    favorite_lists::table
+        .filter(favorite_lists::owner.eq(user))
+        .order_by(favorite_lists::create_on)
+        .load(conn)
+        .context("failed to query favorite lists")
+}
+
+pub fn get_list<T>(conn: &T, id: i32) -> Result<FavoriteList, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    favorite_lists::table.find(id).get_result(conn).context("failed to get favorite list")
+}
+
+pub fn get_list_by_share_code<T>(conn: &T, share_code: &str) -> Result<FavoriteList, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    favorite_lists::table
+        .filter(favorite_lists::share_code.eq(share_code))
+        .get_result(conn)
+        .context("failed to get shared favorite list")
+}
+
+pub fn insert_list<T>(conn: &T, ins: FavoriteListCommand) -> Result<i32, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(favorite_lists::table)
+        .values(ins)
+        .returning(favorite_lists::id)
+        .get_result(conn)
+        .context("failed to insert favorite list")
+}
+
+pub fn update_list<T>(conn: &T, id: i32, upd: FavoriteListCommand) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    diesel::update(favorite_lists::table.filter(favorite_lists::id.eq(id)))
+        .set(upd)
+        .execute(conn)
+        .context("failed to update favorite list")
+}
+
+pub fn delete_list<T>(conn: &T, id: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(favorite_lists::table.filter(favorite_lists::id.eq(id))).execute(conn).context("failed to delete favorite list")
+}
+
+pub fn add_to_list<T>(conn: &T, list: i32, location: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(favorite_list_location_rels::table)
+        .values((favorite_list_location_rels::list.eq(list), favorite_list_location_rels::location.eq(location)))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context("failed to add location to favorite list")
+}
+
+pub fn remove_from_list<T>(conn: &T, list: i32, location: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(favorite_list_location_rels::table.filter(favorite_list_location_rels::list.eq(list).and(favorite_list_location_rels::location.eq(location))))
+        .execute(conn)
+        .context("failed to remove location from favorite list")
+}
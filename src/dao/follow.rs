@@ -0,0 +1,93 @@
+use crate::models::User;
+use crate::schema::*;
+use anyhow::{Context, Error};
+use diesel::{
+    delete,
+    dsl::sql,
+    insert_into,
+    pg::Pg,
+    sql_types::{Bool, Integer},
+    BoolExpressionMethods, BoxableExpression, Connection, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl,
+};
+
+pub fn follow<T>(conn: &T, follower: i32, followee: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(follows::table)
+        .values((follows::follower.eq(follower), follows::followee.eq(followee)))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context("failed to follow user")
+}
+
+pub fn unfollow<T>(conn: &T, follower: i32, followee: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    delete(follows::table.filter(follows::follower.eq(follower).and(follows::followee.eq(followee))))
+        .execute(conn)
+        .context("failed to unfollow user")
+}
+
+pub fn is_following<T>(conn: &T, follower: i32, followee: i32) -> Result<bool, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let count: i64 = follows::table
+        .filter(follows::follower.eq(follower).and(follows::followee.eq(followee)))
+        .count()
+        .get_result(conn)
+        .context("failed to check following")?;
+    Ok(count > 0)
+}
+
+// 按关注时间倒序
+pub fn followers<T>(conn: &T, user: i32, limit: i64, offset: i64) -> Result<(Vec<User>, i64), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let total = follows::table.filter(follows::followee.eq(user)).count().get_result(conn).context("failed to query followers")?;
+    let list = users::table
+        .inner_join(follows::table.on(follows::follower.eq(users::id)))
+        .select(users::all_columns)
+        .filter(follows::followee.eq(user))
+        .order_by(follows::create_on.desc())
+        .limit(limit)
+        .offset(offset)
+        .load(conn)
+        .context("failed to query followers")?;
+    Ok((list, total))
+}
+
+pub fn following<T>(conn: &T, user: i32, limit: i64, offset: i64) -> Result<(Vec<User>, i64), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let total = follows::table.filter(follows::follower.eq(user)).count().get_result(conn).context("failed to query following")?;
+    let list = users::table
+        .inner_join(follows::table.on(follows::followee.eq(users::id)))
+        .select(users::all_columns)
+        .filter(follows::follower.eq(user))
+        .order_by(follows::create_on.desc())
+        .limit(limit)
+        .offset(offset)
+        .load(conn)
+        .context("failed to query following")?;
+    Ok((list, total))
+}
+
+// (粉丝数, 关注数)
+pub fn counts<T>(conn: &T, user: i32) -> Result<(i64, i64), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let followers = follows::table.filter(follows::followee.eq(user)).count().get_result(conn).context("failed to count followers")?;
+    let following = follows::table.filter(follows::follower.eq(user)).count().get_result(conn).context("failed to count following")?;
+    Ok((followers, following))
+}
+
+// 被关注用户贡献的内容, 用于地点, 回忆和动态的筛选
+pub fn followed_by<QS>(column: &str, user: i32) -> Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>> {
+    Box::new(sql::<Bool>(&format!("{} IN (SELECT followee FROM follows WHERE follower = ", column)).bind::<Integer, _>(user).sql(")"))
+}
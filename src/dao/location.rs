@@ -1,4 +1,5 @@
-use crate::models::{Location, LocationInsertion, LocationUpdating, LocationUploadRel, Upload, User};
+use super::{follow, search};
+use crate::models::{Comment, Location, LocationInsertion, LocationUpdating, LocationUploadRel, Upload, User};
 use crate::schema::*;
 use crate::serde::Deserialize;
 use anyhow::{Context, Error};
@@ -7,23 +8,50 @@ use diesel::{
     dsl::{self, sql},
     insert_into,
     pg::{Pg, PgConnection},
-    sql_types::Double,
-    BelongingToDsl, Connection, ExpressionMethods, QueryDsl, RunQueryDsl, TextExpressionMethods,
+    sql_types::{BigInt, Bool, Double},
+    BelongingToDsl, BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, RunQueryDsl,
 };
+use std::collections::HashMap;
 
 #[derive(Debug, Default, Deserialize)]
 pub struct Query {
-    pub name: Option<String>,
+    pub keyword: Option<String>,
     pub latitude: f64,
     pub longitude: f64,
     pub radius: f64,
     pub category: Option<i32>,
+    pub ratings_gte: Vec<(Dimension, f64)>,
+    pub age_months: Option<i32>,
+    // 只返回该用户关注的人发现的地点
+    pub followed_by: Option<i32>,
     pub limit: i64,
     pub offset: i64,
     pub order_by: OrderBy,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Cleanliness,
+    Space,
+    Staff,
+    Accessibility,
+    Noise,
+}
+
+impl Dimension {
+    fn column(&self) -> &'static str {
+        match self {
+            Self::Cleanliness => "cleanliness",
+            Self::Space => "space",
+            Self::Staff => "staff",
+            Self::Accessibility => "accessibility",
+            Self::Noise => "noise",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum OrderBy {
     DistanceAsc,
@@ -34,6 +62,11 @@ pub enum OrderBy {
     UpdateOnDesc,
     NameAsc,
     NameDesc,
+    FavoriteCountAsc,
+    FavoriteCountDesc,
+    ScoreAsc,
+    ScoreDesc,
+    Relevance,
 }
 
 impl Default for OrderBy {
@@ -68,15 +101,45 @@ where
         .offset(query.offset)
         .into_boxed();
 
-    if let Some(name) = query.name {
-        c = c.filter(locations::name.like(format!("%{}%", name)));
-        q = q.filter(locations::name.like(format!("%{}%", name)));
+    if let Some(keyword) = &query.keyword {
+        c = c.filter(search::matches(search::LOCATION_DOCUMENT, keyword));
+        q = q.filter(search::matches(search::LOCATION_DOCUMENT, keyword));
     }
     if let Some(category) = query.category {
         c = c.filter(locations::category.eq(category));
         q = q.filter(locations::category.eq(category));
     }
+    if let Some(user) = query.followed_by {
+        c = c.filter(follow::followed_by("locations.discoverer", user));
+        q = q.filter(follow::followed_by("locations.discoverer", user));
+    }
+    // 没有设置月龄范围的地点视为适合所有年龄
+    if let Some(age) = query.age_months {
+        c = c.filter(locations::min_age_months.is_null().or(locations::min_age_months.le(age)));
+        c = c.filter(locations::max_age_months.is_null().or(locations::max_age_months.ge(age)));
+        q = q.filter(locations::min_age_months.is_null().or(locations::min_age_months.le(age)));
+        q = q.filter(locations::max_age_months.is_null().or(locations::max_age_months.ge(age)));
+    }
+    // 没有人评价过的维度不满足筛选条件
+    for (dim, gte) in query.ratings_gte {
+        let cond = format!(
+            "locations.id IN (SELECT location_id FROM rank_aggregations WHERE {0}_count > 0 AND {0}_total::float8 / {0}_count >= ",
+            dim.column()
+        );
+        c = c.filter(sql::<Bool>(&cond).bind::<Double, _>(gte).sql(")"));
+        q = q.filter(sql::<Bool>(&cond).bind::<Double, _>(gte).sql(")"));
+    }
     match query.order_by {
+        OrderBy::Relevance => match &query.keyword {
+            Some(keyword) => {
+                let distance = format!(
+                    "earth_distance(ll_to_earth({}, {}), ll_to_earth(locations.latitude, locations.longitude))",
+                    query.latitude, query.longitude
+                );
+                q = q.order_by(search::relevance(search::LOCATION_DOCUMENT, keyword, &distance).desc())
+            }
+            None => q = q.order_by(sql::<Double>("distance")),
+        },
         OrderBy::DistanceAsc => q = q.order_by(sql::<Double>("distance")),
         OrderBy::CreateOnAsc => q = q.order_by(locations::create_on),
         OrderBy::UpdateOnAsc => q = q.order_by(locations::update_on),
@@ -85,6 +148,10 @@ where
         OrderBy::CreateOnDesc => q = q.order_by(locations::create_on.desc()),
         OrderBy::UpdateOnDesc => q = q.order_by(locations::update_on.desc()),
         OrderBy::NameDesc => q = q.order_by(locations::name.desc()),
+        OrderBy::FavoriteCountAsc => q = q.order_by(sql::<BigInt>("(SELECT COUNT(*) FROM favorites WHERE favorites.location = locations.id)")),
+        OrderBy::ScoreAsc => q = q.order_by(sql::<Double>("(SELECT score FROM rank_aggregations WHERE rank_aggregations.location_id = locations.id)")),
+        OrderBy::ScoreDesc => q = q.order_by(sql::<Double>("(SELECT score FROM rank_aggregations WHERE rank_aggregations.location_id = locations.id) DESC")),
+        OrderBy::FavoriteCountDesc => q = q.order_by(sql::<BigInt>("(SELECT COUNT(*) FROM favorites WHERE favorites.location = locations.id) DESC")),
     }
     let total = c.count().get_result(conn).context("failed to find locations")?;
     let rows: Vec<(Location, f64)> = q.load(conn).context("failed to find locations")?;
@@ -126,6 +193,35 @@ where
     Ok((loc, user, images))
 }
 
+pub fn get_discoverer<T>(conn: &T, id: i32) -> Result<i32, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    locations::table
+        .select(locations::discoverer)
+        .filter(locations::id.eq(id))
+        .get_result(conn)
+        .context("failed to get discoverer of location")
+}
+
+pub fn locations_of_comments<T>(conn: &T, comments: &Vec<Comment>) -> Result<Vec<Location>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let locs: HashMap<i32, Location> = locations::table
+        .filter(locations::id.eq_any(comments.iter().map(|c| c.location).collect::<Vec<i32>>()))
+        .load::<Location>(conn)
+        .context("failed to query locations of comments")?
+        .into_iter()
+        .map(|l| (l.id, l))
+        .collect();
+    comments
+        .iter()
+        .map(|c| locs.get(&c.location).cloned().ok_or(Error::msg("location not exists")))
+        .collect::<Result<Vec<Location>, Error>>()
+        .context("failed to query locations of comments")
+}
+
 pub fn insert(conn: &PgConnection, loc: LocationInsertion) -> Result<i32, Error> {
     insert_into(locations::table).values(loc).returning(locations::id).get_result(conn).context("failed to insert location")
 }
@@ -167,8 +263,8 @@ where
             query.latitude, query.longitude, query.radius
         )))
         .into_boxed();
-    if let Some(name) = query.name {
-        q = q.filter(locations::name.like(format!("%{}%", name)))
+    if let Some(keyword) = &query.keyword {
+        q = q.filter(search::matches(search::LOCATION_DOCUMENT, keyword))
     }
     if let Some(category) = query.category {
         q = q.filter(locations::category.eq(category))
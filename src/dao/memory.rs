@@ -1,10 +1,58 @@
-use crate::models::{Location, Memory, MemoryCommand, MemoryOrderBy, MemoryQuery, MemoryUploadRel, Upload};
+use super::{follow, search};
+use crate::models::{Child, Location, Memory, MemoryCommand, MemoryOrderBy, MemoryQuery, MemoryUploadRel, Upload};
 use crate::schema::*;
 use anyhow::{Context, Error};
-use diesel::{self, delete, dsl::sql, insert_into, pg::Pg, sql_types::Double, BelongingToDsl, Connection, ExpressionMethods, GroupedBy, QueryDsl, RunQueryDsl, TextExpressionMethods};
+use chrono::NaiveDateTime;
+use diesel::{
+    self,
+    dsl::sql,
+    insert_into,
+    pg::Pg,
+    sql_query,
+    sql_types::{BigInt, Bool, Double, Integer, Nullable, Text, Timestamp},
+    BelongingToDsl, BoxableExpression, Connection, ExpressionMethods, GroupedBy, QueryDsl, RunQueryDsl,
+};
 use itertools::{multiunzip, unfold};
+use serde::Serialize;
 
-pub fn find<T>(conn: &T, query: MemoryQuery) -> Result<(Vec<Memory>, Vec<Location>, Vec<f64>, i64), Error>
+// 可见性规则只在这里定义, diesel 查询和时间线的原始 SQL 共用; user 为 SQL 表达式, 可以是整数字面量或者 $1 这样的参数
+// 该用户以及同一家庭的成员
+fn family_members_sql(user: &str) -> String {
+    format!(
+        "SELECT {user} UNION SELECT fm.\"user\" FROM family_members AS fm JOIN family_members AS me ON fm.family = me.family WHERE me.\"user\" = {user}",
+        user = user
+    )
+}
+
+// 公开的, 自己的, 以及同一家庭成员的家庭可见回忆
+fn visibility_sql(viewer: &str) -> String {
+    format!(
+        "(memories.visibility = 'public' OR memories.owner = {viewer} OR (memories.visibility = 'family' AND memories.owner IN ({members})))",
+        viewer = viewer,
+        members = family_members_sql(viewer)
+    )
+}
+
+pub fn visible_to<QS>(viewer: i32) -> Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>> {
+    Box::new(sql::<Bool>(&visibility_sql(&viewer.to_string())))
+}
+
+fn owned_by_family_of<QS>(user: i32) -> Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>> {
+    Box::new(sql::<Bool>(&format!("memories.owner IN ({})", family_members_sql(&user.to_string()))))
+}
+
+fn anniversary<QS>(month: u32, day: u32) -> Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>> {
+    Box::new(
+        sql::<Bool>("(EXTRACT(MONTH FROM memories.create_on) = ")
+            .bind::<Integer, _>(month as i32)
+            .sql(" AND EXTRACT(DAY FROM memories.create_on) = ")
+            .bind::<Integer, _>(day as i32)
+            .sql(")"),
+    )
+}
+
+// 只返回对 viewer 可见的回忆
+pub fn find<T>(conn: &T, viewer: i32, query: MemoryQuery) -> Result<(Vec<Memory>, Vec<Location>, Vec<f64>, i64), Error>
 where
     T: Connection<Backend = Pg>,
 {
@@ -22,9 +70,11 @@ where
         .offset(query.offset)
         .into_boxed();
     let mut c = memories::table.into_boxed();
-    if let Some(title) = query.title {
-        q = q.filter(memories::title.like(format!("%{}%", title)));
-        c = c.filter(memories::title.like(format!("%{}%", title)));
+    q = q.filter(visible_to(viewer));
+    c = c.filter(visible_to(viewer));
+    if let Some(keyword) = &query.keyword {
+        q = q.filter(search::matches(search::MEMORY_DOCUMENT, keyword));
+        c = c.filter(search::matches(search::MEMORY_DOCUMENT, keyword));
     }
     if let Some(owner) = query.owner {
         q = q.filter(memories::owner.eq(owner));
@@ -34,13 +84,29 @@ where
         q = q.filter(memories::location.eq(location));
         c = c.filter(memories::location.eq(location));
     }
+    if let Some(child) = query.child {
+        q = q.filter(memories::id.eq_any(memory_child_rels::table.filter(memory_child_rels::child.eq(child)).select(memory_child_rels::memory)));
+        c = c.filter(memories::id.eq_any(memory_child_rels::table.filter(memory_child_rels::child.eq(child)).select(memory_child_rels::memory)));
+    }
     if let Some(create_before) = query.create_before {
         q = q.filter(memories::create_on.lt(create_before));
         c = c.filter(memories::create_on.lt(create_before));
     }
     if let Some(create_after) = query.create_after {
-        q = q.filter(memories::create_on.lt(create_after));
-        c = c.filter(memories::create_on.lt(create_after));
+        q = q.filter(memories::create_on.gt(create_after));
+        c = c.filter(memories::create_on.gt(create_after));
+    }
+    if let Some(user) = query.family_of {
+        q = q.filter(owned_by_family_of(user));
+        c = c.filter(owned_by_family_of(user));
+    }
+    if let Some(user) = query.followed_by {
+        q = q.filter(follow::followed_by("memories.owner", user));
+        c = c.filter(follow::followed_by("memories.owner", user));
+    }
+    if let Some((month, day)) = query.anniversary {
+        q = q.filter(anniversary(month, day));
+        c = c.filter(anniversary(month, day));
     }
     match query.order_by {
         MemoryOrderBy::CreateOn => q = q.order_by(memories::create_on),
@@ -51,6 +117,16 @@ where
         MemoryOrderBy::DistanceDesc => q = q.order_by(sql::<Double>("distance desc")),
         MemoryOrderBy::Title => q = q.order_by(memories::title),
         MemoryOrderBy::TitleDesc => q = q.order_by(memories::title.desc()),
+        MemoryOrderBy::Relevance => match &query.keyword {
+            Some(keyword) => {
+                let distance = format!(
+                    "earth_distance(ll_to_earth({}, {}), ll_to_earth(locations.latitude, locations.longitude))",
+                    query.latitude, query.longitude
+                );
+                q = q.order_by(search::relevance(search::MEMORY_DOCUMENT, keyword, &distance).desc())
+            }
+            None => q = q.order_by(sql::<Double>("distance")),
+        },
     }
     let total = c.count().get_result(conn).context("failed to find memory")?;
     let list: Vec<(Memory, Location, f64)> = q.load::<(Memory, Location, f64)>(conn)?;
@@ -58,6 +134,108 @@ where
     Ok((m, l, d, total))
 }
 
+pub fn get<T>(conn: &T, id: i32) -> Result<Memory, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    memories::table.find(id).get_result(conn).context("failed to get memory")
+}
+
+pub fn is_visible<T>(conn: &T, id: i32, viewer: i32) -> Result<bool, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let count: i64 = memories::table
+        .filter(memories::id.eq(id))
+        .filter(visible_to(viewer))
+        .count()
+        .get_result(conn)
+        .context("failed to check visibility of memory")?;
+    Ok(count > 0)
+}
+
+// 关联了该图片的回忆中是否有 viewer 可见的
+pub fn is_upload_visible<T>(conn: &T, upload: i32, viewer: i32) -> Result<bool, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let count: i64 = memories::table
+        .inner_join(memory_upload_rels::table)
+        .filter(memory_upload_rels::upload.eq(upload))
+        .filter(visible_to(viewer))
+        .count()
+        .get_result(conn)
+        .context("failed to check visibility of upload")?;
+    Ok(count > 0)
+}
+
+// 自己和家庭成员的回忆中对当前用户可见的, $1 为当前用户, $2 $3 为可选的时间范围
+fn family_memories() -> String {
+    format!(
+        "SELECT id, create_on, date_trunc('month', create_on) AS month FROM memories
+        WHERE memories.owner IN ({}) AND {}
+        AND ($2::timestamp IS NULL OR create_on < $2)
+        AND ($3::timestamp IS NULL OR create_on > $3)",
+        family_members_sql("$1"),
+        visibility_sql("$1")
+    )
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct TimelineMonth {
+    #[sql_type = "Timestamp"]
+    pub month: NaiveDateTime,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+    #[sql_type = "Nullable<Integer>"]
+    pub cover: Option<i32>,
+    #[sql_type = "Nullable<Text>"]
+    pub cover_fetch_code: Option<String>,
+}
+
+#[derive(Debug, QueryableByName)]
+struct Count {
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+// 按月分组, 封面取当月最新一条带图片的回忆的第一张图片
+pub fn timeline<T>(conn: &T, user: i32, create_before: Option<NaiveDateTime>, create_after: Option<NaiveDateTime>, limit: i64, offset: i64) -> Result<(Vec<TimelineMonth>, i64), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let total = sql_query(format!("SELECT COUNT(DISTINCT month) AS count FROM ({}) AS visible", family_memories()))
+        .bind::<Integer, _>(user)
+        .bind::<Nullable<Timestamp>, _>(create_before)
+        .bind::<Nullable<Timestamp>, _>(create_after)
+        .get_result::<Count>(conn)
+        .context("failed to query memory timeline")?
+        .count;
+    let list = sql_query(format!(
+        "WITH visible AS ({}), months AS (SELECT month, COUNT(*) AS count FROM visible GROUP BY month)
+        SELECT months.month, months.count, cover.id AS cover, cover.fetch_code AS cover_fetch_code
+        FROM months LEFT JOIN LATERAL (
+            SELECT uploads.id, uploads.fetch_code FROM visible
+            JOIN memory_upload_rels ON memory_upload_rels.memory = visible.id
+            JOIN uploads ON uploads.id = memory_upload_rels.upload
+            WHERE visible.month = months.month
+            ORDER BY visible.create_on DESC, memory_upload_rels.id
+            LIMIT 1
+        ) AS cover ON TRUE
+        ORDER BY months.month DESC
+        LIMIT $4 OFFSET $5",
+        family_memories()
+    ))
+    .bind::<Integer, _>(user)
+    .bind::<Nullable<Timestamp>, _>(create_before)
+    .bind::<Nullable<Timestamp>, _>(create_after)
+    .bind::<BigInt, _>(limit)
+    .bind::<BigInt, _>(offset)
+    .load(conn)
+    .context("failed to query memory timeline")?;
+    Ok((list, total))
+}
+
 pub fn insert<T>(conn: &T, ins: MemoryCommand) -> Result<i32, Error>
 where
     T: Connection<Backend = Pg>,
@@ -72,11 +250,20 @@ where
     diesel::update(memories::table).filter(memories::id.eq(id)).set(upd).execute(conn).context("failed to update memory")
 }
 
+// memory_upload_rels 没有级联删除, 需要先清除图片关联
+pub fn delete<T>(conn: &T, id: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    clear_images(conn, id)?;
+    diesel::delete(memories::table.filter(memories::id.eq(id))).execute(conn).context("failed to delete memory")
+}
+
 pub fn clear_images<T>(conn: &T, id: i32) -> Result<usize, Error>
 where
     T: Connection<Backend = Pg>,
 {
-    delete(memory_upload_rels::table)
+    diesel::delete(memory_upload_rels::table)
         .filter(memory_upload_rels::memory.eq(id))
         .execute(conn)
         .context("failed to clear images of memory")
@@ -104,3 +291,53 @@ where
     let upload_set: Vec<Vec<(MemoryUploadRel, Upload)>> = MemoryUploadRel::belonging_to(memories).inner_join(uploads::table).load(conn)?.grouped_by(memories);
     Ok(upload_set.into_iter().map(|upls| upls.into_iter().map(|(_, u)| u).collect()).collect())
 }
+
+pub fn images_of_memory<T>(conn: &T, id: i32) -> Result<Vec<Upload>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    uploads::table
+        .inner_join(memory_upload_rels::table)
+        .select(uploads::all_columns)
+        .filter(memory_upload_rels::memory.eq(id))
+        .load(conn)
+        .context("failed to query images of memory")
+}
+
+pub fn clear_children<T>(conn: &T, id: i32) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    diesel::delete(memory_child_rels::table)
+        .filter(memory_child_rels::memory.eq(id))
+        .execute(conn)
+        .context("failed to clear children of memory")
+}
+
+pub fn add_children<T>(conn: &T, id: i32, children: Vec<i32>) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(memory_child_rels::table)
+        .values(
+            children
+                .into_iter()
+                .map(|child| (memory_child_rels::memory.eq(id), memory_child_rels::child.eq(child)))
+                .collect::<Vec<_>>(),
+        )
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context("failed to add children for memory")
+}
+
+pub fn children_of_memory<T>(conn: &T, id: i32) -> Result<Vec<Child>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    children::table
+        .inner_join(memory_child_rels::table)
+        .select(children::all_columns)
+        .filter(memory_child_rels::memory.eq(id))
+        .load(conn)
+        .context("failed to query children of memory")
+}
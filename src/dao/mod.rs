@@ -1,7 +1,14 @@
+pub mod activity;
+pub mod category;
 pub mod comment;
 pub mod equipment;
+pub mod family;
+pub mod favorite;
+pub mod follow;
 pub mod location;
 pub mod memory;
+pub mod notification;
 pub mod rank_aggregation;
+pub mod search;
 pub mod upload;
 pub mod user;
@@ -0,0 +1,91 @@
+use crate::domain::notification::{self, Insertion, Kind, NotificationPersister};
+use crate::models::Notification;
+use crate::schema::*;
+use anyhow::{Context, Error};
+use diesel::{insert_into, pg::Pg, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+
+impl TryFrom<Notification> for notification::Notification {
+    type Error = Error;
+    fn try_from(n: Notification) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: n.id,
+            recipient: n.recipient,
+            actor: n.actor,
+            kind: Kind::parse(&n.kind)?,
+            location: n.location,
+            target: n.target,
+            content: n.content,
+            is_read: n.is_read,
+            create_on: n.create_on,
+        })
+    }
+}
+
+impl NotificationPersister for PgConnection {
+    fn insert_notification(&self, ins: Insertion) -> Result<notification::Notification, Error> {
+        let n: Notification = insert_into(notifications::table)
+            .values((
+                notifications::recipient.eq(ins.recipient),
+                notifications::actor.eq(ins.actor),
+                notifications::kind.eq(ins.kind.as_str()),
+                notifications::location.eq(ins.location),
+                notifications::target.eq(ins.target),
+                notifications::content.eq(ins.content),
+            ))
+            .get_result(self)
+            .context("failed to insert notification")?;
+        n.try_into()
+    }
+}
+
+pub fn query<T>(conn: &T, recipient: i32, unread_only: bool, limit: i64, offset: i64) -> Result<(Vec<notification::Notification>, i64), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let mut q = notifications::table
+        .filter(notifications::recipient.eq(recipient))
+        .order_by(notifications::id.desc())
+        .limit(limit)
+        .offset(offset)
+        .into_boxed();
+    let mut c = notifications::table.filter(notifications::recipient.eq(recipient)).into_boxed();
+    if unread_only {
+        q = q.filter(notifications::is_read.eq(false));
+        c = c.filter(notifications::is_read.eq(false));
+    }
+    let total = c.count().get_result(conn).context("failed to query notifications")?;
+    let list = q
+        .load::<Notification>(conn)
+        .context("failed to query notifications")?
+        .into_iter()
+        .map(notification::Notification::try_from)
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok((list, total))
+}
+
+pub fn unread_count<T>(conn: &T, recipient: i32) -> Result<i64, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    notifications::table
+        .filter(notifications::recipient.eq(recipient))
+        .filter(notifications::is_read.eq(false))
+        .count()
+        .get_result(conn)
+        .context("failed to count unread notifications")
+}
+
+// id 为空时全部标记为已读
+pub fn mark_read<T>(conn: &T, recipient: i32, id: Option<i32>) -> Result<usize, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let mut q = diesel::update(notifications::table)
+        .filter(notifications::recipient.eq(recipient))
+        .filter(notifications::is_read.eq(false))
+        .into_boxed();
+    if let Some(id) = id {
+        q = q.filter(notifications::id.eq(id));
+    }
+    q.set(notifications::is_read.eq(true)).execute(conn).context("failed to mark notifications as read")
+}
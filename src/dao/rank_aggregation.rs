@@ -1,7 +1,13 @@
-use crate::models::{Location, RankAggregation, RankAggregationInsert, RankAggregationUpdate};
+use crate::models::{Location, RankAggregation, RankAggregationInsert};
 use crate::schema::*;
 use anyhow::{Context, Error};
-use diesel::{insert_into, pg::Pg, BelongingToDsl, Connection, ExpressionMethods, GroupedBy, QueryDsl, RunQueryDsl};
+use diesel::{
+    dsl::{select, sql},
+    insert_into,
+    pg::Pg,
+    sql_types::{BigInt, Integer, Nullable},
+    BelongingToDsl, Connection, GroupedBy, RunQueryDsl,
+};
 
 pub fn insert<T>(conn: &T, ra: RankAggregationInsert) -> Result<i32, Error>
 where
@@ -14,31 +20,27 @@ where
         .context("failed to insert rank aggregation")
 }
 
-pub fn update<T>(conn: &T, loc: i32, ra: RankAggregationUpdate) -> Result<usize, Error>
+// 以评论为准重新计算评分聚合, 平时由 comments 表上的触发器维护
+pub fn recompute<T>(conn: &T, loc: Option<i32>) -> Result<i64, Error>
 where
     T: Connection<Backend = Pg>,
 {
-    diesel::update(rank_aggregations::table.filter(rank_aggregations::location_id.eq(loc)))
-        .set(ra)
-        .execute(conn)
-        .context("failed to update rank aggregation")
+    select(sql::<BigInt>("recompute_rank_aggregations(").bind::<Nullable<Integer>, _>(loc).sql(")"))
+        .get_result(conn)
+        .context("failed to recompute rank aggregations")
 }
 
-pub fn get_for_update<T>(conn: &T, loc: i32) -> Result<RankAggregation, Error>
+pub fn rank_aggs_of_location<T>(conn: &T, locs: &Vec<Location>) -> Result<Vec<RankAggregation>, Error>
 where
     T: Connection<Backend = Pg>,
 {
-    rank_aggregations::table
-        .filter(rank_aggregations::location_id.eq(loc))
-        .for_update()
-        .first(conn)
-        .context("failed to get rank aggregation for update")
+    let l: Vec<Vec<RankAggregation>> = RankAggregation::belonging_to(locs).load::<RankAggregation>(conn)?.grouped_by(locs);
+    Ok(l.into_iter().flatten().collect())
 }
 
-pub fn rank_aggs_of_location<T>(conn: &T, locs: &Vec<Location>) -> Result<Vec<RankAggregation>, Error>
+pub fn rank_agg_of_location<T>(conn: &T, loc: &Location) -> Result<RankAggregation, Error>
 where
     T: Connection<Backend = Pg>,
 {
-    let l: Vec<Vec<RankAggregation>> = RankAggregation::belonging_to(locs).load::<RankAggregation>(conn)?.grouped_by(locs);
-    Ok(l.into_iter().flatten().collect())
+    RankAggregation::belonging_to(loc).first(conn).context("failed to get rank aggregation of location")
 }
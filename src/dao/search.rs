@@ -0,0 +1,141 @@
+use anyhow::{Context, Error};
+use diesel::{
+    dsl::sql,
+    pg::Pg,
+    sql_query,
+    sql_types::{BigInt, Bool, Double, Integer, Text},
+    BoxableExpression, Connection, RunQueryDsl,
+};
+
+// 被检索的文本, 需要和 migration 中的索引表达式保持一致才能命中索引
+pub const LOCATION_DOCUMENT: &str = "(locations.name || ' ' || locations.description)";
+pub const MEMORY_DOCUMENT: &str = "(memories.title || ' ' || memories.content)";
+pub const COMMENT_DOCUMENT: &str = "comments.content";
+
+fn escape_like(keyword: &str) -> String {
+    keyword.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn like_pattern(keyword: &str) -> String {
+    format!("%{}%", escape_like(keyword))
+}
+
+// 中日韩文字由 search_tokens 切分为单字和 bigram, 一两个字的关键字也能命中索引
+fn document_vector(document: &str) -> String {
+    format!("to_tsvector('simple', search_tokens({}, FALSE))", document)
+}
+
+// 全文检索命中, 关键字中的所有词都需要出现
+pub fn matches<QS>(document: &str, keyword: &str) -> Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>> {
+    Box::new(
+        sql::<Bool>(&format!("({} @@ plainto_tsquery('simple', search_tokens(", document_vector(document)))
+            .bind::<Text, _>(keyword.to_owned())
+            .sql(", TRUE)))"),
+    )
+}
+
+// 文本相关度, 按距离衰减: 每远 1 公里相关度降低为原来的 1 / (1 + km)
+pub fn relevance<QS>(document: &str, keyword: &str, distance: &str) -> Box<dyn BoxableExpression<QS, Pg, SqlType = Double>> {
+    Box::new(
+        sql::<Double>(&format!("((ts_rank({}, plainto_tsquery('simple', search_tokens(", document_vector(document)))
+            .bind::<Text, _>(keyword.to_owned())
+            .sql(&format!(", TRUE))) + similarity({}, ", document))
+            .bind::<Text, _>(keyword.to_owned())
+            .sql(&format!("))::float8 / (1 + {} / 1000))", distance)),
+    )
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct LocationSuggestion {
+    #[sql_type = "Integer"]
+    pub id: i32,
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "Double"]
+    pub distance: f64,
+}
+
+// 只在附近范围内查找, 通过 earth_box 命中地点坐标的 gist 索引, 避免对全部 trigram 匹配结果计算距离
+const SUGGEST_RADIUS: f64 = 50_000.0;
+
+// 名称包含关键字或 trigram 相似的附近地点, 前缀匹配额外加分, 分数按距离衰减, 越近越靠前
+pub fn suggest_locations<T>(conn: &T, keyword: &str, latitude: f64, longitude: f64, limit: i64) -> Result<Vec<LocationSuggestion>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    sql_query(format!(
+        "SELECT id, name, distance FROM (
+            SELECT id, name, similarity(name, $1) + CASE WHEN name ILIKE $3 THEN 1 ELSE 0 END AS score,
+                earth_distance(ll_to_earth({lat}, {lng}), ll_to_earth(latitude, longitude)) AS distance
+            FROM locations
+            WHERE earth_box(ll_to_earth({lat}, {lng}), {radius}) @> ll_to_earth(latitude, longitude) AND (name % $1 OR name ILIKE $2)
+        ) AS t
+        ORDER BY score / (1 + distance / 1000) DESC
+        LIMIT $4",
+        lat = latitude,
+        lng = longitude,
+        radius = SUGGEST_RADIUS
+    ))
+    .bind::<Text, _>(keyword)
+    .bind::<Text, _>(like_pattern(keyword))
+    .bind::<Text, _>(format!("{}%", escape_like(keyword)))
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+    .context("failed to suggest locations")
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct CategorySuggestion {
+    #[sql_type = "Integer"]
+    pub id: i32,
+    #[sql_type = "Text"]
+    pub name: String,
+}
+
+pub fn suggest_categories<T>(conn: &T, keyword: &str, limit: i64) -> Result<Vec<CategorySuggestion>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    // 相似度相同时地点多的分类靠前
+    sql_query(
+        "SELECT id, name FROM categories WHERE name ILIKE $1
+        ORDER BY similarity(name, $2) DESC, (SELECT COUNT(*) FROM locations WHERE locations.category = categories.id) DESC
+        LIMIT $3",
+    )
+    .bind::<Text, _>(like_pattern(keyword))
+    .bind::<Text, _>(keyword)
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+    .context("failed to suggest categories")
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct EquipmentSuggestion {
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "BigInt"]
+    pub count: i64,
+}
+
+// 设施按名称聚合, 拥有该设施的地点越多越靠前
+pub fn suggest_equipments<T>(conn: &T, keyword: &str, limit: i64) -> Result<Vec<EquipmentSuggestion>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    sql_query("SELECT name, COUNT(*) AS count FROM equipments WHERE name % $1 OR name ILIKE $2 GROUP BY name ORDER BY count DESC LIMIT $3")
+        .bind::<Text, _>(keyword)
+        .bind::<Text, _>(like_pattern(keyword))
+        .bind::<BigInt, _>(limit)
+        .load(conn)
+        .context("failed to suggest equipments")
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_like_pattern() {
+        assert_eq!(super::like_pattern("公园"), "%公园%");
+        assert_eq!(super::like_pattern("100%_"), "%100\\%\\_%");
+        assert_eq!(super::like_pattern("a\\b"), "%a\\\\b%");
+    }
+}
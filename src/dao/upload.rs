@@ -1,7 +1,17 @@
-use crate::models::{Location, LocationUploadRel, Upload};
+use crate::models::{Location, LocationUploadRel, Upload, UploadRendition, UploadRenditionInsertion, UploadSession, UploadStatus, Variant, Visibility};
 use crate::schema::*;
 use anyhow::{Context, Error};
-use diesel::{insert_into, pg::Pg, BelongingToDsl, Connection, ExpressionMethods, GroupedBy, Insertable, QueryDsl, RunQueryDsl};
+use chrono::Duration;
+use diesel::{
+    delete,
+    dsl::{exists, now, sql, sql_query},
+    insert_into,
+    pg::{data_types::PgInterval, Pg},
+    select,
+    sql_types::{BigInt, Bool, Integer},
+    update, BelongingToDsl, BoolExpressionMethods, BoxableExpression, Connection, ExpressionMethods, GroupedBy, Insertable, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use itertools::Itertools;
 
 #[derive(Debug, Insertable)]
 #[table_name = "location_upload_rels"]
@@ -80,3 +90,437 @@ where
         .map(|(_, u)| u)
         .collect())
 }
+
+pub fn get<T>(conn: &T, id: i32) -> Result<Upload, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    uploads::table.find(id).get_result(conn).context("failed to get upload")
+}
+
+pub fn get_by_ids<T>(conn: &T, ids: &Vec<i32>) -> Result<Vec<Upload>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    uploads::table.filter(uploads::id.eq_any(ids)).load(conn).context("failed to get uploads")
+}
+
+// 上传的可见范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Audience {
+    // 被地点, 评论, playing, eating, 头像或公开的回忆引用
+    Public,
+    // 只被非公开的回忆引用, 跟随回忆的可见性
+    Memory,
+    // 还没有被引用, 只有上传者自己可见
+    Owner,
+}
+
+pub fn audience<T>(conn: &T, id: i32) -> Result<Audience, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let (public, in_memory): (bool, bool) = select((
+        exists(location_upload_rels::table.filter(location_upload_rels::upload_id.eq(id)))
+            .or(exists(comment_upload_rels::table.filter(comment_upload_rels::upload.eq(id))))
+            .or(exists(playings_uploads::table.filter(playings_uploads::upload_id.eq(id))))
+            .or(exists(eatings_uploads::table.filter(eatings_uploads::upload_id.eq(id))))
+            .or(exists(users::table.filter(users::avatar.eq(id))))
+            .or(exists(
+                memory_upload_rels::table
+                    .inner_join(memories::table)
+                    .filter(memory_upload_rels::upload.eq(id))
+                    .filter(memories::visibility.eq(Visibility::Public)),
+            )),
+        exists(memory_upload_rels::table.filter(memory_upload_rels::upload.eq(id))),
+    ))
+    .get_result(conn)
+    .context("failed to check attachments of upload")?;
+    Ok(match (public, in_memory) {
+        (true, _) => Audience::Public,
+        (false, true) => Audience::Memory,
+        (false, false) => Audience::Owner,
+    })
+}
+
+// 等待后台处理的上传, 按上传顺序处理; 存储出错的上传留在 pending 中, 按 id 分页跳过
+pub fn pending<T>(conn: &T, after: i32, limit: i64) -> Result<Vec<Upload>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    uploads::table
+        .filter(uploads::status.eq(UploadStatus::Pending))
+        .filter(uploads::id.gt(after))
+        .order(uploads::id)
+        .limit(limit)
+        .load(conn)
+        .context("failed to query pending uploads")
+}
+
+// 原始文件中可能带有拍摄位置等信息, 完成之后内容相同的上传都改为指向去除元数据之后的 original
+// 返回不再被引用, 可以从存储中删除的原始文件
+pub fn complete<T>(conn: &T, upload: &Upload, renditions: Vec<UploadRenditionInsertion>, width: i32, height: i32, duration: Option<f64>) -> Result<Option<String>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let original = renditions
+        .iter()
+        .find(|r| r.variant == Variant::Original)
+        .map(|r| r.fetch_code.clone())
+        .context("no original rendition")?;
+    conn.transaction::<Option<String>, Error, _>(|| {
+        insert_into(upload_renditions::table).values(renditions).execute(conn)?;
+        update(uploads::table.find(upload.id))
+            .set((
+                uploads::status.eq(UploadStatus::Ready),
+                uploads::width.eq(width),
+                uploads::height.eq(height),
+                uploads::duration.eq(duration),
+            ))
+            .execute(conn)?;
+        // 先更新摘要记录, 同时插入的相同内容的上传会等待并取得新的存储对象
+        match &upload.hash {
+            Some(hash) => {
+                update(blobs::table.filter(blobs::hash.eq(hash)).filter(blobs::storage_key.eq(&upload.fetch_code)))
+                    .set(blobs::storage_key.eq(&original))
+                    .execute(conn)?;
+                update(uploads::table.filter(uploads::hash.eq(hash)).filter(uploads::fetch_code.eq(&upload.fetch_code)))
+                    .set(uploads::fetch_code.eq(&original))
+                    .execute(conn)?;
+            }
+            None => {
+                update(uploads::table.find(upload.id)).set(uploads::fetch_code.eq(&original)).execute(conn)?;
+            }
+        }
+        // 之前由其他上传生成的 original 可能仍被共享
+        let in_use: i64 = upload_renditions::table.filter(upload_renditions::fetch_code.eq(&upload.fetch_code)).count().get_result(conn)?;
+        Ok(if in_use == 0 { Some(upload.fetch_code.clone()) } else { None })
+    })
+    .context("failed to complete upload")
+}
+
+// 内容相同并且已经处理完成的上传, 直接共享它的各个尺寸; 没有时返回 false
+// 锁住摘要记录, 与回收时对同一摘要的释放互斥, 避免共享了正在被删除的尺寸
+pub fn share_renditions<T>(conn: &T, upload: &Upload) -> Result<bool, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let hash = match &upload.hash {
+        Some(hash) => hash,
+        None => return Ok(false),
+    };
+    conn.transaction::<bool, Error, _>(|| {
+        blobs::table.filter(blobs::hash.eq(hash)).select(blobs::id).for_update().first::<i32>(conn).optional()?;
+        let source: Option<Upload> = uploads::table
+            .filter(uploads::hash.eq(hash))
+            .filter(uploads::status.eq(UploadStatus::Ready))
+            .filter(uploads::id.ne(upload.id))
+            .order(uploads::id)
+            .first(conn)
+            .optional()?;
+        let source = match source {
+            Some(source) => source,
+            None => return Ok(false),
+        };
+        let renditions: Vec<UploadRenditionInsertion> = upload_renditions::table
+            .filter(upload_renditions::upload.eq(source.id))
+            .load::<UploadRendition>(conn)?
+            .into_iter()
+            .map(|r| UploadRenditionInsertion {
+                upload: upload.id,
+                variant: r.variant,
+                fetch_code: r.fetch_code,
+                mime: r.mime,
+                width: r.width,
+                height: r.height,
+                size: r.size,
+            })
+            .collect();
+        insert_into(upload_renditions::table).values(renditions).execute(conn)?;
+        update(uploads::table.find(upload.id))
+            .set((
+                uploads::status.eq(UploadStatus::Ready),
+                uploads::width.eq(source.width),
+                uploads::height.eq(source.height),
+                uploads::duration.eq(source.duration),
+            ))
+            .execute(conn)?;
+        Ok(true)
+    })
+    .context("failed to share upload renditions")
+}
+
+pub fn fail<T>(conn: &T, upload: i32) -> Result<(), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    update(uploads::table.find(upload))
+        .set(uploads::status.eq(UploadStatus::Failed))
+        .execute(conn)
+        .context("failed to mark upload as failed")?;
+    Ok(())
+}
+
+pub fn rendition<T>(conn: &T, upload: i32, variant: Variant) -> Result<Option<UploadRendition>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    upload_renditions::table
+        .filter(upload_renditions::upload.eq(upload))
+        .filter(upload_renditions::variant.eq(variant))
+        .first(conn)
+        .optional()
+        .context("failed to get upload rendition")
+}
+
+// 没有被任何地点, 回忆, 评论, playing, eating 或头像引用
+fn unreferenced<QS>() -> Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>> {
+    Box::new(sql::<Bool>(
+        "NOT EXISTS (SELECT 1 FROM location_upload_rels WHERE upload_id = uploads.id)
+        AND NOT EXISTS (SELECT 1 FROM memory_upload_rels WHERE upload = uploads.id)
+        AND NOT EXISTS (SELECT 1 FROM comment_upload_rels WHERE upload = uploads.id)
+        AND NOT EXISTS (SELECT 1 FROM playings_uploads WHERE upload_id = uploads.id)
+        AND NOT EXISTS (SELECT 1 FROM eatings_uploads WHERE upload_id = uploads.id)
+        AND NOT EXISTS (SELECT 1 FROM users WHERE avatar = uploads.id)",
+    ))
+}
+
+// 上传超过 grace 仍没有被引用的上传, 按 id 分页; 时间由数据库计算, 与 create_on 使用同一时区
+pub fn orphans<T>(conn: &T, grace: Duration, after: i32, limit: i64) -> Result<Vec<Upload>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    uploads::table
+        .filter(uploads::create_on.lt(now - PgInterval::from_microseconds(grace.num_microseconds().unwrap_or(i64::MAX))))
+        .filter(uploads::id.gt(after))
+        .filter(unreferenced())
+        .order(uploads::id)
+        .limit(limit)
+        .load(conn)
+        .context("failed to query orphan uploads")
+}
+
+pub fn renditions_of<T>(conn: &T, ids: &Vec<i32>) -> Result<Vec<UploadRendition>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    upload_renditions::table
+        .filter(upload_renditions::upload.eq_any(ids))
+        .load(conn)
+        .context("failed to query upload renditions")
+}
+
+// 删除时再次检查引用, 期间被使用的上传不会被删除, 尺寸记录级联删除
+// 返回实际删除的 id, 以及不再被任何上传引用, 需要从存储中删除的原始文件和各个尺寸
+pub fn delete_orphans<T>(conn: &T, ids: &Vec<i32>) -> Result<(Vec<i32>, Vec<String>), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    conn.transaction::<_, Error, _>(|| {
+        let renditions: Vec<(i32, String)> = upload_renditions::table
+            .filter(upload_renditions::upload.eq_any(ids))
+            .select((upload_renditions::upload, upload_renditions::fetch_code))
+            .load(conn)?;
+        let deleted: Vec<(i32, String, Option<String>)> = delete(uploads::table.filter(uploads::id.eq_any(ids)).filter(unreferenced()))
+            .returning((uploads::id, uploads::fetch_code, uploads::hash))
+            .get_results(conn)?;
+        let mut released = Vec::new();
+        for (_, fetch_code, hash) in &deleted {
+            match hash {
+                Some(hash) => released.extend(release_blob(conn, hash)?),
+                // 记录摘要之前的上传独占存储对象
+                None => released.push(fetch_code.clone()),
+            }
+        }
+        // 尺寸由内容相同的上传共享, 释放摘要之后再检查, 此时共享中的上传已经提交
+        let codes: Vec<String> = renditions
+            .into_iter()
+            .filter(|(upload, _)| deleted.iter().any(|(id, _, _)| id == upload))
+            .map(|(_, code)| code)
+            .unique()
+            .collect();
+        let mut in_use: Vec<String> = upload_renditions::table
+            .filter(upload_renditions::fetch_code.eq_any(&codes))
+            .select(upload_renditions::fetch_code)
+            .load(conn)?;
+        in_use.extend(uploads::table.filter(uploads::fetch_code.eq_any(&codes)).select(uploads::fetch_code).load::<String>(conn)?);
+        released.extend(codes.into_iter().filter(|code| !in_use.contains(code)));
+        Ok((deleted.into_iter().map(|(id, _, _)| id).collect(), released))
+    })
+    .context("failed to delete orphan uploads")
+}
+
+// 引用计数减到 0 时删除记录并返回存储对象; 同时有相同内容上传时计数会先被加回去, 不会删除
+fn release_blob<T>(conn: &T, hash: &str) -> Result<Option<String>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    update(blobs::table.filter(blobs::hash.eq(hash))).set(blobs::refs.eq(blobs::refs - 1)).execute(conn)?;
+    delete(blobs::table.filter(blobs::hash.eq(hash)).filter(blobs::refs.eq(0)))
+        .returning(blobs::storage_key)
+        .get_result(conn)
+        .optional()
+        .context("failed to release blob")
+}
+
+// 各个摘要当前的引用计数
+pub fn blob_refs<T>(conn: &T, hashes: &Vec<String>) -> Result<Vec<(String, i32)>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    blobs::table
+        .filter(blobs::hash.eq_any(hashes))
+        .select((blobs::hash, blobs::refs))
+        .load(conn)
+        .context("failed to query blob references")
+}
+
+// 同一用户检查配额之后写入上传或会话的操作通过事务级的咨询锁串行执行, 第一个参数区分锁的用途
+pub fn lock_quota<T>(conn: &T, owner: i32) -> Result<(), Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    sql_query("SELECT pg_advisory_xact_lock(1, $1)")
+        .bind::<Integer, _>(owner)
+        .execute(conn)
+        .context("failed to lock upload quota")?;
+    Ok(())
+}
+
+// 已有的上传和进行中的会话声明的大小; SUM(BIGINT) 的结果是 NUMERIC, 转回 BIGINT
+pub fn used_storage<T>(conn: &T, owner: i32) -> Result<i64, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let uploaded: i64 = uploads::table
+        .filter(uploads::owner.eq(owner))
+        .select(sql::<BigInt>("COALESCE(SUM(size), 0)::BIGINT"))
+        .first(conn)
+        .context("failed to get used storage")?;
+    let reserved: i64 = upload_sessions::table
+        .filter(upload_sessions::owner.eq(owner))
+        .filter(upload_sessions::upload.is_null())
+        .select(sql::<BigInt>("COALESCE(SUM(size), 0)::BIGINT"))
+        .first(conn)
+        .context("failed to get used storage")?;
+    Ok(uploaded + reserved)
+}
+
+pub fn open_sessions<T>(conn: &T, owner: i32) -> Result<i64, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    upload_sessions::table
+        .filter(upload_sessions::owner.eq(owner))
+        .filter(upload_sessions::upload.is_null())
+        .count()
+        .get_result(conn)
+        .context("failed to count upload sessions")
+}
+
+pub fn create_session<T>(conn: &T, owner: i32, size: i64) -> Result<UploadSession, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    insert_into(upload_sessions::table)
+        .values((upload_sessions::owner.eq(owner), upload_sessions::size.eq(size)))
+        .get_result(conn)
+        .context("failed to create upload session")
+}
+
+pub fn get_session<T>(conn: &T, id: i32) -> Result<UploadSession, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    upload_sessions::table.find(id).get_result(conn).context("failed to get upload session")
+}
+
+// 只有追加的位置正好是已经收到的字节数时才记录, 并发或重复的追加返回 false
+pub fn append_part<T>(conn: &T, session: i32, position: i64, size: i64, fetch_code: &str) -> Result<bool, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    conn.transaction::<bool, Error, _>(|| {
+        let effected = update(
+            upload_sessions::table
+                .find(session)
+                .filter(upload_sessions::received.eq(position))
+                .filter(upload_sessions::upload.is_null()),
+        )
+        .set(upload_sessions::received.eq(upload_sessions::received + size))
+        .execute(conn)?;
+        if effected == 0 {
+            return Ok(false);
+        }
+        insert_into(upload_session_parts::table)
+            .values((
+                upload_session_parts::session.eq(session),
+                upload_session_parts::position.eq(position),
+                upload_session_parts::size.eq(size),
+                upload_session_parts::fetch_code.eq(fetch_code),
+            ))
+            .execute(conn)?;
+        Ok(true)
+    })
+    .context("failed to append to upload session")
+}
+
+// 按位置排列的分段和大小
+pub fn session_parts<T>(conn: &T, session: i32) -> Result<Vec<(String, i64)>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    upload_session_parts::table
+        .filter(upload_session_parts::session.eq(session))
+        .order(upload_session_parts::position)
+        .select((upload_session_parts::fetch_code, upload_session_parts::size))
+        .load(conn)
+        .context("failed to query upload session parts")
+}
+
+// 记录生成的上传并删除分段记录, 已经被其他请求完成时返回 false
+pub fn finish_session<T>(conn: &T, session: i32, upload: i32) -> Result<bool, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    conn.transaction::<bool, Error, _>(|| {
+        let effected = update(upload_sessions::table.find(session).filter(upload_sessions::upload.is_null()))
+            .set(upload_sessions::upload.eq(upload))
+            .execute(conn)?;
+        if effected == 0 {
+            return Ok(false);
+        }
+        delete(upload_session_parts::table.filter(upload_session_parts::session.eq(session))).execute(conn)?;
+        Ok(true)
+    })
+    .context("failed to finish upload session")
+}
+
+// 超过 grace 没有更新的会话, 包括已经完成的
+pub fn stale_sessions<T>(conn: &T, grace: Duration, limit: i64) -> Result<Vec<UploadSession>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    upload_sessions::table
+        .filter(upload_sessions::update_on.lt(now - PgInterval::from_microseconds(grace.num_microseconds().unwrap_or(i64::MAX))))
+        .order(upload_sessions::id)
+        .limit(limit)
+        .load(conn)
+        .context("failed to query stale upload sessions")
+}
+
+// 分段记录级联删除, 返回需要从存储中删除的分段和大小
+pub fn delete_session<T>(conn: &T, session: i32) -> Result<Vec<(String, i64)>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    conn.transaction::<Vec<(String, i64)>, Error, _>(|| {
+        let parts = upload_session_parts::table
+            .filter(upload_session_parts::session.eq(session))
+            .select((upload_session_parts::fetch_code, upload_session_parts::size))
+            .load(conn)?;
+        delete(upload_sessions::table.find(session)).execute(conn)?;
+        Ok(parts)
+    })
+    .context("failed to delete upload session")
+}
@@ -1,8 +1,9 @@
-use crate::models::{Location, User, UserCommand};
+use crate::models::{Comment, Location, User, UserCommand};
 use crate::schema::*;
 use anyhow::{Context, Error};
 use diesel::sql_types::{Array, Integer};
-use diesel::{dsl::sql, pg::Pg, sql_query, Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{dsl::sql, pg::Pg, sql_query, Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use std::collections::HashMap;
 
 pub fn discoverers_of_locations<T>(conn: &T, locations: &Vec<Location>) -> Result<Vec<User>, Error>
 where
@@ -21,9 +22,48 @@ where
     users::table.filter(users::id.eq(location.discoverer)).first(conn).context("failed to get discoverer of location")
 }
 
+pub fn authors_of_comments<T>(conn: &T, comments: &Vec<Comment>) -> Result<Vec<User>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    let users: HashMap<i32, User> = users::table
+        .filter(users::id.eq_any(comments.iter().map(|c| c.user).collect::<Vec<i32>>()))
+        .load::<User>(conn)
+        .context("failed to query authors of comments")?
+        .into_iter()
+        .map(|u| (u.id, u))
+        .collect();
+    comments
+        .iter()
+        .map(|c| users.get(&c.user).cloned().ok_or(Error::msg("author not exists")))
+        .collect::<Result<Vec<User>, Error>>()
+        .context("failed to query authors of comments")
+}
+
 pub fn update<T>(conn: &T, id: i32, user: UserCommand) -> Result<usize, Error>
 where
     T: Connection<Backend = Pg>,
 {
     diesel::update(users::table.filter(users::id.eq(id))).set(user).execute(conn).context("failed to update user")
 }
+
+pub fn get<T>(conn: &T, id: i32) -> Result<User, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    users::table.find(id).get_result(conn).context("failed to get user")
+}
+
+pub fn get_by_phone<T>(conn: &T, phone: &str) -> Result<Option<User>, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    users::table.filter(users::phone.eq(phone)).get_result(conn).optional().context("failed to get user by phone")
+}
+
+pub fn is_admin<T>(conn: &T, id: i32) -> Result<bool, Error>
+where
+    T: Connection<Backend = Pg>,
+{
+    users::table.select(users::is_admin).filter(users::id.eq(id)).get_result(conn).context("failed to check admin")
+}
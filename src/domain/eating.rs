@@ -68,6 +68,14 @@ pub fn create_eating<P>(persister: P, eating: Insertion, images: Vec<i32>) -> Re
 where
     P: EatingPersister + UploadPersister,
 {
+    let uploads = persister.query_upload_by_ids(&images)?;
+    if uploads.len() != images.len() {
+        return Err(Error::msg("cannot find all images").context("failed to create eating"));
+    }
+    // 与 playing 一样只能使用自己的图片
+    if uploads.into_iter().any(|u| u.owner != eating.discoverer) {
+        return Err(Error::msg("no permission to use other's image").context("failed to create eating"));
+    }
     let eating_id = persister.insert_eating(eating)?;
     persister.insert_eating_uploads(upload::EatingUploadsInsertion {
         eating_id: eating_id,
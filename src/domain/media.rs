@@ -0,0 +1,141 @@
+use super::rendition::{self, Rendition};
+use super::upload::Rejection;
+use crate::models::Variant;
+use anyhow::{Context, Error};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+pub struct Video {
+    pub width: u32,
+    pub height: u32,
+    // 单位为秒
+    pub duration: f64,
+    // 去除元数据之后的视频的类型, 视频本身写入调用方指定的文件
+    pub mime: &'static str,
+    // 封面图, 任意能够解码的图片格式
+    pub poster: Vec<u8>,
+}
+
+// 视频的解析和转换依赖外部程序, 测试时使用假的实现; 视频可能很大, 都通过文件读写
+pub trait MediaProcessor: Send + Sync {
+    // 只读取时长, 接受上传之前检查
+    fn duration(&self, input: &Path) -> Result<f64, Error>;
+    fn process(&self, input: &Path, output: &Path, mime: &str) -> Result<Video, Error>;
+}
+
+// 离开作用域时删除
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new() -> Self {
+        Self(std::env::temp_dir().join(Uuid::new_v4().to_string()))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// 不能解析的文件不是有效的视频
+pub fn check_duration<P>(processor: &P, input: &Path, mime: &str, max_duration: f64) -> Result<(), Error>
+where
+    P: MediaProcessor + ?Sized,
+{
+    let duration = match processor.duration(input) {
+        Ok(duration) => duration,
+        Err(e) => {
+            log::warn!("failed to probe video: {:?}", e);
+            return Err(Rejection::Unreadable(mime.to_owned()).into());
+        }
+    };
+    if duration > max_duration {
+        return Err(Rejection::TooLong(max_duration).into());
+    }
+    Ok(())
+}
+
+// 去除元数据之后的视频写入 output 作为 original, 其余尺寸是封面图; 返回视频本身的尺寸和时长
+// 上传时已经检查过时长, 限制可能在之后被调小, 这里再检查一次
+pub fn render_video<P>(processor: &P, input: &Path, output: &Path, mime: &str, max_duration: f64) -> Result<(Video, Vec<Rendition>), Error>
+where
+    P: MediaProcessor + ?Sized,
+{
+    let video = processor.process(input, output, mime).context("failed to process video")?;
+    if video.duration > max_duration {
+        return Err(Rejection::TooLong(max_duration).into());
+    }
+    let renditions: Vec<Rendition> = rendition::render(&video.poster)
+        .context("failed to render poster")?
+        .into_iter()
+        .filter(|r| r.variant != Variant::Original)
+        .collect();
+    Ok((video, renditions))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    // 原样复制视频, 封面是纯黑的图片
+    pub(crate) struct FakeProcessor {
+        pub(crate) duration: f64,
+    }
+
+    impl MediaProcessor for FakeProcessor {
+        fn duration(&self, input: &Path) -> Result<f64, Error> {
+            std::fs::metadata(input)?;
+            Ok(self.duration)
+        }
+
+        fn process(&self, input: &Path, output: &Path, _: &str) -> Result<Video, Error> {
+            std::fs::copy(input, output)?;
+            let mut poster = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(1920, 1080, Rgb([0, 0, 0]))).write_to(&mut poster, ImageOutputFormat::Jpeg(90))?;
+            Ok(Video {
+                width: 1920,
+                height: 1080,
+                duration: self.duration,
+                mime: "video/mp4",
+                poster: poster.into_inner(),
+            })
+        }
+    }
+
+    fn input() -> TempFile {
+        let input = TempFile::new();
+        std::fs::write(&input.0, b"video").unwrap();
+        input
+    }
+
+    #[test]
+    fn test_render_video() {
+        let (input, output) = (input(), TempFile::new());
+        let (video, renditions) = render_video(&FakeProcessor { duration: 12.5 }, &input.0, &output.0, "video/mp4", 60.0).unwrap();
+        assert_eq!(video.duration, 12.5);
+        assert_eq!((video.mime, video.width, video.height), ("video/mp4", 1920, 1080));
+        let sizes: Vec<(Variant, &str, u32, u32)> = renditions.iter().map(|r| (r.variant, r.mime, r.width, r.height)).collect();
+        assert_eq!(sizes, vec![(Variant::Thumbnail, "image/jpeg", 320, 180), (Variant::Medium, "image/jpeg", 1280, 720)]);
+        assert_eq!(std::fs::read(&output.0).unwrap(), b"video");
+    }
+
+    #[test]
+    fn test_render_video_too_long() {
+        let (input, output) = (input(), TempFile::new());
+        let err = render_video(&FakeProcessor { duration: 61.0 }, &input.0, &output.0, "video/mp4", 60.0).err().unwrap();
+        assert_eq!(err.downcast::<Rejection>().unwrap(), Rejection::TooLong(60.0));
+    }
+
+    #[test]
+    fn test_check_duration() {
+        let input = input();
+        assert!(check_duration(&FakeProcessor { duration: 60.0 }, &input.0, "video/mp4", 60.0).is_ok());
+        let err = check_duration(&FakeProcessor { duration: 61.0 }, &input.0, "video/mp4", 60.0).unwrap_err();
+        assert_eq!(err.downcast::<Rejection>().unwrap(), Rejection::TooLong(60.0));
+        let err = check_duration(&FakeProcessor { duration: 1.0 }, &TempFile::new().0, "video/mp4", 60.0).unwrap_err();
+        assert_eq!(err.downcast::<Rejection>().unwrap(), Rejection::Unreadable("video/mp4".into()));
+    }
+}
@@ -1,5 +1,8 @@
 pub mod eating;
+pub mod media;
+pub mod notification;
 pub mod outcomes;
 pub mod playing;
+pub mod rendition;
 pub mod upload;
 pub mod user;
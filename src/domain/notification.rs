@@ -0,0 +1,197 @@
+use anyhow::Error;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // 我发现的地点有了新评论
+    Comment,
+    // 我的评论或回复有了新回复
+    Reply,
+    // 我发现的地点有了新的公开回忆
+    Memory,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Comment => "comment",
+            Self::Reply => "reply",
+            Self::Memory => "memory",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        match s {
+            "comment" => Ok(Self::Comment),
+            "reply" => Ok(Self::Reply),
+            "memory" => Ok(Self::Memory),
+            _ => Err(Error::msg(format!("unrecognized notification kind: {}", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub id: i32,
+    pub recipient: i32,
+    pub actor: Option<i32>,
+    pub kind: Kind,
+    pub location: Option<i32>,
+    pub target: Option<i32>,
+    pub content: String,
+    pub is_read: bool,
+    pub create_on: NaiveDateTime,
+}
+
+pub struct Insertion {
+    pub recipient: i32,
+    pub actor: Option<i32>,
+    pub kind: Kind,
+    pub location: Option<i32>,
+    pub target: Option<i32>,
+    pub content: String,
+}
+
+pub trait NotificationPersister {
+    fn insert_notification(&self, ins: Insertion) -> Result<Notification, Error>;
+}
+
+// 站内通知持久化之后再投递, 以后接入 APNs/FCM 时实现该 trait 即可
+pub trait NotificationSink: Send + Sync {
+    fn deliver(&self, notification: &Notification) -> Result<(), Error>;
+}
+
+// 自己的操作不通知自己; 只负责持久化, 在调用方的事务中执行, 事务提交之后再调用 deliver 投递
+pub fn notify<P>(persister: &P, ins: Insertion) -> Result<Option<Notification>, Error>
+where
+    P: NotificationPersister + ?Sized,
+{
+    if ins.actor == Some(ins.recipient) {
+        return Ok(None);
+    }
+    Ok(Some(persister.insert_notification(ins)?))
+}
+
+// 投递失败不影响通知本身, 用户仍然可以在通知列表中看到
+pub fn deliver<'a, S, I>(sink: &S, notifications: I)
+where
+    S: NotificationSink + ?Sized,
+    I: IntoIterator<Item = &'a Notification>,
+{
+    for notification in notifications {
+        if let Err(e) = sink.deliver(notification) {
+            log::warn!("failed to deliver notification {}: {:?}", notification.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakePersister {
+        inserted: RefCell<Vec<i32>>,
+    }
+
+    impl NotificationPersister for FakePersister {
+        fn insert_notification(&self, ins: Insertion) -> Result<Notification, Error> {
+            let id = self.inserted.borrow().len() as i32 + 1;
+            self.inserted.borrow_mut().push(ins.recipient);
+            Ok(Notification {
+                id: id,
+                recipient: ins.recipient,
+                actor: ins.actor,
+                kind: ins.kind,
+                location: ins.location,
+                target: ins.target,
+                content: ins.content,
+                is_read: false,
+                create_on: chrono::NaiveDate::from_ymd(2022, 7, 29).and_hms(0, 0, 0),
+            })
+        }
+    }
+
+    // 内存中的投递, 记录收到的通知
+    #[derive(Default)]
+    struct MemorySink {
+        delivered: Mutex<Vec<Notification>>,
+    }
+
+    impl NotificationSink for MemorySink {
+        fn deliver(&self, notification: &Notification) -> Result<(), Error> {
+            self.delivered.lock().unwrap().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    struct FailingSink;
+
+    impl NotificationSink for FailingSink {
+        fn deliver(&self, _: &Notification) -> Result<(), Error> {
+            Err(Error::msg("push service unavailable"))
+        }
+    }
+
+    fn comment_of(actor: i32, recipient: i32) -> Insertion {
+        Insertion {
+            recipient: recipient,
+            actor: Some(actor),
+            kind: Kind::Comment,
+            location: Some(1),
+            target: Some(1),
+            content: "".into(),
+        }
+    }
+
+    #[test]
+    fn test_notify() {
+        let persister = FakePersister::default();
+        let sink = MemorySink::default();
+        let n = notify(&persister, comment_of(1, 2)).unwrap().unwrap();
+        assert_eq!(n.recipient, 2);
+        assert_eq!(*persister.inserted.borrow(), vec![2]);
+        // 持久化时不投递
+        assert!(sink.delivered.lock().unwrap().is_empty());
+        deliver(&sink, &[n]);
+        let delivered = sink.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].kind, Kind::Comment);
+    }
+
+    #[test]
+    fn test_notify_self() {
+        let persister = FakePersister::default();
+        let sink = MemorySink::default();
+        let n = notify(&persister, comment_of(1, 1)).unwrap();
+        assert!(n.is_none());
+        assert!(persister.inserted.borrow().is_empty());
+        deliver(&sink, n.iter());
+        assert!(sink.delivered.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_notify_delivery_failed() {
+        let persister = FakePersister::default();
+        let sink = MemorySink::default();
+        let first = notify(&persister, comment_of(1, 2)).unwrap().unwrap();
+        let second = notify(&persister, comment_of(1, 3)).unwrap().unwrap();
+        assert_eq!(*persister.inserted.borrow(), vec![2, 3]);
+        // 一条投递失败不影响其余的
+        deliver(&FailingSink, &[first.clone()]);
+        deliver(&sink, &[first, second]);
+        assert_eq!(sink.delivered.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_kind() {
+        for kind in [Kind::Comment, Kind::Reply, Kind::Memory] {
+            assert_eq!(Kind::parse(kind.as_str()).unwrap(), kind);
+        }
+        assert!(Kind::parse("unknown").is_err());
+    }
+}
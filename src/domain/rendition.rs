@@ -0,0 +1,144 @@
+use crate::models::Variant;
+use anyhow::{Context, Error};
+use exif::{In, Reader, Tag};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageOutputFormat};
+use std::io::Cursor;
+
+const JPEG_QUALITY: u8 = 85;
+
+pub struct Rendition {
+    pub variant: Variant,
+    pub mime: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+// 解码之后重新编码, EXIF (包括 GPS 位置) 等元数据不会保留
+pub fn render(data: &[u8]) -> Result<Vec<Rendition>, Error> {
+    let img = image::load_from_memory(data).context("failed to decode image")?;
+    let img = orient(img, orientation(data));
+    Variant::ALL.iter().map(|v| encode(resize(&img, *v), *v)).collect()
+}
+
+// 手机拍摄的照片通过 EXIF 记录方向, 去除元数据之前需要先把方向应用到像素上
+fn orientation(data: &[u8]) -> u32 {
+    Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY).and_then(|f| f.value.get_uint(0)))
+        .unwrap_or(1)
+}
+
+fn orient(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// 只缩小不放大
+fn resize(img: &DynamicImage, variant: Variant) -> DynamicImage {
+    match variant.max_dimension() {
+        Some(max) if img.width() > max || img.height() > max => img.resize(max, max, FilterType::CatmullRom),
+        _ => img.clone(),
+    }
+}
+
+// 带透明通道的保存为 PNG, 其余保存为 JPEG
+fn encode(img: DynamicImage, variant: Variant) -> Result<Rendition, Error> {
+    let (width, height) = img.dimensions();
+    let mut data = Cursor::new(Vec::new());
+    let mime = if img.color().has_alpha() {
+        img.write_to(&mut data, ImageOutputFormat::Png).context("failed to encode image")?;
+        "image/png"
+    } else {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+            .write_to(&mut data, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+            .context("failed to encode image")?;
+        "image/jpeg"
+    };
+    Ok(Rendition {
+        variant: variant,
+        mime: mime,
+        width: width,
+        height: height,
+        data: data.into_inner(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([200, 100, 50])))
+            .write_to(&mut data, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        data.into_inner()
+    }
+
+    // 在 SOI 之后插入只包含 Orientation 的 APP1 段
+    fn with_orientation(data: Vec<u8>, orientation: u8) -> Vec<u8> {
+        // 头部和第一个 IFD 的偏移
+        let mut tiff = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        // 一个条目: Orientation, SHORT, 之后没有下一个 IFD
+        tiff.extend_from_slice(&[0x01, 0x00]);
+        tiff.extend_from_slice(&[0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, orientation, 0x00, 0x00, 0x00]);
+        tiff.extend_from_slice(&[0x00; 4]);
+        let mut app1 = vec![0xFF, 0xE1, 0x00, (2 + 6 + tiff.len()) as u8];
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        let mut result = data[..2].to_vec();
+        result.extend(app1);
+        result.extend_from_slice(&data[2..]);
+        result
+    }
+
+    #[test]
+    fn test_render() {
+        let renditions = render(&jpeg(2000, 1000)).unwrap();
+        let sizes: Vec<(Variant, u32, u32)> = renditions.iter().map(|r| (r.variant, r.width, r.height)).collect();
+        assert_eq!(sizes, vec![(Variant::Thumbnail, 320, 160), (Variant::Medium, 1280, 640), (Variant::Original, 2000, 1000)]);
+        assert!(renditions.iter().all(|r| r.mime == "image/jpeg"));
+    }
+
+    #[test]
+    fn test_render_small() {
+        let renditions = render(&jpeg(200, 100)).unwrap();
+        assert!(renditions.iter().all(|r| (r.width, r.height) == (200, 100)));
+    }
+
+    #[test]
+    fn test_render_strips_exif() {
+        let data = with_orientation(jpeg(200, 100), 6);
+        assert_eq!(orientation(&data), 6);
+        for r in render(&data).unwrap() {
+            assert_eq!((r.width, r.height), (100, 200));
+            assert_eq!(orientation(&r.data), 1);
+            assert!(!r.data.windows(4).any(|w| w == b"Exif"));
+        }
+    }
+
+    #[test]
+    fn test_render_alpha() {
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 0])))
+            .write_to(&mut data, ImageOutputFormat::Png)
+            .unwrap();
+        assert!(render(data.get_ref()).unwrap().iter().all(|r| r.mime == "image/png"));
+    }
+
+    #[test]
+    fn test_render_invalid() {
+        assert!(render(b"not an image").is_err());
+    }
+}
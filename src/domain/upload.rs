@@ -1,19 +1,189 @@
 use std::io;
 
 use crate::domain::eating::Eating;
+use crate::domain::media::{check_duration, MediaProcessor, TempFile};
+use crate::models::MediaType;
+use actix_web::rt::task::spawn_blocking;
 use anyhow::{Context, Error};
-use bytes::Bytes;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
 use chrono::NaiveDateTime;
 use futures::{Sink, SinkExt, Stream, StreamExt};
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::{fs, io::AsyncWriteExt};
 
-static READ_BUFFER_SIZE: usize = 1024;
-static WRITE_BUFFER_SIZE: usize = 1024;
+// 判断文件类型需要读取的文件头长度
+const SNIFF_SIZE: usize = 512;
 
+// 未完成的可续传上传占用配额, 限制数量以免一直占用
+const MAX_SESSIONS: i64 = 5;
+
+// 目前只允许上传后台能够处理的图片
+pub const IMAGE_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+// 视频由 ffmpeg 处理, 容器格式 ffmpeg 都能识别
+pub const VIDEO_TYPES: [&str; 3] = ["video/mp4", "video/quicktime", "video/webm"];
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, Error>>>>;
+pub type ByteSink = Pin<Box<dyn Sink<Bytes, Error = Error>>>;
+// 运行时按配置选择的存储
+pub type DynStorer = dyn UploadStorer<ByteStream, ByteSink> + Send + Sync;
+
+#[async_trait(?Send)]
 pub trait UploadStorer<SM: Stream, SK: Sink<Bytes>> {
     fn store(&self) -> Result<(SK, String), Error>;
     fn get(&self, fetch_code: &str) -> Result<SM, Error>;
-    fn mime(&self, fetch_code: &str) -> Result<String, Error>;
+    // 读取 [start, end] 之间的字节, 包含 end
+    fn get_range(&self, fetch_code: &str, start: u64, end: u64) -> Result<SM, Error>;
+    async fn delete(&self, fetch_code: &str) -> Result<(), Error>;
+    // 客户端可以直接下载的临时地址, 不支持的存储返回 None
+    fn presign(&self, _fetch_code: &str, _mime: &str) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+    // 清理进程崩溃时留下的超过 older_than 没有写入的临时文件, 返回清理 (试运行时为将要清理) 的文件数
+    async fn sweep_temp(&self, _older_than: std::time::Duration, _dry_run: bool) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+#[async_trait(?Send)]
+impl<SM, SK, T> UploadStorer<SM, SK> for Arc<T>
+where
+    SM: Stream,
+    SK: Sink<Bytes>,
+    T: UploadStorer<SM, SK> + ?Sized,
+{
+    fn store(&self) -> Result<(SK, String), Error> {
+        (**self).store()
+    }
+
+    fn get(&self, fetch_code: &str) -> Result<SM, Error> {
+        (**self).get(fetch_code)
+    }
+
+    fn get_range(&self, fetch_code: &str, start: u64, end: u64) -> Result<SM, Error> {
+        (**self).get_range(fetch_code, start, end)
+    }
+
+    async fn delete(&self, fetch_code: &str) -> Result<(), Error> {
+        (**self).delete(fetch_code).await
+    }
+
+    fn presign(&self, fetch_code: &str, mime: &str) -> Result<Option<String>, Error> {
+        (**self).presign(fetch_code, mime)
+    }
+
+    async fn sweep_temp(&self, older_than: std::time::Duration, dry_run: bool) -> Result<usize, Error> {
+        (**self).sweep_temp(older_than, dry_run).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub allowed_types: Vec<&'static str>,
+    // 单个文件的最大字节数
+    pub max_size: i64,
+    // 每个用户可以使用的总字节数
+    pub quota: i64,
+    // 为空时不允许上传视频
+    pub video_types: Vec<&'static str>,
+    pub max_video_size: i64,
+    // 视频的最大时长, 单位为秒, 接受上传之前检查
+    pub max_video_duration: f64,
+    // 每个用户同时进行中的可续传上传的数量
+    pub max_sessions: i64,
+    // 用量中已经为这次上传预留的字节数, 完成可续传上传时是会话声明的大小
+    pub reserved: i64,
+}
+
+impl Limits {
+    pub fn new(max_size: i64, quota: i64) -> Self {
+        Self {
+            allowed_types: IMAGE_TYPES.to_vec(),
+            max_size: max_size,
+            quota: quota,
+            video_types: Vec::new(),
+            max_video_size: 0,
+            max_video_duration: 0.0,
+            max_sessions: MAX_SESSIONS,
+            reserved: 0,
+        }
+    }
+
+    pub fn with_video(self, max_size: i64, max_duration: f64) -> Self {
+        Self {
+            video_types: VIDEO_TYPES.to_vec(),
+            max_video_size: max_size,
+            max_video_duration: max_duration,
+            ..self
+        }
+    }
+
+    pub fn with_reserved(&self, reserved: i64) -> Self {
+        Self { reserved: reserved, ..self.clone() }
+    }
+
+    pub fn media_type(&self, mime: &str) -> MediaType {
+        if self.video_types.contains(&mime) {
+            MediaType::Video
+        } else {
+            MediaType::Image
+        }
+    }
+
+    // 可续传的上传在开始时只知道总大小, 类型和对应的大小限制在完成时检查
+    pub fn check_declared_size(&self, used: i64, size: i64) -> Result<(), Rejection> {
+        self.check_size(if self.video_types.is_empty() { MediaType::Image } else { MediaType::Video }, used, size)
+    }
+
+    // 进行中的会话声明的大小已经计入 used
+    pub fn check_session(&self, used: i64, open: i64, size: i64) -> Result<(), Rejection> {
+        if open >= self.max_sessions {
+            return Err(Rejection::TooManySessions(self.max_sessions));
+        }
+        self.check_declared_size(used, size)
+    }
+
+    fn check_type(&self, mime: &str) -> Result<(), Rejection> {
+        if !self.allowed_types.contains(&mime) && !self.video_types.contains(&mime) {
+            return Err(Rejection::UnsupportedType(mime.to_owned()));
+        }
+        Ok(())
+    }
+
+    fn check_size(&self, media_type: MediaType, used: i64, size: i64) -> Result<(), Rejection> {
+        let max_size = match media_type {
+            MediaType::Image => self.max_size,
+            MediaType::Video => self.max_video_size,
+        };
+        if size > max_size {
+            return Err(Rejection::TooLarge(max_size));
+        }
+        if used - self.reserved + size > self.quota {
+            return Err(Rejection::QuotaExceeded(self.quota));
+        }
+        Ok(())
+    }
+}
+
+// 因为文件本身不符合要求而拒绝上传, 需要告知客户端
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum Rejection {
+    #[error("unsupported file type: {}", .0)]
+    UnsupportedType(String),
+    #[error("file is larger than {} bytes", .0)]
+    TooLarge(i64),
+    #[error("storage quota of {} bytes exceeded", .0)]
+    QuotaExceeded(i64),
+    #[error("video is longer than {} seconds", .0)]
+    TooLong(f64),
+    #[error("file is not a readable {}", .0)]
+    Unreadable(String),
+    #[error("more than {} uploads in progress", .0)]
+    TooManySessions(i64),
 }
 
 #[derive(Debug, Serialize)]
@@ -23,11 +193,27 @@ pub struct Upload {
     pub owner: i32,
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
+    pub mime: String,
+    pub size: i64,
+    pub status: String,
+    pub hash: Option<String>,
+    pub media_type: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration: Option<f64>,
 }
 
 pub struct Insertion {
     pub fetch_code: String,
     pub owner: i32,
+    pub mime: String,
+    pub size: i64,
+    // 内容的 SHA-256, 十六进制
+    pub hash: String,
+    pub media_type: MediaType,
+    // 写入时按最新的用量再检查一次配额, 用量中包括预留的 reserved
+    pub quota: i64,
+    pub reserved: i64,
 }
 
 pub struct EatingUploadsInsertion {
@@ -36,14 +222,18 @@ pub struct EatingUploadsInsertion {
 }
 
 pub trait UploadPersister {
-    fn insert_upload(&self, ins: Insertion) -> Result<i32, Error>;
+    // 已经存储过相同内容时引用已有的存储对象, 返回的 fetch_code 可能与插入的不同;
+    // 同一用户并发的上传都通过了开始时的配额检查, 插入时超出配额返回 Rejection::QuotaExceeded
+    fn insert_upload(&self, ins: Insertion) -> Result<Upload, Error>;
+    // 包括进行中的可续传上传声明的大小
+    fn used_storage(&self, owner: i32) -> Result<i64, Error>;
     fn insert_eating_uploads(&self, ins: EatingUploadsInsertion) -> Result<usize, Error>;
-    fn get_upload(&self, id: i32) -> Result<Upload, Error>;
     fn query_upload_by_ids(&self, ids: &Vec<i32>) -> Result<Vec<Upload>, Error>;
     fn query_upload_by_eatings(&self, eatings: &Vec<Eating>) -> Result<Vec<Vec<Upload>>, Error>;
 }
 
-pub async fn upload<S, P, SM, SK, IS>(mut stream: IS, storer: S, persister: P, uid: i32) -> Result<i32, Error>
+// 视频的时长需要 processor 读取, 不符合要求的视频不会进入后台处理
+pub async fn upload<S, P, SM, SK, IS>(mut stream: IS, storer: S, persister: P, processor: Arc<dyn MediaProcessor>, uid: i32, limits: &Limits) -> Result<i32, Error>
 where
     S: UploadStorer<SM, SK>,
     P: UploadPersister,
@@ -51,27 +241,386 @@ where
     SK: Sink<Bytes, Error = Error> + Unpin,
     IS: Stream<Item = Result<Bytes, Error>> + Unpin,
 {
+    let used = persister.used_storage(uid).context("failed to upload")?;
+    // 先读出文件头判断类型, 不允许的类型不会写入存储
+    let mut head = BytesMut::new();
+    let mut exhausted = false;
+    while head.len() < SNIFF_SIZE {
+        match stream.next().await {
+            Some(bs) => head.extend_from_slice(&bs?),
+            None => {
+                exhausted = true;
+                break;
+            }
+        }
+    }
+    let mime = infer::get(&head).map(|t| t.mime_type()).unwrap_or("");
+    limits.check_type(mime)?;
+    let media_type = limits.media_type(mime);
+    limits.check_size(media_type, used, head.len() as i64)?;
     let (mut sink, fetch_code) = storer.store()?;
-    while let Some(bs) = stream.next().await {
-        match bs {
-            Ok(b) => {
-                sink.send(b).await.context("failed to upload")?;
+    // 视频同时写入临时文件, 接受之前检查时长
+    let copy = match media_type {
+        MediaType::Video => Some(TempFile::new()),
+        MediaType::Image => None,
+    };
+    let mut written = write(
+        &mut sink,
+        head.freeze(),
+        if exhausted { None } else { Some(&mut stream) },
+        copy.as_ref().map(|c| c.0.as_path()),
+        limits,
+        media_type,
+        used,
+    )
+    .await;
+    if let (Ok(_), Some(copy)) = (&written, copy) {
+        let (mime, max_duration) = (mime.to_owned(), limits.max_video_duration);
+        written = match spawn_blocking(move || check_duration(&*processor, &copy.0, &mime, max_duration)).await {
+            Ok(checked) => checked.and(written),
+            Err(e) => Err(e.into()),
+        };
+    }
+    let result = written.and_then(|(size, hash)| {
+        persister
+            .insert_upload(Insertion {
+                fetch_code: fetch_code.clone(),
+                owner: uid,
+                mime: mime.to_owned(),
+                size: size,
+                hash: hash,
+                media_type: media_type,
+                quota: limits.quota,
+                reserved: limits.reserved,
+            })
+            .context("failed to update")
+    });
+    match result {
+        // 内容重复时刚写入的文件不再需要
+        Ok(upload) if upload.fetch_code != fetch_code => {
+            if let Err(e) = storer.delete(&fetch_code).await {
+                log::warn!("failed to delete duplicated upload {}: {:?}", fetch_code, e);
             }
-            Err(e) => return Err(Error::from(e)),
+            Ok(upload.id)
+        }
+        Ok(upload) => Ok(upload.id),
+        // 被拒绝或者中途出错的文件需要清理掉
+        Err(e) => {
+            if let Err(e) = storer.delete(&fetch_code).await {
+                log::warn!("failed to delete rejected upload {}: {:?}", fetch_code, e);
+            }
+            Err(e)
         }
     }
-    persister.insert_upload(Insertion { fetch_code: fetch_code, owner: uid }).context("failed to update")
 }
 
-pub async fn get<S, SM, P, SK>(id: i32, persister: P, storer: S) -> Result<(SM, String), Error>
+// 边写入边计算摘要, 指定了 copy 时同时写入该文件; 返回大小和 SHA-256
+async fn write<SK, IS>(sink: &mut SK, head: Bytes, stream: Option<&mut IS>, copy: Option<&Path>, limits: &Limits, media_type: MediaType, used: i64) -> Result<(i64, String), Error>
 where
-    SM: Stream,
-    P: UploadPersister,
-    S: UploadStorer<SM, SK>,
-    SK: Sink<Bytes, Error = Error>,
+    SK: Sink<Bytes, Error = Error> + Unpin,
+    IS: Stream<Item = Result<Bytes, Error>> + Unpin,
 {
-    let u = persister.get_upload(id).context("failed to get uploaded file")?;
-    let mime = storer.mime(&u.fetch_code).context("failed to get uploaded file")?;
-    let sm = storer.get(&u.fetch_code).context("failed to get uploaded file")?;
-    Ok((sm, mime))
+    let mut copy = match copy {
+        Some(path) => Some(fs::File::create(path).await.context("failed to create temporary file")?),
+        None => None,
+    };
+    let mut size = head.len() as i64;
+    let mut hasher = Sha256::new();
+    hasher.update(&head);
+    if let Some(file) = copy.as_mut() {
+        file.write_all(&head).await.context("failed to upload")?;
+    }
+    sink.send(head).await.context("failed to upload")?;
+    if let Some(stream) = stream {
+        while let Some(bs) = stream.next().await {
+            let b = bs?;
+            size += b.len() as i64;
+            limits.check_size(media_type, used, size)?;
+            hasher.update(&b);
+            if let Some(file) = copy.as_mut() {
+                file.write_all(&b).await.context("failed to upload")?;
+            }
+            sink.send(b).await.context("failed to upload")?;
+        }
+    }
+    if let Some(file) = copy.as_mut() {
+        file.flush().await.context("failed to upload")?;
+    }
+    sink.close().await.context("failed to upload")?;
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::domain::media::test::FakeProcessor;
+    use futures::stream;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context as TaskContext, Poll};
+
+    type Files = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    pub(crate) struct MemoryFile {
+        code: String,
+        files: Files,
+    }
+
+    impl Sink<Bytes> for MemoryFile {
+        type Error = Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+            self.files.lock().unwrap().get_mut(&self.code).unwrap().extend_from_slice(&item);
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[derive(Default, Clone)]
+    pub(crate) struct MemoryStore {
+        pub(crate) files: Files,
+    }
+
+    #[async_trait(?Send)]
+    impl UploadStorer<stream::Empty<Result<Bytes, Error>>, MemoryFile> for MemoryStore {
+        fn store(&self) -> Result<(MemoryFile, String), Error> {
+            let code = format!("{}", self.files.lock().unwrap().len() + 1);
+            self.files.lock().unwrap().insert(code.clone(), Vec::new());
+            Ok((
+                MemoryFile {
+                    code: code.clone(),
+                    files: self.files.clone(),
+                },
+                code,
+            ))
+        }
+
+        fn get(&self, _: &str) -> Result<stream::Empty<Result<Bytes, Error>>, Error> {
+            Ok(stream::empty())
+        }
+
+        fn get_range(&self, _: &str, _: u64, _: u64) -> Result<stream::Empty<Result<Bytes, Error>>, Error> {
+            Ok(stream::empty())
+        }
+
+        async fn delete(&self, fetch_code: &str) -> Result<(), Error> {
+            self.files.lock().unwrap().remove(fetch_code).context("file not found")?;
+            Ok(())
+        }
+    }
+
+    #[derive(Default, Clone)]
+    struct FakePersister {
+        used: i64,
+        inserted: Rc<RefCell<Vec<(String, i64)>>>,
+        // 摘要到 fetch code
+        blobs: Rc<RefCell<HashMap<String, String>>>,
+    }
+
+    impl UploadPersister for FakePersister {
+        fn insert_upload(&self, ins: Insertion) -> Result<Upload, Error> {
+            let inserted: i64 = self.inserted.borrow().iter().map(|(_, size)| size).sum();
+            if self.used - ins.reserved + inserted + ins.size > ins.quota {
+                return Err(Rejection::QuotaExceeded(ins.quota).into());
+            }
+            self.inserted.borrow_mut().push((ins.mime.clone(), ins.size));
+            let fetch_code = self.blobs.borrow_mut().entry(ins.hash.clone()).or_insert(ins.fetch_code).clone();
+            let now = chrono::NaiveDate::from_ymd(2022, 8, 4).and_hms(0, 0, 0);
+            Ok(Upload {
+                id: self.inserted.borrow().len() as i32,
+                fetch_code: fetch_code,
+                owner: ins.owner,
+                create_on: now,
+                update_on: now,
+                mime: ins.mime,
+                size: ins.size,
+                status: "pending".into(),
+                hash: Some(ins.hash),
+                media_type: ins.media_type.as_str().into(),
+                width: None,
+                height: None,
+                duration: None,
+            })
+        }
+
+        fn used_storage(&self, _: i32) -> Result<i64, Error> {
+            Ok(self.used)
+        }
+
+        fn insert_eating_uploads(&self, _: EatingUploadsInsertion) -> Result<usize, Error> {
+            Err(Error::msg("not supported by fake persister"))
+        }
+
+        fn query_upload_by_ids(&self, _: &Vec<i32>) -> Result<Vec<Upload>, Error> {
+            Err(Error::msg("not supported by fake persister"))
+        }
+
+        fn query_upload_by_eatings(&self, _: &Vec<Eating>) -> Result<Vec<Vec<Upload>>, Error> {
+            Err(Error::msg("not supported by fake persister"))
+        }
+    }
+
+    // 以 PNG 文件头开始, 总长度为 size 的数据
+    fn png_data(size: usize) -> Vec<u8> {
+        let mut data = vec![0u8; size];
+        data[..8].copy_from_slice(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        data
+    }
+
+    // 每块 100 字节
+    fn png(size: usize) -> stream::Iter<std::vec::IntoIter<Result<Bytes, Error>>> {
+        let chunks: Vec<Result<Bytes, Error>> = png_data(size).chunks(100).map(|c| Ok(Bytes::copy_from_slice(c))).collect();
+        stream::iter(chunks)
+    }
+
+    fn processor() -> Arc<dyn MediaProcessor> {
+        Arc::new(FakeProcessor { duration: 12.5 })
+    }
+
+    fn rejection(e: Error) -> Rejection {
+        e.downcast::<Rejection>().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_upload() {
+        let store = MemoryStore::default();
+        let persister = FakePersister::default();
+        let id = upload(png(1000), store.clone(), persister.clone(), processor(), 1, &Limits::new(1000, 2000)).await.unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(*persister.inserted.borrow(), vec![("image/png".to_owned(), 1000)]);
+        assert_eq!(store.files.lock().unwrap().get("1").unwrap().len(), 1000);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_dedup() {
+        let store = MemoryStore::default();
+        let persister = FakePersister::default();
+        let limits = Limits::new(1000, 5000);
+        assert_eq!(upload(png(1000), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap(), 1);
+        assert_eq!(upload(png(1000), store.clone(), persister.clone(), processor(), 2, &limits).await.unwrap(), 2);
+        // 相同的内容只保留第一次存储的文件
+        assert_eq!(store.files.lock().unwrap().keys().collect::<Vec<&String>>(), vec!["1"]);
+        let hashes: Vec<String> = persister.blobs.borrow().keys().cloned().collect();
+        assert_eq!(hashes, vec![hex::encode(Sha256::digest(&png_data(1000)))]);
+        upload(png(900), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap();
+        assert_eq!(store.files.lock().unwrap().len(), 2);
+    }
+
+    fn mp4(size: usize) -> stream::Iter<std::vec::IntoIter<Result<Bytes, Error>>> {
+        let mut data = vec![0u8; size];
+        data[..12].copy_from_slice(b"\x00\x00\x00\x18ftypisom");
+        stream::iter(vec![Ok(Bytes::from(data))])
+    }
+
+    #[actix_web::test]
+    async fn test_upload_video() {
+        let store = MemoryStore::default();
+        let persister = FakePersister::default();
+        let err = upload(mp4(1000), store.clone(), persister.clone(), processor(), 1, &Limits::new(1000, 5000)).await.unwrap_err();
+        assert_eq!(rejection(err), Rejection::UnsupportedType("video/mp4".into()));
+        // 视频使用单独的大小限制
+        let limits = Limits::new(1000, 5000).with_video(2000, 60.0);
+        upload(mp4(1500), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap();
+        assert_eq!(*persister.inserted.borrow(), vec![("video/mp4".to_owned(), 1500)]);
+        let err = upload(mp4(2001), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap_err();
+        assert_eq!(rejection(err), Rejection::TooLarge(2000));
+        let err = upload(png(1500), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap_err();
+        assert_eq!(rejection(err), Rejection::TooLarge(1000));
+        // 超过时长的视频在入库之前被拒绝, 已经写入的文件被删除
+        let err = upload(mp4(1200), store.clone(), persister.clone(), Arc::new(FakeProcessor { duration: 61.0 }), 1, &limits)
+            .await
+            .unwrap_err();
+        assert_eq!(rejection(err), Rejection::TooLong(60.0));
+        assert_eq!(persister.inserted.borrow().len(), 1);
+        assert_eq!(store.files.lock().unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_unsupported_type() {
+        let store = MemoryStore::default();
+        let persister = FakePersister::default();
+        let text = stream::iter(vec![Ok(Bytes::from_static(b"hello world"))]);
+        let err = upload(text, store.clone(), persister.clone(), processor(), 1, &Limits::new(1000, 2000)).await.unwrap_err();
+        assert_eq!(rejection(err), Rejection::UnsupportedType("".into()));
+        assert!(store.files.lock().unwrap().is_empty());
+        assert!(persister.inserted.borrow().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_upload_too_large() {
+        let store = MemoryStore::default();
+        let persister = FakePersister::default();
+        let err = upload(png(1001), store.clone(), persister.clone(), processor(), 1, &Limits::new(1000, 2000)).await.unwrap_err();
+        assert_eq!(rejection(err), Rejection::TooLarge(1000));
+        assert!(store.files.lock().unwrap().is_empty());
+        assert!(persister.inserted.borrow().is_empty());
+    }
+
+    // 开始时读取的用量已经过期, 插入时按最新的用量拒绝
+    #[actix_web::test]
+    async fn test_upload_concurrent_quota() {
+        let store = MemoryStore::default();
+        let persister = FakePersister::default();
+        let limits = Limits::new(1000, 1500);
+        upload(png(1000), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap();
+        let err = upload(png(900), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<Rejection>(), Some(&Rejection::QuotaExceeded(1500)));
+        assert_eq!(store.files.lock().unwrap().len(), 1);
+        assert_eq!(persister.inserted.borrow().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_quota_exceeded() {
+        let store = MemoryStore::default();
+        let persister = FakePersister { used: 1500, ..Default::default() };
+        let err = upload(png(600), store.clone(), persister.clone(), processor(), 1, &Limits::new(1000, 2000)).await.unwrap_err();
+        assert_eq!(rejection(err), Rejection::QuotaExceeded(2000));
+        assert!(store.files.lock().unwrap().is_empty());
+        assert!(persister.inserted.borrow().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_upload_reserved() {
+        // 用量中包括会话预留的 600 字节, 完成该会话时不重复计算
+        let store = MemoryStore::default();
+        let persister = FakePersister { used: 1500, ..Default::default() };
+        let limits = Limits::new(1000, 2000).with_reserved(600);
+        upload(png(600), store.clone(), persister.clone(), processor(), 1, &limits).await.unwrap();
+        assert_eq!(persister.inserted.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_check_declared_size() {
+        let limits = Limits::new(1000, 2000);
+        assert_eq!(limits.check_declared_size(0, 1000), Ok(()));
+        assert_eq!(limits.check_declared_size(0, 1001), Err(Rejection::TooLarge(1000)));
+        assert_eq!(limits.check_declared_size(1000, 1000), Ok(()));
+        assert_eq!(limits.check_declared_size(1001, 1000), Err(Rejection::QuotaExceeded(2000)));
+        // 允许视频时按视频的大小限制
+        let limits = limits.with_video(1500, 60.0);
+        assert_eq!(limits.check_declared_size(0, 1500), Ok(()));
+        assert_eq!(limits.check_declared_size(0, 1501), Err(Rejection::TooLarge(1500)));
+    }
+
+    #[test]
+    fn test_check_session() {
+        let limits = Limits::new(1000, 2000);
+        assert_eq!(limits.check_session(0, MAX_SESSIONS - 1, 1000), Ok(()));
+        assert_eq!(limits.check_session(0, MAX_SESSIONS, 1000), Err(Rejection::TooManySessions(MAX_SESSIONS)));
+        assert_eq!(limits.check_session(1500, 1, 600), Err(Rejection::QuotaExceeded(2000)));
+    }
 }
@@ -30,6 +30,7 @@ pub struct User {
     pub create_on: chrono::NaiveDateTime,
     pub update_on: chrono::NaiveDateTime,
     pub avatar: Option<i32>,
+    pub is_admin: bool,
 }
 
 pub trait UserPersister {
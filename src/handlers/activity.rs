@@ -0,0 +1,50 @@
+use super::{models::User, PgPool};
+use crate::dao::activity;
+use crate::error::Error;
+use crate::models::{Activity, Location};
+use crate::response::CursorResponse;
+use crate::token::UID;
+use actix_web::{
+    web::{get, Data, Json, Query},
+    Scope,
+};
+use anyhow::Context;
+use serde::Deserialize;
+
+pub(crate) fn register(scope: Scope) -> Scope {
+    scope.route("", get().to(feed))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Feed {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    radius: Option<f64>,
+    user: Option<i32>,
+    #[serde(default)]
+    followed: bool,
+    before: Option<i32>,
+    limit: i64,
+}
+
+pub async fn feed(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<Feed>) -> Result<Json<CursorResponse<(Activity, User, Location)>>, Error> {
+    let conn = pool.get().context("failed to get activities")?;
+    let area = match (q.latitude, q.longitude) {
+        (Some(latitude), Some(longitude)) => Some((latitude, longitude, q.radius.unwrap_or(10000.0).min(10i32.pow(5) as f64))),
+        (None, None) => None,
+        _ => return Err(Error::BusinessError("latitude and longitude must be provided together".into())),
+    };
+    let limit = if q.limit > 40 { 40 } else { q.limit };
+    let list = activity::query(
+        &conn,
+        activity::Query {
+            area: area,
+            actor: q.user,
+            followed_by: if q.followed { Some(uid) } else { None },
+            before: q.before,
+            limit: limit,
+        },
+    )?;
+    let next = if list.len() as i64 == limit { list.last().map(|(a, _, _)| a.id) } else { None };
+    Ok(Json(CursorResponse::new(list.into_iter().map(|(a, l, u)| (a, User::from(u), l)).collect(), next)))
+}
@@ -0,0 +1,67 @@
+use super::PgPool;
+use crate::dao::{category, rank_aggregation, user};
+use crate::domain::upload::DynStorer;
+use crate::error::Error;
+use crate::token::UID;
+use crate::worker::gc::{GarbageCollector, Report};
+use actix_web::{
+    web::{post, put, Data, Json, Path, Query},
+    Scope,
+};
+use anyhow::Context;
+use diesel::{Connection, PgConnection};
+use serde::Deserialize;
+
+pub(crate) fn register(scope: Scope) -> Scope {
+    scope
+        .route("/rank_aggregations/recompute", post().to(recompute_rank_aggregations))
+        .route("/uploads/gc", post().to(collect_uploads))
+        .route("/categories/{id}", put().to(save_category))
+}
+
+pub(crate) fn ensure_admin(conn: &PgConnection, uid: i32) -> Result<(), Error> {
+    if !user::is_admin(conn, uid)? {
+        return Err(Error::PermissionError);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Recompute {
+    location: Option<i32>,
+}
+
+pub async fn recompute_rank_aggregations(pool: Data<PgPool>, UID(uid): UID, Query(Recompute { location }): Query<Recompute>) -> Result<Json<i64>, Error> {
+    let conn = pool.get().context("failed to recompute rank aggregations")?;
+    ensure_admin(&conn, uid)?;
+    Ok(Json(rank_aggregation::recompute(&conn, location)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Collect {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// 手动触发一次清理, 试运行时只报告将要删除的上传
+pub async fn collect_uploads(pool: Data<PgPool>, storer: Data<DynStorer>, gc: Data<GarbageCollector>, UID(uid): UID, Query(Collect { dry_run }): Query<Collect>) -> Result<Json<Report>, Error> {
+    let conn = pool.get().context("failed to collect orphan uploads")?;
+    ensure_admin(&conn, uid)?;
+    drop(conn);
+    Ok(Json(gc.collect(pool.get_ref(), storer.get_ref(), dry_run).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaveCategory {
+    name: String,
+}
+
+// 分类的编号与名称由客户端的地点分类决定, 由管理员录入, 搜索建议只返回录入过的分类
+pub async fn save_category(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>, Json(SaveCategory { name }): Json<SaveCategory>) -> Result<Json<usize>, Error> {
+    if id.0 <= 0 || name.trim().is_empty() {
+        return Err(Error::BusinessError("invalid category".into()));
+    }
+    let conn = pool.get().context("failed to save category")?;
+    ensure_admin(&conn, uid)?;
+    Ok(Json(conn.transaction::<usize, anyhow::Error, _>(|| category::save(&conn, id.0, name.trim()))?))
+}
@@ -1,15 +1,16 @@
-use crate::dao::{comment, rank_aggregation};
+use crate::dao::{activity, comment, location, user};
+use crate::domain::notification::{deliver, notify, Insertion, Kind, Notification, NotificationSink};
 use crate::error::Error;
-use crate::handlers::PgPool;
-use crate::models::{Comment, CommentInsert, CommentUpdate, RankAggregationUpdate};
+use crate::handlers::{models::User, notification::excerpt, upload::check_owner, PgPool};
+use crate::models::{ActivityInsert, ActivityKind, Comment, CommentInsert, CommentReply, CommentReplyInsert, CommentUpdate, Location, Upload};
 use crate::response::ListResponse;
 use crate::token::UID;
 use actix_web::{
     http::StatusCode,
-    web::{get, post, put, scope, Data, Json, Path, Query},
+    web::{delete, get, post, put, Data, Json, Path, Query},
     HttpResponse, Scope,
 };
-use diesel::Connection;
+use diesel::{Connection, PgConnection};
 use serde::Deserialize;
 use std::default::Default;
 
@@ -19,92 +20,328 @@ pub(crate) fn register(scope: Scope) -> Scope {
         .route("/{loc}/comments", post().to(create))
         .route("/{loc}/comments", put().to(upsert))
         .route("/{loc}/comment", get().to(may_get))
+        .route("/{loc}/comments/{id}/replies", get().to(replies))
+        .route("/{loc}/comments/{id}/replies", post().to(reply))
+        .route("/{loc}/comments/{id}/replies/{reply}", delete().to(delete_reply))
+        .route("/{loc}/comments/{id}/helpful", put().to(vote))
+        .route("/{loc}/comments/{id}/helpful", delete().to(unvote))
+}
+
+pub(crate) fn register_my(scope: Scope) -> Scope {
+    scope.route("/comments", get().to(my_comments))
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Create {
     content: String,
     rank: i32,
+    cleanliness: Option<i32>,
+    space: Option<i32>,
+    staff: Option<i32>,
+    accessibility: Option<i32>,
+    noise: Option<i32>,
+    #[serde(default)]
+    images: Vec<i32>,
 }
 
 pub async fn may_get(pool: Data<PgPool>, UID(uid): UID, loc: Path<(i32,)>) -> Result<Json<Option<Comment>>, Error> {
     Ok(Json(comment::may_get(&pool.get()?, uid, loc.0)?))
 }
 
-pub async fn create(pool: Data<PgPool>, UID(uid): UID, loc: Path<(i32,)>, Json(body): Json<Create>) -> Result<HttpResponse, Error> {
-    match comment::insert(
-        &pool.get()?,
-        CommentInsert {
-            rank: body.rank,
-            content: body.content,
-            user: uid,
-            location: loc.0,
-        },
-    ) {
-        Ok(id) => return Ok(HttpResponse::build(StatusCode::OK).json(id)),
-        Err(e) => match e {
-            diesel::result::Error::DatabaseError(kind, _) => match kind {
-                diesel::result::DatabaseErrorKind::UniqueViolation => {
-                    return Ok(HttpResponse::build(StatusCode::CONFLICT).finish());
-                }
-                _ => return Err(Error::from(e)),
+fn validate_rank(rank: i32, dimensions: [Option<i32>; 5]) -> Result<(), Error> {
+    if !(1..=5).contains(&rank) {
+        return Err(Error::BusinessError("rank must be between 1 and 5".into()));
+    }
+    if dimensions.iter().flatten().any(|r| !(1..=5).contains(r)) {
+        return Err(Error::BusinessError("dimension rank must be between 1 and 5".into()));
+    }
+    Ok(())
+}
+
+pub async fn create(pool: Data<PgPool>, sink: Data<dyn NotificationSink>, UID(uid): UID, loc: Path<(i32,)>, Json(body): Json<Create>) -> Result<HttpResponse, Error> {
+    validate_rank(body.rank, [body.cleanliness, body.space, body.staff, body.accessibility, body.noise])?;
+    let summary = excerpt(&body.content);
+    let conn = pool.get()?;
+    check_owner(&conn, uid, &body.images)?;
+    let res = conn.transaction::<(i32, Option<Notification>), anyhow::Error, _>(|| {
+        let id = comment::insert(
+            &conn,
+            CommentInsert {
+                rank: body.rank,
+                content: body.content,
+                user: uid,
+                location: loc.0,
+                cleanliness: body.cleanliness,
+                space: body.space,
+                staff: body.staff,
+                accessibility: body.accessibility,
+                noise: body.noise,
             },
+        )?;
+        comment::add_images(&conn, id, body.images)?;
+        let notified = comment_created(&conn, uid, loc.0, id, summary)?;
+        Ok((id, notified))
+    });
+    match res {
+        Ok((id, notified)) => {
+            deliver(sink.get_ref(), notified.iter());
+            return Ok(HttpResponse::build(StatusCode::OK).json(id));
+        }
+        Err(e) => match e.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+                return Ok(HttpResponse::build(StatusCode::CONFLICT).finish());
+            }
             _ => return Err(Error::from(e)),
         },
     }
 }
 
-pub async fn upsert(pool: Data<PgPool>, UID(uid): UID, loc: Path<(i32,)>, Json(body): Json<CommentUpdate>) -> Result<Json<usize>, Error> {
+// 新评论产生动态, 并通知地点的发现者; 返回的通知需要在事务提交之后投递
+fn comment_created(conn: &PgConnection, uid: i32, loc: i32, id: i32, summary: String) -> Result<Option<Notification>, anyhow::Error> {
+    activity::insert(
+        conn,
+        ActivityInsert {
+            actor: uid,
+            kind: ActivityKind::Comment,
+            location: loc,
+            target: id,
+        },
+    )?;
+    notify(
+        conn,
+        Insertion {
+            recipient: location::get_discoverer(conn, loc)?,
+            actor: Some(uid),
+            kind: Kind::Comment,
+            location: Some(loc),
+            target: Some(id),
+            content: summary,
+        },
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Upsert {
+    content: String,
+    rank: i32,
+    cleanliness: Option<i32>,
+    space: Option<i32>,
+    staff: Option<i32>,
+    accessibility: Option<i32>,
+    noise: Option<i32>,
+    #[serde(default)]
+    images: Vec<i32>,
+}
+
+// 评分聚合由数据库触发器维护, 这里只需要写评论
+pub async fn upsert(pool: Data<PgPool>, sink: Data<dyn NotificationSink>, UID(uid): UID, loc: Path<(i32,)>, Json(body): Json<Upsert>) -> Result<Json<usize>, Error> {
+    validate_rank(body.rank, [body.cleanliness, body.space, body.staff, body.accessibility, body.noise])?;
+    let summary = excerpt(&body.content);
     let conn = pool.get()?;
-    let res = conn.transaction::<usize, Error, _>(|| {
-        let agg = rank_aggregation::get_for_update(&conn, loc.0)?;
-        let mut ori_rank = 0;
-        let new_rank = body.rank;
-        let mut res = 0usize;
-        let mut added_count = 0;
+    check_owner(&conn, uid, &body.images)?;
+    let (res, notified) = conn.transaction::<(usize, Option<Notification>), Error, _>(|| {
         if let Some(cmt) = comment::may_get_for_update(&conn, uid, loc.0)? {
-            res = comment::update(&conn, uid, loc.0, body)?;
-            ori_rank = cmt.rank;
-        } else {
-            comment::insert(
+            let effected = comment::update(
                 &conn,
-                CommentInsert {
+                uid,
+                loc.0,
+                CommentUpdate {
                     rank: body.rank,
                     content: body.content,
-                    user: uid,
-                    location: loc.0,
+                    cleanliness: body.cleanliness,
+                    space: body.space,
+                    staff: body.staff,
+                    accessibility: body.accessibility,
+                    noise: body.noise,
                 },
             )?;
-            added_count = 1;
+            comment::clear_images(&conn, cmt.id)?;
+            comment::add_images(&conn, cmt.id, body.images)?;
+            return Ok((effected, None));
         }
-        rank_aggregation::update(
+        let id = comment::insert(
             &conn,
-            loc.0,
-            RankAggregationUpdate {
-                total: agg.total + new_rank as i64 - ori_rank as i64,
-                count: agg.count + added_count,
+            CommentInsert {
+                rank: body.rank,
+                content: body.content,
+                user: uid,
+                location: loc.0,
+                cleanliness: body.cleanliness,
+                space: body.space,
+                staff: body.staff,
+                accessibility: body.accessibility,
+                noise: body.noise,
             },
         )?;
-        Ok(res)
+        comment::add_images(&conn, id, body.images)?;
+        Ok((0, comment_created(&conn, uid, loc.0, id, summary)?))
     })?;
+    deliver(sink.get_ref(), notified.iter());
     Ok(Json(res))
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CommentsOfLocation {
+    keyword: Option<String>,
+    rank_gt: Option<i32>,
+    rank_lt: Option<i32>,
+    #[serde(default)]
+    order_by: comment::OrderBy,
     limit: i64,
     offset: i64,
 }
 
-pub(crate) async fn comments_of_location(pool: Data<PgPool>, loc: Path<(i32,)>, Query(CommentsOfLocation { limit, offset }): Query<CommentsOfLocation>) -> Result<Json<ListResponse<Comment>>, Error> {
+pub(crate) async fn comments_of_location(pool: Data<PgPool>, loc: Path<(i32,)>, Query(q): Query<CommentsOfLocation>) -> Result<Json<ListResponse<(Comment, User, Vec<Upload>)>>, Error> {
+    let conn = pool.get()?;
     let (list, total) = comment::query(
-        &pool.get()?,
+        &conn,
         comment::Query {
-            limit: limit,
-            offset: offset,
+            limit: q.limit,
+            offset: q.offset,
             location: Some(loc.0),
+            keyword: q.keyword,
+            rank_gt: q.rank_gt,
+            rank_lt: q.rank_lt,
+            order_by: q.order_by,
             ..Default::default()
         },
     )?;
+    let authors = user::authors_of_comments(&conn, &list)?;
+    let images = comment::query_images(&conn, &list)?;
+    let list = list.into_iter().zip(authors.into_iter().map(User::from)).zip(images).map(|((c, u), ims)| (c, u, ims)).collect();
+    Ok(Json(ListResponse::new(list, total)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MyComments {
+    #[serde(default)]
+    order_by: comment::OrderBy,
+    limit: i64,
+    offset: i64,
+}
+
+pub async fn my_comments(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<MyComments>) -> Result<Json<ListResponse<(Comment, Location, Vec<Upload>)>>, Error> {
+    let conn = pool.get()?;
+    let (list, total) = comment::query(
+        &conn,
+        comment::Query {
+            limit: q.limit,
+            offset: q.offset,
+            user: Some(uid),
+            order_by: q.order_by,
+            ..Default::default()
+        },
+    )?;
+    let locs = location::locations_of_comments(&conn, &list)?;
+    let images = comment::query_images(&conn, &list)?;
+    let list = list.into_iter().zip(locs).zip(images).map(|((c, l), ims)| (c, l, ims)).collect();
+    Ok(Json(ListResponse::new(list, total)))
+}
+
+// 评论必须属于路径中的地点
+fn comment_of_location(conn: &PgConnection, loc: i32, id: i32) -> Result<Comment, Error> {
+    let cmt = comment::get(conn, id)?;
+    if cmt.location != loc {
+        return Err(Error::BusinessError("comment not belongs to location".into()));
+    }
+    Ok(cmt)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Replies {
+    limit: i64,
+    offset: i64,
+}
+
+pub async fn replies(pool: Data<PgPool>, path: Path<(i32, i32)>, Query(Replies { limit, offset }): Query<Replies>) -> Result<Json<ListResponse<CommentReply>>, Error> {
+    let (loc, id) = path.into_inner();
+    let conn = pool.get()?;
+    comment_of_location(&conn, loc, id)?;
+    let (list, total) = comment::replies_of_comment(&conn, id, limit, offset)?;
     Ok(Json(ListResponse::new(list, total)))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct Reply {
+    content: String,
+    parent: Option<i32>,
+}
+
+// 地点的发现者回复时标记为官方回复; 通知评论的作者, 以及被回复的回复的作者
+pub async fn reply(pool: Data<PgPool>, sink: Data<dyn NotificationSink>, UID(uid): UID, path: Path<(i32, i32)>, Json(body): Json<Reply>) -> Result<Json<i32>, Error> {
+    let (loc, id) = path.into_inner();
+    let conn = pool.get()?;
+    let cmt = comment_of_location(&conn, loc, id)?;
+    let mut recipients = vec![cmt.user];
+    if let Some(parent) = body.parent {
+        let p = comment::get_reply(&conn, parent)?;
+        if p.comment != id {
+            return Err(Error::BusinessError("parent reply not belongs to comment".into()));
+        }
+        if p.user != cmt.user {
+            recipients.push(p.user);
+        }
+    }
+    let is_official = location::get_discoverer(&conn, loc)? == uid;
+    let summary = excerpt(&body.content);
+    let (reply_id, notified) = conn.transaction::<(i32, Vec<Notification>), anyhow::Error, _>(|| {
+        let reply_id = comment::insert_reply(
+            &conn,
+            CommentReplyInsert {
+                comment: id,
+                user: uid,
+                parent: body.parent,
+                content: body.content,
+                is_official: is_official,
+            },
+        )?;
+        let mut notified = Vec::new();
+        for recipient in recipients {
+            notified.extend(notify(
+                &*conn,
+                Insertion {
+                    recipient: recipient,
+                    actor: Some(uid),
+                    kind: Kind::Reply,
+                    location: Some(loc),
+                    target: Some(reply_id),
+                    content: summary.clone(),
+                },
+            )?);
+        }
+        Ok((reply_id, notified))
+    })?;
+    // 全部通知都写入之后才投递, 避免推送了之后回滚的通知
+    deliver(sink.get_ref(), &notified);
+    Ok(Json(reply_id))
+}
+
+pub async fn delete_reply(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32, i32)>) -> Result<Json<usize>, Error> {
+    let (loc, id, reply) = path.into_inner();
+    let conn = pool.get()?;
+    comment_of_location(&conn, loc, id)?;
+    let r = comment::get_reply(&conn, reply)?;
+    if r.comment != id {
+        return Err(Error::BusinessError("reply not belongs to comment".into()));
+    }
+    if r.user != uid {
+        return Err(Error::PermissionError);
+    }
+    Ok(Json(comment::delete_reply(&conn, reply)?))
+}
+
+pub async fn vote(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32)>) -> Result<Json<usize>, Error> {
+    let (loc, id) = path.into_inner();
+    let conn = pool.get()?;
+    if comment_of_location(&conn, loc, id)?.user == uid {
+        return Err(Error::BusinessError("cannot vote for own comment".into()));
+    }
+    Ok(Json(comment::vote(&conn, id, uid)?))
+}
+
+pub async fn unvote(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32)>) -> Result<Json<usize>, Error> {
+    let (loc, id) = path.into_inner();
+    let conn = pool.get()?;
+    comment_of_location(&conn, loc, id)?;
+    Ok(Json(comment::unvote(&conn, id, uid)?))
+}
@@ -0,0 +1,217 @@
+use super::{models::User, PgPool};
+use crate::dao::{family, memory, user};
+use crate::error::Error;
+use crate::models::{Child, ChildCommand, Family, FamilyInvitation, Location, Memory, MemoryOrderBy, MemoryQuery, Upload};
+use crate::response::ListResponse;
+use crate::token::UID;
+use actix_web::{
+    web::{delete, get, post, put, Data, Json, Path, Query},
+    Scope,
+};
+use anyhow::Context;
+use chrono::NaiveDate;
+use diesel::{Connection, PgConnection};
+use itertools::izip;
+use serde::{Deserialize, Serialize};
+
+pub(crate) fn register(scope: Scope) -> Scope {
+    scope
+        .route("", get().to(my_family))
+        .route("", post().to(create))
+        .route("/members/{user}", delete().to(remove_member))
+        .route("/invitations", get().to(my_invitations))
+        .route("/invitations", post().to(invite))
+        .route("/invitations/{id}/accept", put().to(accept))
+        .route("/invitations/{id}/decline", put().to(decline))
+        .route("/children", get().to(children))
+        .route("/children", post().to(create_child))
+        .route("/children/{id}", put().to(update_child))
+        .route("/children/{id}", delete().to(delete_child))
+        .route("/children/{id}/memories", get().to(child_timeline))
+}
+
+fn my_family_or_err(conn: &PgConnection, uid: i32) -> Result<Family, Error> {
+    family::family_of_user(conn, uid)?.ok_or(Error::BusinessError("not in a family".into()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct FamilyDetail {
+    family: Family,
+    members: Vec<User>,
+}
+
+pub async fn my_family(pool: Data<PgPool>, UID(uid): UID) -> Result<Json<Option<FamilyDetail>>, Error> {
+    let conn = pool.get().context("failed to get family")?;
+    match family::family_of_user(&conn, uid)? {
+        Some(f) => {
+            let members = family::members(&conn, f.id)?.into_iter().map(User::from).collect();
+            Ok(Json(Some(FamilyDetail { family: f, members: members })))
+        }
+        None => Ok(Json(None)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Create {
+    name: String,
+}
+
+pub async fn create(pool: Data<PgPool>, UID(uid): UID, Json(body): Json<Create>) -> Result<Json<i32>, Error> {
+    let conn = pool.get().context("failed to create family")?;
+    if family::family_of_user(&conn, uid)?.is_some() {
+        return Err(Error::BusinessError("already in a family".into()));
+    }
+    let id = conn.transaction::<i32, anyhow::Error, _>(|| family::insert(&conn, body.name, uid))?;
+    Ok(Json(id))
+}
+
+// 成员可以自行退出, 创建者可以移除其他成员, 创建者退出则解散家庭
+pub async fn remove_member(pool: Data<PgPool>, UID(uid): UID, user: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to remove family member")?;
+    let f = my_family_or_err(&conn, uid)?;
+    if user.0 != uid && f.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    if user.0 == f.owner {
+        return Ok(Json(family::delete_family(&conn, f.id)?));
+    }
+    Ok(Json(family::remove_member(&conn, f.id, user.0)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Invite {
+    phone: String,
+}
+
+// 家庭中的任一成员都可以邀请; 手机号未注册, 对方已在家庭中或已有待处理的邀请时同样返回成功, 避免通过邀请探测手机号
+pub async fn invite(pool: Data<PgPool>, UID(uid): UID, Json(body): Json<Invite>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to invite family member")?;
+    let f = my_family_or_err(&conn, uid)?;
+    if let Some(invitee) = user::get_by_phone(&conn, &body.phone)? {
+        if family::family_of_user(&conn, invitee.id)?.is_none() {
+            family::invite(&conn, f.id, uid, invitee.id)?;
+        }
+    }
+    Ok(Json(1))
+}
+
+pub async fn my_invitations(pool: Data<PgPool>, UID(uid): UID) -> Result<Json<Vec<(FamilyInvitation, Family)>>, Error> {
+    Ok(Json(family::pending_invitations(&pool.get()?, uid)?))
+}
+
+fn pending_invitation_of(conn: &PgConnection, uid: i32, id: i32) -> Result<FamilyInvitation, Error> {
+    let inv = family::get_invitation(conn, id)?;
+    if inv.invitee != uid {
+        return Err(Error::PermissionError);
+    }
+    if inv.status != "pending" {
+        return Err(Error::BusinessError("invitation is already handled".into()));
+    }
+    Ok(inv)
+}
+
+pub async fn accept(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to accept family invitation")?;
+    let inv = pending_invitation_of(&conn, uid, id.0)?;
+    if family::family_of_user(&conn, uid)?.is_some() {
+        return Err(Error::BusinessError("already in a family".into()));
+    }
+    let effected = conn.transaction::<usize, anyhow::Error, _>(|| {
+        family::set_invitation_status(&conn, inv.id, "accepted")?;
+        family::add_member(&conn, inv.family, uid)
+    })?;
+    Ok(Json(effected))
+}
+
+pub async fn decline(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to decline family invitation")?;
+    let inv = pending_invitation_of(&conn, uid, id.0)?;
+    Ok(Json(family::set_invitation_status(&conn, inv.id, "declined")?))
+}
+
+pub async fn children(pool: Data<PgPool>, UID(uid): UID) -> Result<Json<Vec<Child>>, Error> {
+    let conn = pool.get().context("failed to get children")?;
+    let f = my_family_or_err(&conn, uid)?;
+    Ok(Json(family::children_of_family(&conn, f.id)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChildBody {
+    name: String,
+    birth_date: NaiveDate,
+}
+
+pub async fn create_child(pool: Data<PgPool>, UID(uid): UID, Json(body): Json<ChildBody>) -> Result<Json<i32>, Error> {
+    let conn = pool.get().context("failed to create child")?;
+    let f = my_family_or_err(&conn, uid)?;
+    let id = family::insert_child(
+        &conn,
+        ChildCommand {
+            family: f.id,
+            name: body.name,
+            birth_date: body.birth_date,
+        },
+    )?;
+    Ok(Json(id))
+}
+
+// 孩子必须属于当前用户所在的家庭
+fn child_of_my_family(conn: &PgConnection, uid: i32, id: i32) -> Result<Child, Error> {
+    let f = my_family_or_err(conn, uid)?;
+    let child = family::get_child(conn, id)?;
+    if child.family != f.id {
+        return Err(Error::PermissionError);
+    }
+    Ok(child)
+}
+
+pub async fn update_child(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>, Json(body): Json<ChildBody>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to update child")?;
+    let child = child_of_my_family(&conn, uid, id.0)?;
+    let effected = family::update_child(
+        &conn,
+        child.id,
+        ChildCommand {
+            family: child.family,
+            name: body.name,
+            birth_date: body.birth_date,
+        },
+    )?;
+    Ok(Json(effected))
+}
+
+pub async fn delete_child(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to delete child")?;
+    let child = child_of_my_family(&conn, uid, id.0)?;
+    Ok(Json(family::delete_child(&conn, child.id)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChildTimeline {
+    latitude: f64,
+    longitude: f64,
+    limit: i64,
+    offset: i64,
+}
+
+// 按时间倒序列出标记了孩子的回忆, 附带拍摄时孩子的月龄
+pub async fn child_timeline(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>, Query(q): Query<ChildTimeline>) -> Result<Json<ListResponse<(Memory, Location, Vec<Upload>, i32)>>, Error> {
+    let conn = pool.get().context("failed to get timeline of child")?;
+    let child = child_of_my_family(&conn, uid, id.0)?;
+    let (mems, locs, _, total) = memory::find(
+        &conn,
+        uid,
+        MemoryQuery {
+            latitude: q.latitude,
+            longitude: q.longitude,
+            child: Some(child.id),
+            limit: q.limit,
+            offset: q.offset,
+            order_by: MemoryOrderBy::CreateOnDesc,
+            ..Default::default()
+        },
+    )?;
+    let imgs = memory::query_images(&conn, &mems)?;
+    let ages: Vec<i32> = mems.iter().map(|m| child.age_in_months(m.create_on.date())).collect();
+    Ok(Json(ListResponse::new(izip!(mems, locs, imgs, ages).collect(), total)))
+}
@@ -0,0 +1,182 @@
+use super::location::{enrich, EnrichedLocation};
+use super::PgPool;
+use crate::dao::favorite;
+use crate::error::Error;
+use crate::models::{FavoriteList, FavoriteListCommand};
+use crate::response::ListResponse;
+use crate::token::UID;
+use actix_web::{
+    web::{delete, get, post, put, Data, Json, Path, Query},
+    Scope,
+};
+use anyhow::Context;
+use diesel::Connection;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub(crate) fn register(scope: Scope) -> Scope {
+    scope
+        .route("/favorites", get().to(my_favorites))
+        .route("/favorites/{loc}", put().to(add))
+        .route("/favorites/{loc}", delete().to(remove))
+        .route("/favorite_lists", get().to(my_lists))
+        .route("/favorite_lists", post().to(create_list))
+        .route("/favorite_lists/{id}", put().to(update_list))
+        .route("/favorite_lists/{id}", delete().to(delete_list))
+        .route("/favorite_lists/{id}/locations/{loc}", put().to(add_to_list))
+        .route("/favorite_lists/{id}/locations/{loc}", delete().to(remove_from_list))
+}
+
+pub(crate) fn register_shared(scope: Scope) -> Scope {
+    scope.route("/favorite_lists/{share_code}", get().to(shared_list))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MyFavorites {
+    latitude: f64,
+    longitude: f64,
+    list: Option<i32>,
+    limit: i64,
+    offset: i64,
+}
+
+pub async fn my_favorites(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<MyFavorites>) -> Result<Json<ListResponse<EnrichedLocation>>, Error> {
+    let conn = pool.get().context("failed to get favorites")?;
+    if let Some(list) = q.list {
+        if favorite::get_list(&conn, list)?.owner != uid {
+            return Err(Error::PermissionError);
+        }
+    }
+    let ((locs, dists), total) = favorite::locations(
+        &conn,
+        favorite::Query {
+            user: Some(uid),
+            list: q.list,
+            latitude: q.latitude,
+            longitude: q.longitude,
+            limit: if q.limit > 40 { 40 } else { q.limit },
+            offset: q.offset,
+        },
+    )?;
+    Ok(Json(ListResponse::new(enrich(&conn, locs, dists)?, total)))
+}
+
+pub async fn add(pool: Data<PgPool>, UID(uid): UID, loc: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    Ok(Json(favorite::add(&pool.get()?, uid, loc.0)?))
+}
+
+pub async fn remove(pool: Data<PgPool>, UID(uid): UID, loc: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to remove favorite")?;
+    let effected = conn.transaction::<usize, anyhow::Error, _>(|| favorite::remove(&conn, uid, loc.0))?;
+    Ok(Json(effected))
+}
+
+pub async fn my_lists(pool: Data<PgPool>, UID(uid): UID) -> Result<Json<Vec<FavoriteList>>, Error> {
+    Ok(Json(favorite::lists_of_user(&pool.get()?, uid)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListBody {
+    name: String,
+    is_shared: bool,
+}
+
+pub async fn create_list(pool: Data<PgPool>, UID(uid): UID, Json(body): Json<ListBody>) -> Result<Json<i32>, Error> {
+    let id = favorite::insert_list(
+        &pool.get()?,
+        FavoriteListCommand {
+            name: body.name,
+            owner: uid,
+            share_code: if body.is_shared { Some(Uuid::new_v4().to_string()) } else { None },
+        },
+    )?;
+    Ok(Json(id))
+}
+
+pub async fn update_list(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>, Json(body): Json<ListBody>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to update favorite list")?;
+    let list = favorite::get_list(&conn, id.0)?;
+    if list.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    // 保留已有的分享码, 以免已经分享出去的链接失效
+    let share_code = match (body.is_shared, list.share_code) {
+        (true, Some(code)) => Some(code),
+        (true, None) => Some(Uuid::new_v4().to_string()),
+        (false, _) => None,
+    };
+    let effected = favorite::update_list(
+        &conn,
+        id.0,
+        FavoriteListCommand {
+            name: body.name,
+            owner: uid,
+            share_code: share_code,
+        },
+    )?;
+    Ok(Json(effected))
+}
+
+pub async fn delete_list(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    let conn = pool.get().context("failed to delete favorite list")?;
+    if favorite::get_list(&conn, id.0)?.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    Ok(Json(favorite::delete_list(&conn, id.0)?))
+}
+
+pub async fn add_to_list(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32)>) -> Result<Json<usize>, Error> {
+    let (id, loc) = path.into_inner();
+    let conn = pool.get().context("failed to add location to favorite list")?;
+    if favorite::get_list(&conn, id)?.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    let effected = conn.transaction::<usize, anyhow::Error, _>(|| {
+        favorite::add(&conn, uid, loc)?;
+        favorite::add_to_list(&conn, id, loc)
+    })?;
+    Ok(Json(effected))
+}
+
+pub async fn remove_from_list(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32)>) -> Result<Json<usize>, Error> {
+    let (id, loc) = path.into_inner();
+    let conn = pool.get().context("failed to remove location from favorite list")?;
+    if favorite::get_list(&conn, id)?.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    Ok(Json(favorite::remove_from_list(&conn, id, loc)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SharedList {
+    latitude: f64,
+    longitude: f64,
+    limit: i64,
+    offset: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedListResponse {
+    list: FavoriteList,
+    locations: ListResponse<EnrichedLocation>,
+}
+
+pub async fn shared_list(pool: Data<PgPool>, share_code: Path<(String,)>, Query(q): Query<SharedList>) -> Result<Json<SharedListResponse>, Error> {
+    let conn = pool.get().context("failed to get shared favorite list")?;
+    let list = favorite::get_list_by_share_code(&conn, &share_code.0)?;
+    let ((locs, dists), total) = favorite::locations(
+        &conn,
+        favorite::Query {
+            list: Some(list.id),
+            latitude: q.latitude,
+            longitude: q.longitude,
+            limit: if q.limit > 40 { 40 } else { q.limit },
+            offset: q.offset,
+            ..Default::default()
+        },
+    )?;
+    Ok(Json(SharedListResponse {
+        list: list,
+        locations: ListResponse::new(enrich(&conn, locs, dists)?, total),
+    }))
+}
@@ -0,0 +1,67 @@
+use super::{models::User, PgPool};
+use crate::dao::{follow, user};
+use crate::error::Error;
+use crate::response::ListResponse;
+use crate::token::UID;
+use actix_web::{
+    web::{delete, get, put, Data, Json, Path, Query},
+    Scope,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+pub(crate) fn register(scope: Scope) -> Scope {
+    scope
+        .route("/{id}", get().to(profile))
+        .route("/{id}/follow", put().to(follow_user))
+        .route("/{id}/follow", delete().to(unfollow_user))
+        .route("/{id}/followers", get().to(followers))
+        .route("/{id}/following", get().to(following))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    user: User,
+    followers: i64,
+    following: i64,
+    is_following: bool,
+}
+
+pub async fn profile(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<Profile>, Error> {
+    let conn = pool.get().context("failed to get user profile")?;
+    let u = user::get(&conn, id.0)?;
+    let (followers, following) = follow::counts(&conn, u.id)?;
+    Ok(Json(Profile {
+        is_following: follow::is_following(&conn, uid, u.id)?,
+        user: u.into(),
+        followers: followers,
+        following: following,
+    }))
+}
+
+pub async fn follow_user(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    if id.0 == uid {
+        return Err(Error::BusinessError("cannot follow yourself".into()));
+    }
+    Ok(Json(follow::follow(&pool.get()?, uid, id.0)?))
+}
+
+pub async fn unfollow_user(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    Ok(Json(follow::unfollow(&pool.get()?, uid, id.0)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    limit: i64,
+    offset: i64,
+}
+
+pub async fn followers(pool: Data<PgPool>, id: Path<(i32,)>, Query(Page { limit, offset }): Query<Page>) -> Result<Json<ListResponse<User>>, Error> {
+    let (list, total) = follow::followers(&pool.get()?, id.0, if limit > 40 { 40 } else { limit }, offset)?;
+    Ok(Json(ListResponse::new(list.into_iter().map(User::from).collect(), total)))
+}
+
+pub async fn following(pool: Data<PgPool>, id: Path<(i32,)>, Query(Page { limit, offset }): Query<Page>) -> Result<Json<ListResponse<User>>, Error> {
+    let (list, total) = follow::following(&pool.get()?, id.0, if limit > 40 { 40 } else { limit }, offset)?;
+    Ok(Json(ListResponse::new(list.into_iter().map(User::from).collect(), total)))
+}
@@ -1,13 +1,14 @@
 // use super::models::Location;
 use crate::error::Error;
 use crate::geo::h3;
-use crate::handlers::PgPool;
+use crate::handlers::{upload::check_owner, PgPool};
+use crate::realtime::hub::{Event, Hub};
 use crate::response::ListResponse;
 use crate::serde::Deserialize;
 use crate::token::UID;
 use crate::{
-    dao::{equipment, location, rank_aggregation, upload, user},
-    models::{Equipment, Location, LocationInsertion, LocationUpdating, RankAggregation, RankAggregationInsert, Upload, User},
+    dao::{activity, equipment, location, rank_aggregation, upload, user},
+    models::{ActivityInsert, ActivityKind, Equipment, Location, LocationInsertion, LocationUpdating, RankAggregation, RankAggregationInsert, Upload, User},
 };
 
 use actix_web::{
@@ -15,7 +16,7 @@ use actix_web::{
     Scope,
 };
 use anyhow::Context;
-use diesel::Connection;
+use diesel::{Connection, PgConnection};
 use itertools::izip;
 use std::default::Default;
 
@@ -28,33 +29,73 @@ pub fn register(scope: Scope) -> Scope {
         .route("/my", get().to(my_locations))
 }
 
+pub(crate) type EnrichedLocation = (Location, User, Vec<Equipment>, Vec<Upload>, f64, RankAggregation);
+
+pub(crate) fn enrich(conn: &PgConnection, locs: Vec<Location>, dists: Vec<f64>) -> Result<Vec<EnrichedLocation>, Error> {
+    let users = user::discoverers_of_locations(conn, &locs)?;
+    let equips = equipment::equipements_of_locations(conn, &locs)?;
+    let uploads = upload::uploads_of_locations(conn, &locs)?;
+    let rank_agg = rank_aggregation::rank_aggs_of_location(conn, &locs)?;
+    Ok(izip!(locs, users, equips, uploads, dists, rank_agg).collect())
+}
+
 #[derive(Debug, Deserialize)]
 pub struct NearbyRequest {
     latitude: f64,
     longitude: f64,
+    keyword: Option<String>,
+    cleanliness_gte: Option<f64>,
+    space_gte: Option<f64>,
+    staff_gte: Option<f64>,
+    accessibility_gte: Option<f64>,
+    noise_gte: Option<f64>,
+    age_months: Option<i32>,
+    #[serde(default)]
+    followed: bool,
     limit: i64,
     offset: i64,
+    // 未指定时有关键字按相关度排序, 否则按距离排序
+    order_by: Option<location::OrderBy>,
+}
+
+impl NearbyRequest {
+    fn ratings_gte(&self) -> Vec<(location::Dimension, f64)> {
+        [
+            (location::Dimension::Cleanliness, self.cleanliness_gte),
+            (location::Dimension::Space, self.space_gte),
+            (location::Dimension::Staff, self.staff_gte),
+            (location::Dimension::Accessibility, self.accessibility_gte),
+            (location::Dimension::Noise, self.noise_gte),
+        ]
+        .into_iter()
+        .filter_map(|(dim, gte)| gte.map(|v| (dim, v)))
+        .collect()
+    }
 }
 
-pub async fn nearby_locations(pool: Data<PgPool>, Query(params): Query<NearbyRequest>) -> Result<Json<ListResponse<(Location, User, Vec<Equipment>, Vec<Upload>, f64, RankAggregation)>>, Error> {
+pub async fn nearby_locations(pool: Data<PgPool>, UID(uid): UID, Query(params): Query<NearbyRequest>) -> Result<Json<ListResponse<EnrichedLocation>>, Error> {
     let conn = pool.get().context("failed to get nearby locations")?;
+    let order_by = params
+        .order_by
+        .unwrap_or(if params.keyword.is_some() { location::OrderBy::Relevance } else { location::OrderBy::DistanceAsc });
     let ((locs, dists), total) = location::query(
         &conn,
         location::Query {
+            ratings_gte: params.ratings_gte(),
+            age_months: params.age_months,
+            followed_by: if params.followed { Some(uid) } else { None },
+            keyword: params.keyword,
             latitude: params.latitude,
             longitude: params.longitude,
             radius: 10i32.pow(5) as f64,
             limit: if params.limit > 40 { 40 } else { params.limit },
             offset: params.offset,
+            order_by: order_by,
             ..Default::default()
         },
     )
     .context("failed to find nearby locations")?;
-    let users = user::discoverers_of_locations(&conn, &locs)?;
-    let equips = equipment::equipements_of_locations(&conn, &locs)?;
-    let uploads = upload::uploads_of_locations(&conn, &locs)?;
-    let rank_agg = rank_aggregation::rank_aggs_of_location(&conn, &locs)?;
-    Ok(Json(ListResponse::new(izip!(locs, users, equips, uploads, dists, rank_agg).collect(), total)))
+    Ok(Json(ListResponse::new(enrich(&conn, locs, dists)?, total)))
 }
 
 #[derive(Debug, Deserialize)]
@@ -65,10 +106,29 @@ pub struct CreateRequest {
     pub category: i32,
     pub description: String,
     pub images: Vec<i32>,
+    #[serde(default)]
+    pub min_age_months: Option<i32>,
+    #[serde(default)]
+    pub max_age_months: Option<i32>,
 }
 
-pub async fn create_location(pool: Data<PgPool>, uid: UID, Json(body): Json<CreateRequest>) -> Result<Json<i32>, Error> {
+fn validate_age_range(min: Option<i32>, max: Option<i32>) -> Result<(), Error> {
+    if min.iter().chain(max.iter()).any(|m| *m < 0) {
+        return Err(Error::BusinessError("age months must not be negative".into()));
+    }
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            return Err(Error::BusinessError("min age months must not be greater than max age months".into()));
+        }
+    }
+    Ok(())
+}
+
+pub async fn create_location(pool: Data<PgPool>, hub: Data<Hub>, uid: UID, Json(body): Json<CreateRequest>) -> Result<Json<i32>, Error> {
+    validate_age_range(body.min_age_months, body.max_age_months)?;
+    let (name, latitude, longitude) = (body.name.clone(), body.latitude, body.longitude);
     let conn = pool.get().context("failed to create location")?;
+    check_owner(&conn, uid.0, &body.images)?;
     let id = conn.transaction(|| {
         let exists = location::exists(
             &conn,
@@ -92,16 +152,39 @@ pub async fn create_location(pool: Data<PgPool>, uid: UID, Json(body): Json<Crea
                 description: body.description,
                 discoverer: uid.0,
                 geo_index: h3::index(body.latitude, body.longitude, 13).to_string(),
+                min_age_months: body.min_age_months,
+                max_age_months: body.max_age_months,
             },
         )?;
+        activity::insert_photo(&conn, uid.0, id, &Vec::new(), &body.images)?;
         for img_id in body.images {
             upload::insert_location_upload_rel(&conn, upload::LocationUploadRelInsertion { location_id: id, upload_id: img_id })?;
         }
         // create rank aggregation
         rank_aggregation::insert(&conn, RankAggregationInsert { total: 0, count: 0, location_id: id })?;
+        activity::insert(
+            &conn,
+            ActivityInsert {
+                actor: uid.0,
+                kind: ActivityKind::Location,
+                location: id,
+                target: id,
+            },
+        )?;
 
         Ok(id)
     })?;
+    hub.publish_at(
+        latitude,
+        longitude,
+        Event::Location {
+            id: id,
+            name: name,
+            latitude: latitude,
+            longitude: longitude,
+            discoverer: uid.0,
+        },
+    );
     Ok(Json(id))
 }
 
@@ -111,13 +194,14 @@ pub struct DetailParams {
     longitude: f64,
 }
 
-pub async fn detail(pool: Data<PgPool>, id: Path<(i32,)>, Query(DetailParams { latitude, longitude }): Query<DetailParams>) -> Result<Json<(Location, User, Vec<Equipment>, Vec<Upload>, f64)>, Error> {
+pub async fn detail(pool: Data<PgPool>, id: Path<(i32,)>, Query(DetailParams { latitude, longitude }): Query<DetailParams>) -> Result<Json<EnrichedLocation>, Error> {
     let conn = pool.get().context("failed to get location detail")?;
     let (loc, dist) = location::get(&conn, id.0, latitude, longitude)?;
     let user = user::discoverer_of_location(&conn, &loc)?;
     let uploads = upload::uploads_of_location(&conn, &loc)?;
     let equipments = equipment::equipements_of_location(&conn, &loc)?;
-    Ok(Json((loc, user, equipments, uploads, dist)))
+    let rank_agg = rank_aggregation::rank_agg_of_location(&conn, &loc)?;
+    Ok(Json((loc, user, equipments, uploads, dist, rank_agg)))
 }
 
 #[derive(Debug, Deserialize)]
@@ -126,14 +210,20 @@ pub struct UpdateBody {
     description: String,
     category: i32,
     images: Vec<i32>,
+    #[serde(default)]
+    min_age_months: Option<i32>,
+    #[serde(default)]
+    max_age_months: Option<i32>,
 }
 
 pub async fn update(pool: Data<PgPool>, uid: UID, id: Path<(i32,)>, Json(body): Json<UpdateBody>) -> Result<Json<usize>, Error> {
+    validate_age_range(body.min_age_months, body.max_age_months)?;
     let conn = pool.get().context("failed to update location")?;
-    let (loc, user, _) = location::get_without_coord(&conn, id.0)?;
+    let (loc, user, images) = location::get_without_coord(&conn, id.0)?;
     if user.id != uid.0 {
         return Err(Error::PermissionError);
     }
+    check_owner(&conn, uid.0, &body.images)?;
     conn.transaction::<(), anyhow::Error, _>(|| {
         location::update(
             &conn,
@@ -146,8 +236,12 @@ pub async fn update(pool: Data<PgPool>, uid: UID, id: Path<(i32,)>, Json(body):
                 description: body.description,
                 discoverer: user.id,
                 geo_index: loc.geo_index,
+                min_age_months: body.min_age_months,
+                max_age_months: body.max_age_months,
             },
         )?;
+        // 新增了照片才产生动态
+        activity::insert_photo(&conn, uid.0, id.0, &images.iter().map(|u| u.id).collect(), &body.images)?;
         location::clear_images(&conn, id.0)?;
         location::add_images(&conn, id.0, body.images)?;
         Ok(())
@@ -159,6 +253,7 @@ pub async fn update(pool: Data<PgPool>, uid: UID, id: Path<(i32,)>, Json(body):
 pub struct MyLocations {
     latitude: f64,
     longitude: f64,
+    keyword: Option<String>,
     limit: i64,
     offset: i64,
     order_by: location::OrderBy,
@@ -168,6 +263,7 @@ pub(crate) async fn my_locations(pool: Data<PgPool>, UID(uid): UID, Query(q): Qu
     let ((locs, dists), total) = location::query(
         &pool.get()?,
         location::Query {
+            keyword: q.keyword,
             latitude: q.latitude,
             longitude: q.longitude,
             radius: 10i32.pow(5) as f64,
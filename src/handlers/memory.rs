@@ -1,21 +1,41 @@
-use super::{PgPool, QueryResponse};
-use crate::dao::memory::{add_images, find, insert, query_images};
+use super::{notification::excerpt, upload::check_owner, PgPool, QueryResponse};
+use crate::dao::memory::{
+    add_children, add_images, children_of_memory, clear_children, clear_images, delete as delete_memory, find, get as get_memory, images_of_memory, insert, is_visible, query_images,
+    timeline as memory_timeline, update as update_memory, TimelineMonth,
+};
+use crate::dao::{activity, family, location as location_dao};
+use crate::domain::notification::{deliver, notify, Insertion, Kind, Notification, NotificationSink};
 use crate::error::Error;
-use crate::models::{Location, Memory, MemoryCommand, MemoryOrderBy, MemoryQuery, Upload};
+use crate::models::{ActivityInsert, ActivityKind, Child, Location, Memory, MemoryCommand, MemoryOrderBy, MemoryQuery, Upload, Visibility};
+use crate::realtime::hub::{Event, Hub};
 use crate::response::ListResponse;
 use crate::token::UID;
 use actix_web::{
-    web::{get, post, Data, Json, Path, Query},
+    web::{delete, get, post, put, Data, Json, Path, Query},
     Scope,
 };
 use anyhow::Context;
-use diesel::Connection;
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime};
+use diesel::{Connection, PgConnection};
 use itertools::izip;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::default::Default;
 
+pub(crate) fn register_feed(scope: Scope) -> Scope {
+    scope
+        .route("", get().to(near_memories))
+        .route("/timeline", get().to(timeline))
+        .route("/on_this_day", get().to(on_this_day))
+}
+
 pub fn register(scope: Scope) -> Scope {
-    scope.route("/{id}/memories", post().to(create)).route("/{id}/memories", get().to(list)).route("/my", get().to(my))
+    scope
+        .route("/{id}/memories", post().to(create))
+        .route("/{id}/memories", get().to(list))
+        .route("/{id}/memories/{memory}", get().to(detail))
+        .route("/{id}/memories/{memory}", put().to(update))
+        .route("/{id}/memories/{memory}", delete().to(remove))
+        .route("/my", get().to(my))
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,10 +43,39 @@ pub struct CreateBody {
     pub title: String,
     pub content: String,
     pub images: Vec<i32>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub children: Vec<i32>,
+}
+
+// 只能标记自己家庭中的孩子
+fn check_children(conn: &PgConnection, uid: i32, children: &Vec<i32>) -> Result<(), Error> {
+    if children.is_empty() {
+        return Ok(());
+    }
+    let f = family::family_of_user(conn, uid)?.ok_or(Error::BusinessError("not in a family".into()))?;
+    let mut ids = children.clone();
+    ids.sort();
+    ids.dedup();
+    if family::count_children_in_family(conn, f.id, &ids)? != ids.len() as i64 {
+        return Err(Error::PermissionError);
+    }
+    Ok(())
 }
-pub async fn create(pool: Data<PgPool>, uid: UID, location: Path<(i32,)>, Json(body): Json<CreateBody>) -> Result<Json<i32>, Error> {
+
+pub async fn create(pool: Data<PgPool>, sink: Data<dyn NotificationSink>, hub: Data<Hub>, uid: UID, location: Path<(i32,)>, Json(body): Json<CreateBody>) -> Result<Json<i32>, Error> {
     let conn = pool.get().context("failed to create memory")?;
-    let id = conn.transaction::<i32, anyhow::Error, _>(|| {
+    check_children(&conn, uid.0, &body.children)?;
+    check_owner(&conn, uid.0, &body.images)?;
+    let title = body.title.clone();
+    // 推送需要地点坐标, 在事务之前读取, 避免回忆已经创建之后再返回错误
+    let loc = if body.visibility == Visibility::Public {
+        Some(location_dao::get_without_coord(&conn, location.0)?.0)
+    } else {
+        None
+    };
+    let (id, notified) = conn.transaction::<(i32, Option<Notification>), anyhow::Error, _>(|| {
         let id = insert(
             &conn,
             MemoryCommand {
@@ -34,14 +83,163 @@ pub async fn create(pool: Data<PgPool>, uid: UID, location: Path<(i32,)>, Json(b
                 content: body.content,
                 owner: uid.0,
                 location: location.0,
+                visibility: body.visibility,
             },
         )?;
+        if body.visibility == Visibility::Public {
+            activity::insert_photo(&conn, uid.0, location.0, &Vec::new(), &body.images)?;
+        }
         add_images(&conn, id, body.images)?;
-        Ok(id)
+        add_children(&conn, id, body.children)?;
+        // 公开的回忆产生动态, 并通知地点的发现者
+        if body.visibility != Visibility::Public {
+            return Ok((id, None));
+        }
+        activity::insert(
+            &conn,
+            ActivityInsert {
+                actor: uid.0,
+                kind: ActivityKind::Memory,
+                location: location.0,
+                target: id,
+            },
+        )?;
+        let notified = notify(
+            &*conn,
+            Insertion {
+                recipient: location_dao::get_discoverer(&conn, location.0)?,
+                actor: Some(uid.0),
+                kind: Kind::Memory,
+                location: Some(location.0),
+                target: Some(id),
+                content: excerpt(&title),
+            },
+        )?;
+        Ok((id, notified))
     })?;
+    deliver(sink.get_ref(), notified.iter());
+    if let Some(loc) = loc {
+        hub.publish_at(
+            loc.latitude,
+            loc.longitude,
+            Event::Memory {
+                id: id,
+                title: title,
+                location: loc.id,
+                owner: uid.0,
+            },
+        );
+    }
     Ok(Json(id))
 }
 
+// 回忆必须属于路径中的地点
+fn memory_of_location(conn: &PgConnection, loc: i32, id: i32) -> Result<Memory, Error> {
+    let mem = get_memory(conn, id)?;
+    if mem.location != loc {
+        return Err(Error::BusinessError("memory not belongs to location".into()));
+    }
+    Ok(mem)
+}
+
+// 回忆中标记的孩子; 家庭之外的查看者只能看到名字和当时的月龄, 看不到生日
+#[derive(Debug, Serialize)]
+pub struct TaggedChild {
+    name: String,
+    age_months: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    birth_date: Option<NaiveDate>,
+}
+
+impl TaggedChild {
+    fn new(child: Child, at: NaiveDate, in_family: bool) -> Self {
+        Self {
+            age_months: child.age_in_months(at),
+            id: if in_family { Some(child.id) } else { None },
+            birth_date: if in_family { Some(child.birth_date) } else { None },
+            name: child.name,
+        }
+    }
+}
+
+pub async fn detail(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32)>) -> Result<Json<(Memory, Vec<Upload>, Vec<TaggedChild>)>, Error> {
+    let (loc, id) = path.into_inner();
+    let conn = pool.get().context("failed to get memory")?;
+    if !is_visible(&conn, id, uid)? {
+        return Err(Error::PermissionError);
+    }
+    let mem = memory_of_location(&conn, loc, id)?;
+    let my_family = family::family_of_user(&conn, uid)?.map(|f| f.id);
+    let at = mem.create_on.date();
+    let children = children_of_memory(&conn, id)?
+        .into_iter()
+        .map(|c| {
+            let in_family = my_family == Some(c.family);
+            TaggedChild::new(c, at, in_family)
+        })
+        .collect();
+    Ok(Json((mem, images_of_memory(&conn, id)?, children)))
+}
+
+pub async fn update(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32)>, Json(body): Json<CreateBody>) -> Result<Json<usize>, Error> {
+    let (loc, id) = path.into_inner();
+    let conn = pool.get().context("failed to update memory")?;
+    let mem = memory_of_location(&conn, loc, id)?;
+    if mem.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    check_children(&conn, uid, &body.children)?;
+    check_owner(&conn, uid, &body.images)?;
+    let effected = conn.transaction::<usize, anyhow::Error, _>(|| {
+        let effected = update_memory(
+            &conn,
+            id,
+            MemoryCommand {
+                title: body.title,
+                content: body.content,
+                owner: uid,
+                location: loc,
+                visibility: body.visibility,
+            },
+        )?;
+        // 公开的回忆新增了照片时产生照片动态
+        if body.visibility == Visibility::Public {
+            let before = images_of_memory(&conn, id)?.into_iter().map(|u| u.id).collect();
+            activity::insert_photo(&conn, uid, loc, &before, &body.images)?;
+        }
+        clear_images(&conn, id)?;
+        add_images(&conn, id, body.images)?;
+        clear_children(&conn, id)?;
+        add_children(&conn, id, body.children)?;
+        // 由非公开改为公开时才产生动态
+        if mem.visibility != Visibility::Public && body.visibility == Visibility::Public {
+            activity::insert(
+                &conn,
+                ActivityInsert {
+                    actor: uid,
+                    kind: ActivityKind::Memory,
+                    location: loc,
+                    target: id,
+                },
+            )?;
+        }
+        Ok(effected)
+    })?;
+    Ok(Json(effected))
+}
+
+pub async fn remove(pool: Data<PgPool>, UID(uid): UID, path: Path<(i32, i32)>) -> Result<Json<usize>, Error> {
+    let (loc, id) = path.into_inner();
+    let conn = pool.get().context("failed to delete memory")?;
+    if memory_of_location(&conn, loc, id)?.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    let effected = conn.transaction::<usize, anyhow::Error, _>(|| delete_memory(&conn, id))?;
+    Ok(Json(effected))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListParams {
     latitude: f64,
@@ -53,6 +251,7 @@ pub struct ListParams {
 
 pub async fn list(
     pool: Data<PgPool>,
+    UID(uid): UID,
     location: Path<(i32,)>,
     Query(ListParams {
         latitude,
@@ -66,10 +265,15 @@ pub async fn list(
     let (list, total) = conn.transaction::<(Vec<(Memory, Location, Vec<Upload>, f64)>, i64), anyhow::Error, _>(|| {
         let (m, l, d, total) = find(
             &conn,
+            uid,
             MemoryQuery {
-                title: None,
+                keyword: None,
                 owner: None,
                 location: Some(location.0),
+                child: None,
+                family_of: None,
+                anniversary: None,
+                followed_by: None,
                 create_before: None,
                 create_after: None,
                 limit: limit,
@@ -89,23 +293,34 @@ pub async fn list(
 pub struct My {
     limit: i64,
     offset: i64,
-    title: Option<String>,
+    #[serde(alias = "title")]
+    keyword: Option<String>,
     latitude: f64,
     longitude: f64,
     order_by: MemoryOrderBy,
+    create_before: Option<NaiveDateTime>,
+    create_after: Option<NaiveDateTime>,
+    // 同时包含家庭成员的回忆
+    #[serde(default)]
+    include_family: bool,
 }
 
 pub async fn my(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<My>) -> Result<Json<ListResponse<(Memory, Location, f64)>>, Error> {
     let (mems, locs, dists, total) = find(
         &pool.get()?,
+        uid,
         MemoryQuery {
             latitude: q.latitude,
             longitude: q.longitude,
-            title: q.title,
-            owner: Some(uid),
+            keyword: q.keyword,
+            owner: if q.include_family { None } else { Some(uid) },
             location: None,
-            create_before: None,
-            create_after: None,
+            child: None,
+            family_of: if q.include_family { Some(uid) } else { None },
+            anniversary: None,
+            followed_by: None,
+            create_before: q.create_before,
+            create_after: q.create_after,
             limit: q.limit,
             offset: q.offset,
             order_by: q.order_by,
@@ -118,23 +333,44 @@ pub async fn my(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<My>) -> Resul
 pub struct NearMemories {
     latitude: f64,
     longitude: f64,
+    keyword: Option<String>,
+    create_before: Option<NaiveDateTime>,
+    create_after: Option<NaiveDateTime>,
+    #[serde(default)]
+    followed: bool,
     limit: i64,
     offset: i64,
 }
 
 pub async fn near_memories(
     pool: Data<PgPool>,
-    Query(NearMemories { latitude, longitude, limit, offset }): Query<NearMemories>,
+    UID(uid): UID,
+    Query(NearMemories {
+        latitude,
+        longitude,
+        keyword,
+        create_before,
+        create_after,
+        followed,
+        limit,
+        offset,
+    }): Query<NearMemories>,
 ) -> Result<Json<ListResponse<(Memory, Location, Vec<Upload>, f64)>>, Error> {
     let conn = pool.get()?;
+    let order_by = if keyword.is_some() { MemoryOrderBy::Relevance } else { MemoryOrderBy::Distance };
     let (mems, locs, dists, total) = find(
         &conn,
+        uid,
         MemoryQuery {
             latitude: latitude,
             longitude: longitude,
+            keyword: keyword,
             limit: limit,
             offset: offset,
-            order_by: MemoryOrderBy::Distance,
+            order_by: order_by,
+            create_before: create_before,
+            create_after: create_after,
+            followed_by: if followed { Some(uid) } else { None },
             ..Default::default()
         },
     )?;
@@ -142,3 +378,50 @@ pub async fn near_memories(
     let list = izip!(mems, locs, imgs, dists).collect();
     Ok(Json(ListResponse::new(list, total)))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct Timeline {
+    create_before: Option<NaiveDateTime>,
+    create_after: Option<NaiveDateTime>,
+    limit: i64,
+    offset: i64,
+}
+
+// 自己和家庭成员的回忆按月分组, 每页为若干个月
+pub async fn timeline(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<Timeline>) -> Result<Json<ListResponse<TimelineMonth>>, Error> {
+    let (list, total) = memory_timeline(&pool.get()?, uid, q.create_before, q.create_after, q.limit, q.offset)?;
+    Ok(Json(ListResponse::new(list, total)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OnThisDay {
+    latitude: f64,
+    longitude: f64,
+    // 客户端所在时区的日期, 默认为服务器当天
+    date: Option<NaiveDate>,
+    limit: i64,
+    offset: i64,
+}
+
+// 往年今日: 同月同日且早于今天的回忆
+pub async fn on_this_day(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<OnThisDay>) -> Result<Json<ListResponse<(Memory, Location, Vec<Upload>, f64)>>, Error> {
+    let conn = pool.get()?;
+    let date = q.date.unwrap_or_else(|| Local::today().naive_local());
+    let (mems, locs, dists, total) = find(
+        &conn,
+        uid,
+        MemoryQuery {
+            latitude: q.latitude,
+            longitude: q.longitude,
+            family_of: Some(uid),
+            anniversary: Some((date.month(), date.day())),
+            create_before: Some(date.and_hms(0, 0, 0)),
+            limit: q.limit,
+            offset: q.offset,
+            order_by: MemoryOrderBy::CreateOnDesc,
+            ..Default::default()
+        },
+    )?;
+    let imgs = query_images(&conn, &mems)?;
+    Ok(Json(ListResponse::new(izip!(mems, locs, imgs, dists).collect(), total)))
+}
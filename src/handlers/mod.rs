@@ -1,7 +1,15 @@
+pub(crate) mod activity;
+pub(crate) mod admin;
 pub(crate) mod comment;
+pub(crate) mod family;
+pub(crate) mod favorite;
+pub(crate) mod follow;
 pub(crate) mod location;
 pub(crate) mod memory;
 pub(crate) mod models;
+pub(crate) mod notification;
+pub(crate) mod realtime;
+pub(crate) mod search;
 pub(crate) mod upload;
 pub(crate) mod user;
 
@@ -21,6 +29,8 @@ pub static JWT_TOKEN: &str = "JWT_TOKEN";
 pub trait Tokener {
     fn generate(&self, uid: i32) -> Result<String, anyhow::Error>;
     fn validate(&self, token: &str) -> Result<i32, anyhow::Error>;
+    // 同时返回过期时间 (unix 时间戳), 长连接需要在 token 过期时断开
+    fn validate_until(&self, token: &str) -> Result<(i32, i64), anyhow::Error>;
 }
 
 #[derive(Debug, Clone)]
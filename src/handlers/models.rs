@@ -9,6 +9,8 @@ pub struct Upload {
     pub owner: i32,
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
+    pub mime: String,
+    pub size: i64,
 }
 
 impl From<models::Upload> for Upload {
@@ -19,6 +21,8 @@ impl From<models::Upload> for Upload {
             owner: u.owner,
             create_on: u.create_on,
             update_on: u.update_on,
+            mime: u.mime,
+            size: u.size,
         }
     }
 }
@@ -27,11 +31,16 @@ impl From<models::Upload> for Upload {
 pub struct User {
     id: i32,
     name: String,
+    avatar: Option<i32>,
 }
 
 impl From<models::User> for User {
     fn from(u: models::User) -> Self {
-        Self { id: u.id, name: u.name }
+        Self {
+            id: u.id,
+            name: u.name,
+            avatar: u.avatar,
+        }
     }
 }
 
@@ -0,0 +1,49 @@
+use super::PgPool;
+use crate::dao::notification;
+use crate::domain::notification::Notification;
+use crate::error::Error;
+use crate::response::ListResponse;
+use crate::token::UID;
+use actix_web::{
+    web::{get, put, Data, Json, Path, Query},
+    Scope,
+};
+use serde::Deserialize;
+
+pub(crate) fn register(scope: Scope) -> Scope {
+    scope
+        .route("", get().to(list))
+        .route("/unread_count", get().to(unread_count))
+        .route("/read", put().to(read_all))
+        .route("/{id}/read", put().to(read))
+}
+
+// 通知中附带的内容摘要
+pub(crate) fn excerpt(content: &str) -> String {
+    content.chars().take(100).collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct List {
+    #[serde(default)]
+    unread_only: bool,
+    limit: i64,
+    offset: i64,
+}
+
+pub async fn list(pool: Data<PgPool>, UID(uid): UID, Query(q): Query<List>) -> Result<Json<ListResponse<Notification>>, Error> {
+    let (list, total) = notification::query(&pool.get()?, uid, q.unread_only, if q.limit > 40 { 40 } else { q.limit }, q.offset)?;
+    Ok(Json(ListResponse::new(list, total)))
+}
+
+pub async fn unread_count(pool: Data<PgPool>, UID(uid): UID) -> Result<Json<i64>, Error> {
+    Ok(Json(notification::unread_count(&pool.get()?, uid)?))
+}
+
+pub async fn read(pool: Data<PgPool>, UID(uid): UID, id: Path<(i32,)>) -> Result<Json<usize>, Error> {
+    Ok(Json(notification::mark_read(&pool.get()?, uid, Some(id.0))?))
+}
+
+pub async fn read_all(pool: Data<PgPool>, UID(uid): UID) -> Result<Json<usize>, Error> {
+    Ok(Json(notification::mark_read(&pool.get()?, uid, None)?))
+}
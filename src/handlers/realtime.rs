@@ -0,0 +1,104 @@
+use super::{Tokener, JWT_TOKEN};
+use crate::error::Error;
+use crate::geo::h3;
+use crate::realtime::hub::{Hub, Message, CELL_RESOLUTION};
+use crate::realtime::ticket::Tickets;
+use actix_web::{
+    web::{get, post, Data, Json, Query},
+    HttpRequest, HttpResponse, Scope,
+};
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+pub(crate) fn register<T>(scope: Scope) -> Scope
+where
+    T: Tokener + 'static,
+{
+    scope.route("/events", get().to(events::<T>))
+}
+
+// 换取票据需要登录, 注册在需要认证的范围内
+pub(crate) fn register_ticket<T>(scope: Scope) -> Scope
+where
+    T: Tokener + 'static,
+{
+    scope.route("/tickets", post().to(ticket::<T>))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TicketResponse {
+    ticket: String,
+    // 票据的有效期, 单位为秒
+    expires_in: u64,
+}
+
+fn token_of(req: &HttpRequest) -> Result<&str, Error> {
+    req.headers().get(JWT_TOKEN).and_then(|h| h.to_str().ok()).ok_or(Error::PermissionError)
+}
+
+pub async fn ticket<T>(req: HttpRequest, tokener: Data<T>, tickets: Data<Tickets>) -> Result<Json<TicketResponse>, Error>
+where
+    T: Tokener,
+{
+    let (uid, until) = tokener.validate_until(token_of(&req)?).map_err(|_| Error::PermissionError)?;
+    Ok(Json(TicketResponse {
+        ticket: tickets.issue(uid, until),
+        expires_in: TICKET_TTL.as_secs(),
+    }))
+}
+
+pub const TICKET_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+pub struct Events {
+    // EventSource 不能设置请求头, 通过参数传递一次性的票据而不是 token
+    ticket: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    k: Option<i32>,
+}
+
+fn encode(msg: Message) -> Bytes {
+    match msg {
+        Message::Event(envelope) => Bytes::from(format!("event: {}\ndata: {}\n\n", envelope.event.name(), serde_json::to_string(&envelope.event).unwrap_or_default())),
+        Message::Lagged(n) => Bytes::from(format!("event: lagged\ndata: {}\n\n", n)),
+    }
+}
+
+// 推送当前用户的通知, 以及订阅范围内新的地点和公开回忆; token 过期时发送 expired 事件并断开, 客户端需要重新换取票据
+pub async fn events<T>(req: HttpRequest, tokener: Data<T>, tickets: Data<Tickets>, hub: Data<Hub>, Query(q): Query<Events>) -> Result<HttpResponse, Error>
+where
+    T: Tokener,
+{
+    let (uid, until) = match &q.ticket {
+        Some(t) => tickets.redeem(t).ok_or(Error::PermissionError)?,
+        None => tokener.validate_until(token_of(&req)?).map_err(|_| Error::PermissionError)?,
+    };
+    let remaining = Duration::from_secs((until - chrono::Local::now().timestamp()).max(0) as u64);
+    let cells: HashSet<u64> = match (q.latitude, q.longitude) {
+        (Some(latitude), Some(longitude)) => {
+            let k = q.k.unwrap_or(1);
+            if !(0..=3).contains(&k) {
+                return Err(Error::BusinessError("k must be between 0 and 3".into()));
+            }
+            h3::k_ring(h3::index(latitude, longitude, CELL_RESOLUTION), k).into_iter().filter(|c| *c > 0).collect()
+        }
+        (None, None) => HashSet::new(),
+        _ => return Err(Error::BusinessError("latitude and longitude must be provided together".into())),
+    };
+    let subscription = hub.subscribe(uid, cells);
+    let events = stream::unfold(subscription, |mut sub| async move { sub.recv().await.map(|msg| (encode(msg), sub)) });
+    // 定时发送注释行, 防止连接被代理断开
+    let keepalive = stream::unfold(tokio::time::interval(Duration::from_secs(30)), |mut interval| async move {
+        interval.tick().await;
+        Some((Bytes::from_static(b": keepalive\n\n"), interval))
+    });
+    let body = stream::select(events, keepalive)
+        .take_until(tokio::time::sleep(remaining))
+        .chain(stream::once(async { Bytes::from_static(b"event: expired\ndata: \n\n") }))
+        .map(Ok::<_, actix_web::Error>);
+    Ok(HttpResponse::Ok().content_type("text/event-stream").insert_header(("Cache-Control", "no-cache")).streaming(body))
+}
@@ -0,0 +1,50 @@
+use super::PgPool;
+use crate::dao::search;
+use crate::error::Error;
+use actix_web::{
+    web::{get, Data, Json, Query},
+    Scope,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+pub(crate) fn register(scope: Scope) -> Scope {
+    scope.route("/suggest", get().to(suggest))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Suggestion {
+    Location { id: i32, name: String, distance: f64 },
+    Category { id: i32, name: String },
+    Equipment { name: String, count: i64 },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Suggest {
+    q: String,
+    latitude: f64,
+    longitude: f64,
+}
+
+pub async fn suggest(pool: Data<PgPool>, Query(Suggest { q, latitude, longitude }): Query<Suggest>) -> Result<Json<Vec<Suggestion>>, Error> {
+    let q = q.trim();
+    if q.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+    if q.chars().count() > 50 {
+        return Err(Error::BusinessError("keyword is too long".into()));
+    }
+    let conn = pool.get().context("failed to get search suggestions")?;
+    let mut list: Vec<Suggestion> = search::suggest_locations(&conn, q, latitude, longitude, 8)?
+        .into_iter()
+        .map(|l| Suggestion::Location {
+            id: l.id,
+            name: l.name,
+            distance: l.distance,
+        })
+        .collect();
+    list.extend(search::suggest_categories(&conn, q, 3)?.into_iter().map(|c| Suggestion::Category { id: c.id, name: c.name }));
+    list.extend(search::suggest_equipments(&conn, q, 3)?.into_iter().map(|e| Suggestion::Equipment { name: e.name, count: e.count }));
+    Ok(Json(list))
+}
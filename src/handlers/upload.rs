@@ -1,36 +1,484 @@
 use super::PgPool;
-use crate::domain::upload;
+use crate::dao::{
+    memory as memory_dao,
+    upload::{self as upload_dao, Audience},
+};
+use crate::domain::{
+    media::MediaProcessor,
+    upload::{self, DynStorer, UploadStorer},
+};
 use crate::error::Error;
+use crate::models::{UploadSession, UploadStatus, Variant};
 use crate::persister::postgres::PostgresPersister;
-use crate::storer::local::LocalStore;
 use crate::token::UID;
+use crate::worker::rendition::RenditionWorker;
 use actix_multipart::Multipart;
 use actix_web::{
-    body::BodyStream,
-    web::{get, post, Data, Json, Path},
-    HttpResponse, Scope,
+    body::SizedStream,
+    http::{
+        header::{self, CacheControl, CacheDirective, ContentRange, ContentRangeSpec, ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range},
+        Method, StatusCode,
+    },
+    web::{get, head, patch, post, Data, Json, Path, Payload, Query},
+    HttpMessage, HttpRequest, HttpResponse, Scope,
 };
-use dotenv;
-use futures::StreamExt;
+use anyhow::Context;
+use diesel::{Connection, PgConnection};
+use futures::{stream, Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use std::time::{Duration, UNIX_EPOCH};
 
 pub fn register_route(scope: &str) -> Scope {
-    Scope::new(scope).route("", post().to(upload)).route("/{id}", get().to(fetch))
+    Scope::new(scope)
+        .route("", post().to(upload))
+        .route("/sessions", post().to(create_session))
+        .route("/sessions/{id}", get().to(get_session))
+        .route("/sessions/{id}", patch().to(append))
+        .route("/sessions/{id}/complete", post().to(complete))
+        .route("/{id}", get().to(fetch))
+        .route("/{id}", head().to(fetch))
 }
 
-pub async fn upload(uid: UID, mut multi: Multipart, pool: Data<PgPool>) -> Result<Json<Vec<i32>>, Error> {
-    let storer = LocalStore::new(&dotenv::var("UPLOAD_DIR").unwrap());
+pub async fn upload(
+    uid: UID,
+    mut multi: Multipart,
+    pool: Data<PgPool>,
+    storer: Data<DynStorer>,
+    processor: Data<dyn MediaProcessor>,
+    limits: Data<upload::Limits>,
+    worker: Data<RenditionWorker>,
+) -> Result<Json<Vec<i32>>, Error> {
     let mut ids = Vec::new();
     while let Some(Ok(field)) = multi.next().await {
         let persister = PostgresPersister::new(pool.get().map_err(|e| anyhow::Error::from(e))?);
         let f = field.map(|v| v.map_err(|e| anyhow::Error::from(e)));
-        ids.push(upload::upload(f, storer.clone(), persister, uid.0).await?);
+        let id = upload::upload(f, storer.clone().into_inner(), persister, processor.clone().into_inner(), uid.0, &limits)
+            .await
+            .map_err(rejected)?;
+        ids.push(id);
+        worker.wake();
     }
     Ok(Json(ids))
 }
 
-pub async fn fetch(id: Path<(i32,)>, pool: Data<PgPool>) -> Result<HttpResponse, Error> {
-    let persister = PostgresPersister::new(pool.get().map_err(|e| anyhow::Error::from(e))?);
-    let storer = LocalStore::new(&dotenv::var("UPLOAD_DIR").unwrap());
-    let (stream, mime) = upload::get(id.0, persister, storer).await?;
-    Ok(HttpResponse::Ok().content_type(mime).body(BodyStream::new(stream)))
+// 文件本身不符合要求时告知客户端
+fn rejected(e: anyhow::Error) -> Error {
+    match e.downcast_ref::<upload::Rejection>() {
+        Some(r) => Error::BusinessError(r.to_string()),
+        None => Error::from(e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSession {
+    size: i64,
+}
+
+// 网络不稳定时使用可续传的上传: 创建会话之后按顺序追加数据, 中断时查询已经收到的位置继续, 全部收到之后完成
+pub async fn create_session(UID(uid): UID, pool: Data<PgPool>, limits: Data<upload::Limits>, Json(body): Json<CreateSession>) -> Result<Json<UploadSession>, Error> {
+    if body.size <= 0 {
+        return Err(Error::BusinessError("size must be positive".into()));
+    }
+    // 进行中的会话声明的大小计入用量, 与普通上传共用同一个锁, 并发创建时不会超出配额
+    let conn = pool.get()?;
+    let session = conn
+        .transaction::<UploadSession, anyhow::Error, _>(|| {
+            upload_dao::lock_quota(&conn, uid)?;
+            let used = upload_dao::used_storage(&conn, uid)?;
+            let open = upload_dao::open_sessions(&conn, uid)?;
+            limits.check_session(used, open, body.size)?;
+            upload_dao::create_session(&conn, uid, body.size)
+        })
+        .map_err(rejected)?;
+    Ok(Json(session))
+}
+
+fn session_of(conn: &PgConnection, uid: i32, id: i32) -> Result<UploadSession, Error> {
+    let session = upload_dao::get_session(conn, id)?;
+    if session.owner != uid {
+        return Err(Error::PermissionError);
+    }
+    Ok(session)
+}
+
+pub async fn get_session(UID(uid): UID, id: Path<(i32,)>, pool: Data<PgPool>) -> Result<Json<UploadSession>, Error> {
+    Ok(Json(session_of(&*pool.get()?, uid, id.0)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Append {
+    offset: i64,
+}
+
+// 请求体是从 offset 开始的一段数据; 位置与已经收到的不一致时返回 409 和当前的会话, 客户端从 received 继续
+pub async fn append(UID(uid): UID, id: Path<(i32,)>, Query(Append { offset }): Query<Append>, mut payload: Payload, pool: Data<PgPool>, storer: Data<DynStorer>) -> Result<HttpResponse, Error> {
+    let session = session_of(&*pool.get()?, uid, id.0)?;
+    let remaining = match check_append(&session, offset)? {
+        AppendCheck::Accept(remaining) => remaining,
+        AppendCheck::Conflict => return Ok(HttpResponse::Conflict().json(session)),
+    };
+    // 每段单独存储, 中途断开的这一段整个丢弃
+    let (mut sink, fetch_code) = storer.store()?;
+    let appended = match write_part(&mut sink, &mut payload, remaining).await {
+        Ok(size) => upload_dao::append_part(&pool.get()?, session.id, offset, size, &fetch_code).map_err(Error::from),
+        Err(e) => Err(e),
+    };
+    if !matches!(appended, Ok(true)) {
+        if let Err(e) = storer.delete(&fetch_code).await {
+            log::warn!("failed to delete upload part {}: {:?}", fetch_code, e);
+        }
+    }
+    let session = upload_dao::get_session(&pool.get()?, session.id)?;
+    match appended? {
+        true => Ok(HttpResponse::Ok().json(session)),
+        false => Ok(HttpResponse::Conflict().json(session)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum AppendCheck {
+    // 还可以接收的字节数
+    Accept(i64),
+    Conflict,
+}
+
+fn check_append(session: &UploadSession, offset: i64) -> Result<AppendCheck, Error> {
+    if session.upload.is_some() {
+        return Err(Error::BusinessError("upload session is already completed".into()));
+    }
+    if offset != session.received {
+        return Ok(AppendCheck::Conflict);
+    }
+    Ok(AppendCheck::Accept(session.size - session.received))
+}
+
+async fn write_part<SK, P, E>(sink: &mut SK, payload: &mut P, remaining: i64) -> Result<i64, Error>
+where
+    SK: Sink<bytes::Bytes, Error = anyhow::Error> + Unpin,
+    P: Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let mut size = 0;
+    while let Some(bs) = payload.try_next().await.map_err(|e| anyhow::Error::msg(e.to_string()))? {
+        size += bs.len() as i64;
+        if size > remaining {
+            return Err(Error::BusinessError("data exceeds the declared size".into()));
+        }
+        sink.send(bs).await?;
+    }
+    sink.close().await?;
+    if size == 0 {
+        return Err(Error::BusinessError("no data to append".into()));
+    }
+    Ok(size)
+}
+
+// 按顺序读出各段, 与 multipart 上传走相同的检查和入库流程; 重复完成返回同一个上传
+pub async fn complete(
+    UID(uid): UID,
+    id: Path<(i32,)>,
+    pool: Data<PgPool>,
+    storer: Data<DynStorer>,
+    processor: Data<dyn MediaProcessor>,
+    limits: Data<upload::Limits>,
+    worker: Data<RenditionWorker>,
+) -> Result<Json<i32>, Error> {
+    let (session, parts) = {
+        let conn = pool.get()?;
+        let session = session_of(&conn, uid, id.0)?;
+        if let CompleteCheck::Completed(upload) = check_complete(&session)? {
+            return Ok(Json(upload));
+        }
+        let parts: Vec<String> = upload_dao::session_parts(&conn, session.id)?.into_iter().map(|(code, _)| code).collect();
+        (session, parts)
+    };
+    let s = storer.clone().into_inner();
+    let data = Box::pin(stream::iter(parts.clone()).map(move |code| s.get(&code)).try_flatten());
+    let persister = PostgresPersister::new(pool.get()?);
+    // 会话声明的大小已经计入用量, 检查配额时扣除
+    let limits = limits.with_reserved(session.size);
+    let id = match upload::upload(data, storer.clone().into_inner(), persister, processor.into_inner(), uid, &limits).await {
+        Ok(id) => id,
+        // 被拒绝的文件不会再被接受, 直接删除会话
+        Err(e) if e.is::<upload::Rejection>() => {
+            discard(storer.get_ref(), upload_dao::delete_session(&pool.get()?, session.id)?.into_iter().map(|(code, _)| code).collect()).await;
+            return Err(rejected(e));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    worker.wake();
+    let conn = pool.get()?;
+    if !upload_dao::finish_session(&conn, session.id, id)? {
+        // 其他请求已经完成了该会话, 这次生成的上传没有被引用, 由清理任务删除
+        return Ok(Json(upload_dao::get_session(&conn, session.id)?.upload.context("upload session is not completed")?));
+    }
+    discard(storer.get_ref(), parts).await;
+    Ok(Json(id))
+}
+
+#[derive(Debug, PartialEq)]
+enum CompleteCheck {
+    // 已经完成过, 返回同一个上传
+    Completed(i32),
+    Ready,
+}
+
+fn check_complete(session: &UploadSession) -> Result<CompleteCheck, Error> {
+    if let Some(upload) = session.upload {
+        return Ok(CompleteCheck::Completed(upload));
+    }
+    if session.received != session.size {
+        return Err(Error::BusinessError("upload session is incomplete".into()));
+    }
+    Ok(CompleteCheck::Ready)
+}
+
+async fn discard(storer: &DynStorer, parts: Vec<String>) {
+    for code in parts {
+        if let Err(e) = storer.delete(&code).await {
+            log::warn!("failed to delete upload part {}: {:?}", code, e);
+        }
+    }
+}
+
+// 地点, 回忆, 评论和头像只能使用自己上传的图片
+pub(crate) fn check_owner(conn: &PgConnection, uid: i32, images: &Vec<i32>) -> Result<(), Error> {
+    if images.is_empty() {
+        return Ok(());
+    }
+    let mut ids = images.clone();
+    ids.sort();
+    ids.dedup();
+    let uploads = upload_dao::get_by_ids(conn, &ids)?;
+    if uploads.len() != ids.len() {
+        return Err(Error::BusinessError("cannot find all images".into()));
+    }
+    if uploads.iter().any(|u| u.owner != uid) {
+        return Err(Error::PermissionError);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Fetch {
+    #[serde(default)]
+    size: Variant,
+}
+
+// 处理之后的文件不会再改变, 可以长期缓存
+const CACHE_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+#[derive(Debug, PartialEq)]
+enum Serve {
+    NotModified,
+    Full,
+    // 包含两端的字节范围
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+fn evaluate(req: &HttpRequest, etag: &EntityTag, last_modified: HttpDate, length: u64) -> Serve {
+    // 同时存在时 If-None-Match 优先于 If-Modified-Since
+    if let Some(inm) = req.get_header::<IfNoneMatch>() {
+        let matched = match inm {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|t| t.weak_eq(etag)),
+        };
+        if matched {
+            return Serve::NotModified;
+        }
+    } else if let Some(IfModifiedSince(since)) = req.get_header::<IfModifiedSince>() {
+        if last_modified <= since {
+            return Serve::NotModified;
+        }
+    }
+    let specs = match req.get_header::<Range>() {
+        Some(Range::Bytes(specs)) => specs,
+        _ => return Serve::Full,
+    };
+    // 客户端缓存的版本已经过期时返回完整内容
+    if let Some(if_range) = req.get_header::<IfRange>() {
+        let fresh = match if_range {
+            IfRange::EntityTag(tag) => tag.strong_eq(etag),
+            IfRange::Date(date) => date == last_modified,
+        };
+        if !fresh {
+            return Serve::Full;
+        }
+    }
+    // 只支持单个范围, 多个范围时返回完整内容
+    if specs.len() != 1 {
+        return Serve::Full;
+    }
+    match specs[0].to_satisfiable_range(length) {
+        Some((start, end)) => Serve::Partial(start, end),
+        None => Serve::Unsatisfiable,
+    }
+}
+
+// 只提供处理之后的图片, 原始文件中可能带有拍摄位置等信息; 存储支持时重定向到预签名地址, 由客户端直接下载
+pub async fn fetch(req: HttpRequest, UID(uid): UID, id: Path<(i32,)>, Query(q): Query<Fetch>, pool: Data<PgPool>, storer: Data<DynStorer>) -> Result<HttpResponse, Error> {
+    let conn = pool.get()?;
+    let upload = upload_dao::get(&conn, id.0)?;
+    // 还没有被引用的上传只有上传者可见, 只在非公开回忆中使用的图片仅对能看到其中某个回忆的用户可见; 都不能被共享缓存保存
+    let audience = upload_dao::audience(&conn, upload.id)?;
+    let visible = match audience {
+        Audience::Public => true,
+        Audience::Memory => upload.owner == uid || memory_dao::is_upload_visible(&conn, upload.id, uid)?,
+        Audience::Owner => upload.owner == uid,
+    };
+    if !visible {
+        return Err(Error::PermissionError);
+    }
+    let rendition = match upload.status {
+        UploadStatus::Pending => return Err(Error::BusinessError("upload is still being processed".into())),
+        UploadStatus::Failed => return Err(Error::BusinessError("upload could not be processed".into())),
+        UploadStatus::Ready => upload_dao::rendition(&conn, upload.id, q.size)?.context("failed to get upload rendition")?,
+    };
+    // 每个尺寸都有自己的 fetch code, 内容不会改变, 直接作为 ETag
+    let etag = EntityTag::new_strong(rendition.fetch_code.clone());
+    let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(rendition.create_on.timestamp() as u64));
+    let length = rendition.size as u64;
+    let serve = evaluate(&req, &etag, last_modified, length);
+    let mut resp = HttpResponse::build(match serve {
+        Serve::NotModified => StatusCode::NOT_MODIFIED,
+        Serve::Full => StatusCode::OK,
+        Serve::Partial(..) => StatusCode::PARTIAL_CONTENT,
+        Serve::Unsatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+    });
+    resp.insert_header(ETag(etag))
+        .insert_header(LastModified(last_modified))
+        .insert_header(CacheControl(vec![
+            if audience == Audience::Public { CacheDirective::Public } else { CacheDirective::Private },
+            CacheDirective::MaxAge(CACHE_MAX_AGE),
+            CacheDirective::Extension("immutable".into(), None),
+        ]))
+        .insert_header((header::ACCEPT_RANGES, "bytes"));
+    match serve {
+        Serve::NotModified => return Ok(resp.finish()),
+        Serve::Unsatisfiable => {
+            return Ok(resp
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(length),
+                }))
+                .finish())
+        }
+        _ => {}
+    }
+    if let Some(url) = storer.presign(&rendition.fetch_code, &rendition.mime)? {
+        return Ok(HttpResponse::Found().insert_header((header::LOCATION, url)).finish());
+    }
+    resp.content_type(rendition.mime);
+    let range = match serve {
+        Serve::Partial(start, end) => {
+            resp.insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((start, end)),
+                instance_length: Some(length),
+            }));
+            Some((start, end))
+        }
+        _ => None,
+    };
+    let size = range.map_or(length, |(start, end)| end - start + 1);
+    // HEAD 只需要响应头, 不读取存储
+    let body = if req.method() == Method::HEAD {
+        stream::empty().boxed_local()
+    } else if let Some((start, end)) = range {
+        storer.get_range(&rendition.fetch_code, start, end).context("failed to get uploaded file")?
+    } else {
+        storer.get(&rendition.fetch_code).context("failed to get uploaded file")?
+    };
+    Ok(resp.body(SizedStream::new(size, body)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn evaluate_with(headers: &[(&str, &str)]) -> Serve {
+        let mut req = TestRequest::get();
+        for (k, v) in headers {
+            req = req.insert_header((*k, *v));
+        }
+        let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1_659_000_000));
+        evaluate(&req.to_http_request(), &EntityTag::new_strong("code".into()), last_modified, 100)
+    }
+
+    #[test]
+    fn test_evaluate_conditional() {
+        assert_eq!(evaluate_with(&[]), Serve::Full);
+        assert_eq!(evaluate_with(&[("if-none-match", "\"code\"")]), Serve::NotModified);
+        assert_eq!(evaluate_with(&[("if-none-match", "W/\"code\"")]), Serve::NotModified);
+        assert_eq!(evaluate_with(&[("if-none-match", "*")]), Serve::NotModified);
+        assert_eq!(evaluate_with(&[("if-none-match", "\"other\"")]), Serve::Full);
+        assert_eq!(evaluate_with(&[("if-modified-since", "Fri, 29 Jul 2022 00:00:00 GMT")]), Serve::NotModified);
+        assert_eq!(evaluate_with(&[("if-modified-since", "Wed, 27 Jul 2022 00:00:00 GMT")]), Serve::Full);
+        // If-None-Match 不匹配时忽略 If-Modified-Since
+        assert_eq!(evaluate_with(&[("if-none-match", "\"other\""), ("if-modified-since", "Fri, 29 Jul 2022 00:00:00 GMT")]), Serve::Full);
+    }
+
+    #[test]
+    fn test_evaluate_range() {
+        assert_eq!(evaluate_with(&[("range", "bytes=10-19")]), Serve::Partial(10, 19));
+        assert_eq!(evaluate_with(&[("range", "bytes=90-")]), Serve::Partial(90, 99));
+        assert_eq!(evaluate_with(&[("range", "bytes=-10")]), Serve::Partial(90, 99));
+        assert_eq!(evaluate_with(&[("range", "bytes=50-500")]), Serve::Partial(50, 99));
+        assert_eq!(evaluate_with(&[("range", "bytes=100-")]), Serve::Unsatisfiable);
+        assert_eq!(evaluate_with(&[("range", "bytes=0-1,5-6")]), Serve::Full);
+        assert_eq!(evaluate_with(&[("range", "bytes=0-9"), ("if-range", "\"code\"")]), Serve::Partial(0, 9));
+        assert_eq!(evaluate_with(&[("range", "bytes=0-9"), ("if-range", "\"other\"")]), Serve::Full);
+    }
+
+    fn session(size: i64, received: i64, upload: Option<i32>) -> UploadSession {
+        let now = chrono::Utc::now().naive_utc();
+        UploadSession {
+            id: 1,
+            owner: 1,
+            size: size,
+            received: received,
+            upload: upload,
+            create_on: now,
+            update_on: now,
+        }
+    }
+
+    #[test]
+    fn test_check_append() {
+        assert_eq!(check_append(&session(100, 0, None), 0).unwrap(), AppendCheck::Accept(100));
+        assert_eq!(check_append(&session(100, 40, None), 40).unwrap(), AppendCheck::Accept(60));
+        // 位置不一致时客户端从 received 继续
+        assert_eq!(check_append(&session(100, 40, None), 0).unwrap(), AppendCheck::Conflict);
+        assert_eq!(check_append(&session(100, 40, None), 80).unwrap(), AppendCheck::Conflict);
+        assert_eq!(check_append(&session(100, 100, None), 100).unwrap(), AppendCheck::Accept(0));
+        assert!(matches!(check_append(&session(100, 100, Some(3)), 100), Err(Error::BusinessError(_))));
+    }
+
+    #[test]
+    fn test_check_complete() {
+        assert_eq!(check_complete(&session(100, 100, None)).unwrap(), CompleteCheck::Ready);
+        // 重复完成返回同一个上传
+        assert_eq!(check_complete(&session(100, 100, Some(3))).unwrap(), CompleteCheck::Completed(3));
+        assert!(matches!(check_complete(&session(100, 40, None)), Err(Error::BusinessError(_))));
+    }
+
+    async fn write(chunks: Vec<&'static [u8]>, remaining: i64) -> (Result<i64, Error>, Vec<bytes::Bytes>) {
+        let mut written = Vec::new();
+        let mut sink = (&mut written).sink_map_err(|e| match e {});
+        let mut payload = stream::iter(chunks.into_iter().map(|c| Ok::<_, std::io::Error>(bytes::Bytes::from_static(c))));
+        let res = write_part(&mut sink, &mut payload, remaining).await;
+        (res, written)
+    }
+
+    #[actix_web::test]
+    async fn test_write_part() {
+        let (res, written) = write(vec![b"abc", b"de"], 10).await;
+        assert_eq!(res.unwrap(), 5);
+        assert_eq!(written, vec![bytes::Bytes::from_static(b"abc"), bytes::Bytes::from_static(b"de")]);
+        let (res, _) = write(vec![b"abc", b"de"], 5).await;
+        assert_eq!(res.unwrap(), 5);
+        assert!(matches!(write(vec![b"abc", b"de"], 4).await.0, Err(Error::BusinessError(_))));
+        assert!(matches!(write(vec![], 10).await.0, Err(Error::BusinessError(_))));
+    }
 }
@@ -13,7 +13,7 @@ use diesel::{
 use serde::{Deserialize, Serialize};
 use std::default::Default;
 
-use super::PgPool;
+use super::{upload::check_owner, PgPool};
 
 impl<T> user::SaltGenerator for Data<T>
 where
@@ -43,6 +43,9 @@ where
     fn validate(&self, token: &str) -> Result<i32, anyhow::Error> {
         self.as_ref().validate(token)
     }
+    fn validate_until(&self, token: &str) -> Result<(i32, i64), anyhow::Error> {
+        self.as_ref().validate_until(token)
+    }
 }
 
 pub async fn signup<SG, PH>(db: Data<Pool<ConnectionManager<PgConnection>>>, salt_generator: Data<SG>, password_hasher: Data<PH>, Json(req): Json<user::Registration>) -> Result<Json<i32>, Error>
@@ -93,6 +96,7 @@ pub struct UpdateAvatar {
 
 pub async fn update_avatar(db: Data<PgPool>, uid: UID, Json(UpdateAvatar { avatar }): Json<UpdateAvatar>) -> Result<Json<usize>, Error> {
     let conn = db.get().context("failed to udpate avatar")?;
+    check_owner(&conn, uid.0, &vec![avatar])?;
     let effected = update(
         &conn,
         uid.0,
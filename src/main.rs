@@ -5,12 +5,15 @@ mod generator;
 mod geo;
 mod handlers;
 mod hasher;
+mod media;
 mod models;
 mod persister;
+mod realtime;
 mod response;
 mod schema;
 mod storer;
 mod token;
+mod worker;
 
 #[macro_use]
 extern crate diesel;
@@ -26,21 +29,59 @@ use diesel::{
     r2d2::{ConnectionManager, Pool},
     PgConnection,
 };
+use domain::notification::NotificationSink;
 use env_logger;
 use generator::random::Generator;
-use handlers::{comment, location, memory, upload};
+use handlers::{activity, admin, comment, family, favorite, follow, location, memory, notification, search, upload};
 use hasher::sha::Hasher;
 use rand::{rngs::ThreadRng, thread_rng};
+use realtime::{hub::Hub, ticket::Tickets};
+use std::sync::Arc;
 use token::jwt::JWT;
+use worker::{gc::GarbageCollector, rendition::RenditionWorker};
 
 const DATABASE_URL: &str = "DATABASE_URL";
 const JWT_SECRET: &str = "JWT_SECRET";
 const JWT_TOKEN_DURATION: &str = "JWT_TOKEN_DURATION";
+const UPLOAD_MAX_SIZE: &str = "UPLOAD_MAX_SIZE";
+const UPLOAD_QUOTA: &str = "UPLOAD_QUOTA";
+const UPLOAD_MAX_VIDEO_SIZE: &str = "UPLOAD_MAX_VIDEO_SIZE";
+const UPLOAD_MAX_VIDEO_DURATION: &str = "UPLOAD_MAX_VIDEO_DURATION";
+const UPLOAD_GC_GRACE: &str = "UPLOAD_GC_GRACE";
+const UPLOAD_GC_INTERVAL: &str = "UPLOAD_GC_INTERVAL";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().expect("failed to load .env file");
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("debug"));
+    // 广播中心需要在所有 worker 之间共享, 所以在这里创建; 推送服务接入之前通知只通过它实时下发
+    // 每个用户和单元各一个通道, 单个通道容量不需要很大
+    let hub = Arc::new(Hub::new(64));
+    let sink: Arc<dyn NotificationSink> = hub.clone();
+    let tickets = Data::new(Tickets::new(handlers::realtime::TICKET_TTL));
+    // 未配置时单个文件 20MB, 每个用户 2GB
+    let upload_limits = domain::upload::Limits::new(
+        dotenv::var(UPLOAD_MAX_SIZE).map_or(20 << 20, |v| v.parse::<i64>().expect("UPLOAD_MAX_SIZE environment variable must be integer")),
+        dotenv::var(UPLOAD_QUOTA).map_or(2 << 30, |v| v.parse::<i64>().expect("UPLOAD_QUOTA environment variable must be integer")),
+    )
+    // 视频单个文件 200MB, 最长 60 秒
+    .with_video(
+        dotenv::var(UPLOAD_MAX_VIDEO_SIZE).map_or(200 << 20, |v| v.parse::<i64>().expect("UPLOAD_MAX_VIDEO_SIZE environment variable must be integer")),
+        dotenv::var(UPLOAD_MAX_VIDEO_DURATION).map_or(60, |v| v.parse::<i64>().expect("UPLOAD_MAX_VIDEO_DURATION environment variable must be integer")) as f64,
+    );
+    let storer = storer::from_env();
+    // 图片处理使用单独的连接池, 不占用请求的连接
+    let processor = media::from_env();
+    let renditions = RenditionWorker::new(processor.clone(), upload_limits.max_video_duration);
+    let worker_pool =
+        Pool::new(ConnectionManager::<PgConnection>::new(dotenv::var(DATABASE_URL).expect("DATABASE_URL environment variable not exists"))).expect("failed to create database connection pool");
+    actix_web::rt::spawn(renditions.clone().run(worker_pool.clone(), storer.clone()));
+    // 未配置时清理上传超过一天仍未被引用的文件, 每小时一次
+    let gc = GarbageCollector::new(chrono::Duration::seconds(
+        dotenv::var(UPLOAD_GC_GRACE).map_or(24 * 60 * 60, |v| v.parse::<i64>().expect("UPLOAD_GC_GRACE environment variable must be integer")),
+    ));
+    let gc_interval = dotenv::var(UPLOAD_GC_INTERVAL).map_or(60 * 60, |v| v.parse::<u64>().expect("UPLOAD_GC_INTERVAL environment variable must be integer"));
+    actix_web::rt::spawn(gc.clone().run(worker_pool, storer.clone(), std::time::Duration::from_secs(gc_interval)));
     HttpServer::new(move || {
         let mut location_scope = scope("/locations");
         location_scope = location::register(location_scope);
@@ -58,23 +99,42 @@ async fn main() -> std::io::Result<()> {
             ),
         );
         App::new()
-            .wrap(Logger::new("%a %{User-Agent}i %r"))
+            // 不记录查询参数, 其中可能有推送票据等敏感信息
+            .wrap(Logger::new("%a %{User-Agent}i %{request}xi").custom_request_replace("request", |req| format!("{} {}", req.method(), req.path())))
             .app_data(Data::new(Generator::new(thread_rng())))
             .app_data(Data::new(Hasher::new()))
             .app_data(Data::new(pool))
+            .app_data(Data::from(sink.clone()))
+            .app_data(Data::from(hub.clone()))
+            .app_data(tickets.clone())
             .app_data(Data::new(jwt.clone()))
+            .app_data(Data::from(storer.clone()))
+            .app_data(Data::from(processor.clone()))
+            .app_data(Data::new(upload_limits.clone()))
+            .app_data(Data::new(renditions.clone()))
+            .app_data(Data::new(gc.clone()))
             .service(
                 scope("/user")
                     .route("/signup", web::post().to(handlers::user::signup::<Generator<ThreadRng>, Hasher>))
                     .route("/signin", web::post().to(handlers::user::signin::<Hasher, token::jwt::JWT>)),
             )
+            .service(handlers::realtime::register::<JWT>(scope("/stream")))
+            // 分享的收藏列表通过链接打开, 不需要登录
+            .service(favorite::register_shared(scope("/shared")))
             .service(
                 scope("/api")
                     .wrap(jwt)
                     .service(upload::register_route("/upload"))
                     .service(location_scope)
-                    .service(scope("/my").route("/avatar", web::put().to(handlers::user::update_avatar)))
-                    .service(scope("/memories").route("", web::get().to(memory::near_memories))),
+                    .service(comment::register_my(favorite::register(scope("/my").route("/avatar", web::put().to(handlers::user::update_avatar)))))
+                    .service(family::register(scope("/family")))
+                    .service(activity::register(scope("/activities")))
+                    .service(follow::register(scope("/users")))
+                    .service(notification::register(scope("/notifications")))
+                    .service(handlers::realtime::register_ticket::<JWT>(scope("/stream")))
+                    .service(search::register(scope("/search")))
+                    .service(admin::register(scope("/admin")))
+                    .service(memory::register_feed(scope("/memories"))),
             )
     })
     .bind((
@@ -0,0 +1,127 @@
+use crate::domain::media::{MediaProcessor, Video};
+use anyhow::{Context, Error};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+// 通过 ffprobe 读取尺寸和时长, 通过 ffmpeg 截取封面并去除元数据
+pub struct Ffmpeg {
+    ffmpeg: String,
+    ffprobe: String,
+}
+
+#[derive(Deserialize)]
+struct Probe {
+    streams: Vec<Stream>,
+    format: Format,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+}
+
+#[derive(Deserialize)]
+struct SideData {
+    #[serde(default)]
+    rotation: i32,
+}
+
+#[derive(Deserialize)]
+struct Format {
+    duration: String,
+}
+
+impl Ffmpeg {
+    pub fn new(ffmpeg: &str, ffprobe: &str) -> Self {
+        Self {
+            ffmpeg: ffmpeg.to_owned(),
+            ffprobe: ffprobe.to_owned(),
+        }
+    }
+
+    // 手机竖着拍摄的视频通过旋转信息记录方向, 返回旋转之后的尺寸
+    fn probe(&self, path: &Path) -> Result<(u32, u32, f64), Error> {
+        let output = run(Command::new(&self.ffprobe)
+            .args([
+                "-v",
+                "error",
+                "-select_streams",
+                "v:0",
+                "-show_entries",
+                "stream=width,height:stream_side_data=rotation:format=duration",
+                "-of",
+                "json",
+            ])
+            .arg(path))?;
+        let probe: Probe = serde_json::from_slice(&output).context("failed to parse ffprobe output")?;
+        let stream = probe.streams.into_iter().next().context("no video stream")?;
+        let duration = probe.format.duration.parse::<f64>().context("invalid video duration")?;
+        let rotated = stream.side_data_list.iter().any(|d| d.rotation.abs() % 180 == 90);
+        Ok(if rotated {
+            (stream.height, stream.width, duration)
+        } else {
+            (stream.width, stream.height, duration)
+        })
+    }
+
+    // ffmpeg 默认会按旋转信息转正画面
+    fn poster(&self, path: &Path, at: f64) -> Result<Vec<u8>, Error> {
+        run(Command::new(&self.ffmpeg)
+            .args(["-v", "error", "-ss", &format!("{:.3}", at), "-i"])
+            .arg(path)
+            .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "mjpeg", "-"]))
+    }
+
+    // 只重新封装不重新编码, 去除拍摄位置等元数据
+    fn strip(&self, input: &Path, output: &Path, webm: bool) -> Result<(), Error> {
+        let mut cmd = Command::new(&self.ffmpeg);
+        cmd.args(["-v", "error", "-y", "-i"])
+            .arg(input)
+            .args(["-map", "0:v:0", "-map", "0:a?", "-map_metadata", "-1", "-map_chapters", "-1", "-fflags", "+bitexact", "-c", "copy"]);
+        if webm {
+            cmd.args(["-f", "webm"]);
+        } else {
+            cmd.args(["-movflags", "+faststart", "-f", "mp4"]);
+        }
+        run(cmd.arg(output))?;
+        Ok(())
+    }
+}
+
+impl MediaProcessor for Ffmpeg {
+    fn duration(&self, input: &Path) -> Result<f64, Error> {
+        Ok(self.probe(input)?.2)
+    }
+
+    fn process(&self, input: &Path, output: &Path, mime: &str) -> Result<Video, Error> {
+        let (width, height, duration) = self.probe(input)?;
+        // 太短的视频取中间一帧
+        let poster = self.poster(input, (duration / 2.0).min(1.0))?;
+        let webm = mime == "video/webm";
+        self.strip(input, output, webm)?;
+        Ok(Video {
+            width: width,
+            height: height,
+            duration: duration,
+            mime: if webm { "video/webm" } else { "video/mp4" },
+            poster: poster,
+        })
+    }
+}
+
+fn run(cmd: &mut Command) -> Result<Vec<u8>, Error> {
+    let output = cmd.output().with_context(|| format!("failed to run {:?}", cmd.get_program()))?;
+    if !output.status.success() {
+        return Err(Error::msg(format!(
+            "{:?} exited with {}: {}",
+            cmd.get_program(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,13 @@
+pub mod ffmpeg;
+
+use crate::domain::media::MediaProcessor;
+use ffmpeg::Ffmpeg;
+use std::sync::Arc;
+
+// 未配置时从 PATH 中查找 ffmpeg 和 ffprobe
+pub fn from_env() -> Arc<dyn MediaProcessor> {
+    Arc::new(Ffmpeg::new(
+        &dotenv::var("FFMPEG_PATH").unwrap_or("ffmpeg".into()),
+        &dotenv::var("FFPROBE_PATH").unwrap_or("ffprobe".into()),
+    ))
+}
@@ -1,10 +1,17 @@
 use crate::schema::*;
-use chrono::NaiveDateTime;
-use diesel::{AsChangeset, Associations, Identifiable, Insertable, Queryable, QueryableByName};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use diesel::{
+    deserialize::{self, FromSql},
+    pg::Pg,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Varchar,
+    AsChangeset, Associations, Identifiable, Insertable, Queryable, QueryableByName,
+};
 use serde::{Deserialize, Serialize};
 use std::default::Default;
+use std::io::Write;
 
-#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, QueryableByName)]
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, QueryableByName, Clone)]
 #[table_name = "users"]
 pub struct User {
     pub id: i32,
@@ -15,6 +22,7 @@ pub struct User {
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
     pub avatar: Option<i32>,
+    pub is_admin: bool,
 }
 
 #[derive(Debug, AsChangeset, Default)]
@@ -37,6 +45,12 @@ pub struct Comment {
     pub location: i32,
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
+    pub cleanliness: Option<i32>,
+    pub space: Option<i32>,
+    pub staff: Option<i32>,
+    pub accessibility: Option<i32>,
+    pub noise: Option<i32>,
+    pub helpful_count: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Insertable)]
@@ -46,13 +60,57 @@ pub struct CommentInsert {
     pub content: String,
     pub user: i32,
     pub location: i32,
+    pub cleanliness: Option<i32>,
+    pub space: Option<i32>,
+    pub staff: Option<i32>,
+    pub accessibility: Option<i32>,
+    pub noise: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, AsChangeset)]
 #[table_name = "comments"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct CommentUpdate {
     pub rank: i32,
     pub content: String,
+    pub cleanliness: Option<i32>,
+    pub space: Option<i32>,
+    pub staff: Option<i32>,
+    pub accessibility: Option<i32>,
+    pub noise: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Associations, Identifiable)]
+#[table_name = "comment_upload_rels"]
+#[belongs_to(Comment, foreign_key = "comment")]
+#[belongs_to(Upload, foreign_key = "upload")]
+pub struct CommentUploadRel {
+    pub id: i32,
+    pub comment: i32,
+    pub upload: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable)]
+#[table_name = "comment_replies"]
+pub struct CommentReply {
+    pub id: i32,
+    pub comment: i32,
+    pub user: i32,
+    pub parent: Option<i32>,
+    pub content: String,
+    pub is_official: bool,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "comment_replies"]
+pub struct CommentReplyInsert {
+    pub comment: i32,
+    pub user: i32,
+    pub parent: Option<i32>,
+    pub content: String,
+    pub is_official: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Insertable, AsChangeset)]
@@ -65,6 +123,8 @@ pub struct LocationInsertion {
     pub description: String,
     pub discoverer: i32,
     pub geo_index: String,
+    pub min_age_months: Option<i32>,
+    pub max_age_months: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, QueryableByName, AsChangeset, Clone)]
@@ -80,6 +140,8 @@ pub struct Location {
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
     pub geo_index: String,
+    pub min_age_months: Option<i32>,
+    pub max_age_months: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, AsChangeset, Clone)]
@@ -92,15 +154,192 @@ pub struct LocationUpdating {
     pub description: String,
     pub discoverer: i32,
     pub geo_index: String,
+    pub min_age_months: Option<i32>,
+    pub max_age_months: Option<i32>,
 }
 
-#[derive(Debug, Serialize, Queryable)]
+#[derive(Debug, Clone, Serialize, Queryable)]
 pub struct Upload {
     pub id: i32,
     pub fetch_code: String,
     pub owner: i32,
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
+    pub mime: String,
+    pub size: i64,
+    pub status: UploadStatus,
+    pub hash: Option<String>,
+    pub media_type: MediaType,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    // 视频的时长, 单位为秒
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Varchar"]
+pub enum MediaType {
+    Image,
+    Video,
+}
+
+impl MediaType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Video => "video",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for MediaType {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for MediaType {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"image" => Ok(Self::Image),
+            b"video" => Ok(Self::Video),
+            _ => Err("unrecognized media type".into()),
+        }
+    }
+}
+
+// 上传之后需要在后台去除元数据并生成各个尺寸, 完成之前不能读取
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Varchar"]
+pub enum UploadStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+impl UploadStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Ready => "ready",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for UploadStatus {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for UploadStatus {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"pending" => Ok(Self::Pending),
+            b"ready" => Ok(Self::Ready),
+            b"failed" => Ok(Self::Failed),
+            _ => Err("unrecognized upload status".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Associations, Identifiable)]
+#[belongs_to(Upload, foreign_key = "upload")]
+pub struct UploadRendition {
+    pub id: i32,
+    pub upload: i32,
+    pub variant: Variant,
+    pub fetch_code: String,
+    pub mime: String,
+    pub width: i32,
+    pub height: i32,
+    pub size: i64,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "upload_renditions"]
+pub struct UploadRenditionInsertion {
+    pub upload: i32,
+    pub variant: Variant,
+    pub fetch_code: String,
+    pub mime: String,
+    pub width: i32,
+    pub height: i32,
+    pub size: i64,
+}
+
+// 可续传的上传, received 是已经收到的字节数, 也就是下一次追加的位置; 完成之后 upload 为生成的上传
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
+pub struct UploadSession {
+    pub id: i32,
+    pub owner: i32,
+    pub size: i64,
+    pub received: i64,
+    pub upload: Option<i32>,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+// 图片的尺寸规格, original 是去除元数据之后的原图; 视频的 original 是去除元数据之后的视频, 其余为封面图
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Varchar"]
+pub enum Variant {
+    Thumbnail,
+    Medium,
+    Original,
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Self::Original
+    }
+}
+
+impl Variant {
+    pub const ALL: [Variant; 3] = [Variant::Thumbnail, Variant::Medium, Variant::Original];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Thumbnail => "thumbnail",
+            Self::Medium => "medium",
+            Self::Original => "original",
+        }
+    }
+
+    // 长边的最大像素数, 原图不缩放
+    pub fn max_dimension(&self) -> Option<u32> {
+        match self {
+            Self::Thumbnail => Some(320),
+            Self::Medium => Some(1280),
+            Self::Original => None,
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for Variant {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for Variant {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"thumbnail" => Ok(Self::Thumbnail),
+            b"medium" => Ok(Self::Medium),
+            b"original" => Ok(Self::Original),
+            _ => Err("unrecognized upload variant".into()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Queryable, Associations, Identifiable)]
@@ -122,6 +361,7 @@ pub struct Memory {
     pub location: i32,
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
+    pub visibility: Visibility,
 }
 
 #[derive(Debug, Serialize, Deserialize, Insertable, AsChangeset)]
@@ -131,6 +371,101 @@ pub struct MemoryCommand {
     pub content: String,
     pub owner: i32,
     pub location: i32,
+    pub visibility: Visibility,
+}
+
+// 回忆的可见范围: 仅自己, 家庭成员, 所有人
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Varchar"]
+pub enum Visibility {
+    Private,
+    Family,
+    Public,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self::Private
+    }
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Private => "private",
+            Self::Family => "family",
+            Self::Public => "public",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for Visibility {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for Visibility {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"private" => Ok(Self::Private),
+            b"family" => Ok(Self::Family),
+            b"public" => Ok(Self::Public),
+            _ => Err("unrecognized memory visibility".into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable)]
+#[table_name = "families"]
+pub struct Family {
+    pub id: i32,
+    pub name: String,
+    pub owner: i32,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable)]
+#[table_name = "family_invitations"]
+pub struct FamilyInvitation {
+    pub id: i32,
+    pub family: i32,
+    pub inviter: i32,
+    pub invitee: i32,
+    pub status: String,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable)]
+#[table_name = "children"]
+pub struct Child {
+    pub id: i32,
+    pub family: i32,
+    pub name: String,
+    pub birth_date: NaiveDate,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+impl Child {
+    // 某一天时的月龄, 不足一个月的部分舍去; 出生之前按 0 计算
+    pub fn age_in_months(&self, at: NaiveDate) -> i32 {
+        let months = (at.year() - self.birth_date.year()) * 12 + at.month() as i32 - self.birth_date.month() as i32;
+        let months = if at.day() < self.birth_date.day() { months - 1 } else { months };
+        months.max(0)
+    }
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[table_name = "children"]
+pub struct ChildCommand {
+    pub family: i32,
+    pub name: String,
+    pub birth_date: NaiveDate,
 }
 
 #[derive(Debug, Deserialize)]
@@ -144,6 +479,7 @@ pub enum MemoryOrderBy {
     UpdateOnDesc,
     Title,
     TitleDesc,
+    Relevance,
 }
 
 impl Default for MemoryOrderBy {
@@ -156,9 +492,16 @@ impl Default for MemoryOrderBy {
 pub struct MemoryQuery {
     pub latitude: f64,
     pub longitude: f64,
-    pub title: Option<String>,
+    pub keyword: Option<String>,
     pub owner: Option<i32>,
     pub location: Option<i32>,
+    pub child: Option<i32>,
+    // 该用户以及其家庭成员的回忆
+    pub family_of: Option<i32>,
+    // (月, 日), 往年同一天的回忆
+    pub anniversary: Option<(u32, u32)>,
+    // 只返回该用户关注的人的回忆
+    pub followed_by: Option<i32>,
     pub create_before: Option<NaiveDateTime>,
     pub create_after: Option<NaiveDateTime>,
     pub limit: i64,
@@ -208,6 +551,22 @@ pub struct RankAggregation {
     pub location_id: i32,
     pub create_on: NaiveDateTime,
     pub update_on: NaiveDateTime,
+    pub rank_1: i64,
+    pub rank_2: i64,
+    pub rank_3: i64,
+    pub rank_4: i64,
+    pub rank_5: i64,
+    pub score: f64,
+    pub cleanliness_total: i64,
+    pub cleanliness_count: i64,
+    pub space_total: i64,
+    pub space_count: i64,
+    pub staff_total: i64,
+    pub staff_count: i64,
+    pub accessibility_total: i64,
+    pub accessibility_count: i64,
+    pub noise_total: i64,
+    pub noise_count: i64,
 }
 
 #[derive(Debug, Deserialize, Insertable)]
@@ -218,9 +577,123 @@ pub struct RankAggregationInsert {
     pub location_id: i32,
 }
 
-#[derive(Debug, Deserialize, AsChangeset)]
-#[table_name = "rank_aggregations"]
-pub struct RankAggregationUpdate {
-    pub total: i64,
-    pub count: i64,
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable)]
+#[table_name = "favorite_lists"]
+pub struct FavoriteList {
+    pub id: i32,
+    pub name: String,
+    pub owner: i32,
+    pub share_code: Option<String>,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[table_name = "favorite_lists"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct FavoriteListCommand {
+    pub name: String,
+    pub owner: i32,
+    pub share_code: Option<String>,
+}
+
+// 动态的类型, target 分别指向地点, 回忆, 评论, 以及新增照片的地点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Varchar"]
+pub enum ActivityKind {
+    Location,
+    Memory,
+    Comment,
+    Photo,
+}
+
+impl ActivityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Location => "location",
+            Self::Memory => "memory",
+            Self::Comment => "comment",
+            Self::Photo => "photo",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for ActivityKind {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for ActivityKind {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"location" => Ok(Self::Location),
+            b"memory" => Ok(Self::Memory),
+            b"comment" => Ok(Self::Comment),
+            b"photo" => Ok(Self::Photo),
+            _ => Err("unrecognized activity kind".into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable)]
+#[table_name = "activities"]
+pub struct Activity {
+    pub id: i32,
+    pub actor: i32,
+    pub kind: ActivityKind,
+    pub location: i32,
+    pub target: i32,
+    pub create_on: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "activities"]
+pub struct ActivityInsert {
+    pub actor: i32,
+    pub kind: ActivityKind,
+    pub location: i32,
+    pub target: i32,
+}
+
+#[derive(Debug, Identifiable, Queryable)]
+#[table_name = "notifications"]
+pub struct Notification {
+    pub id: i32,
+    pub recipient: i32,
+    pub actor: Option<i32>,
+    pub kind: String,
+    pub location: Option<i32>,
+    pub target: Option<i32>,
+    pub content: String,
+    pub is_read: bool,
+    pub create_on: NaiveDateTime,
+    pub update_on: NaiveDateTime,
+}
+
+#[cfg(test)]
+mod test {
+    use super::Child;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_age_in_months() {
+        let child = Child {
+            id: 1,
+            family: 1,
+            name: "baby".into(),
+            birth_date: NaiveDate::from_ymd(2021, 1, 31),
+            create_on: NaiveDate::from_ymd(2021, 2, 1).and_hms(0, 0, 0),
+            update_on: NaiveDate::from_ymd(2021, 2, 1).and_hms(0, 0, 0),
+        };
+        assert_eq!(child.age_in_months(NaiveDate::from_ymd(2021, 1, 31)), 0);
+        assert_eq!(child.age_in_months(NaiveDate::from_ymd(2021, 2, 28)), 0);
+        assert_eq!(child.age_in_months(NaiveDate::from_ymd(2021, 3, 31)), 2);
+        assert_eq!(child.age_in_months(NaiveDate::from_ymd(2022, 7, 30)), 17);
+        // 出生之前
+        assert_eq!(child.age_in_months(NaiveDate::from_ymd(2020, 12, 1)), 0);
+        assert_eq!(child.age_in_months(NaiveDate::from_ymd(2019, 1, 31)), 0);
+    }
 }
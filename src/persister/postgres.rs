@@ -1,3 +1,4 @@
+use crate::dao::upload as upload_dao;
 use crate::domain::{
     eating::{self, EatingPersister},
     playing,
@@ -13,7 +14,7 @@ use diesel::{
     pg::PgConnection,
     r2d2::{ConnectionManager, PooledConnection},
     sql_types::{Array, Double, Integer},
-    Associations, BelongingToDsl, ExpressionMethods, GroupedBy, QueryDsl, RunQueryDsl,
+    Associations, BelongingToDsl, Connection, ExpressionMethods, GroupedBy, QueryDsl, RunQueryDsl,
 };
 use std::borrow::Borrow;
 
@@ -28,6 +29,7 @@ pub struct User {
     create_on: chrono::NaiveDateTime,
     update_on: chrono::NaiveDateTime,
     avatar: Option<i32>,
+    is_admin: bool,
 }
 
 impl From<User> for user::User {
@@ -41,6 +43,7 @@ impl From<User> for user::User {
             create_on: u.create_on,
             update_on: u.update_on,
             avatar: u.avatar,
+            is_admin: u.is_admin,
         }
     }
 }
@@ -197,6 +200,14 @@ struct Upload {
     owner: i32,
     create_on: NaiveDateTime,
     update_on: NaiveDateTime,
+    mime: String,
+    size: i64,
+    status: String,
+    hash: Option<String>,
+    media_type: String,
+    width: Option<i32>,
+    height: Option<i32>,
+    duration: Option<f64>,
 }
 
 impl Into<upload::Upload> for Upload {
@@ -207,6 +218,14 @@ impl Into<upload::Upload> for Upload {
             owner: self.owner,
             create_on: self.create_on,
             update_on: self.update_on,
+            mime: self.mime,
+            size: self.size,
+            status: self.status,
+            hash: self.hash,
+            media_type: self.media_type,
+            width: self.width,
+            height: self.height,
+            duration: self.duration,
         }
     }
 }
@@ -230,12 +249,36 @@ pub struct EatingsUpload {
 }
 
 impl UploadPersister for PostgresPersister {
-    fn insert_upload(&self, ins: Insertion) -> Result<i32, Error> {
-        let id = diesel::insert_into(uploads::table)
-            .values((uploads::fetch_code.eq(ins.fetch_code), uploads::owner.eq(ins.owner)))
-            .returning(uploads::id)
-            .get_result(&self.conn)?;
-        Ok(id)
+    // 摘要相同的内容已经存在时只增加引用计数, 上传记录使用已有的存储对象
+    fn insert_upload(&self, ins: Insertion) -> Result<upload::Upload, Error> {
+        self.conn.transaction::<upload::Upload, Error, _>(|| {
+            upload_dao::lock_quota(&self.conn, ins.owner)?;
+            if self.used_storage(ins.owner)? - ins.reserved + ins.size > ins.quota {
+                return Err(upload::Rejection::QuotaExceeded(ins.quota).into());
+            }
+            let storage_key: String = insert_into(blobs::table)
+                .values((blobs::hash.eq(&ins.hash), blobs::storage_key.eq(&ins.fetch_code), blobs::size.eq(ins.size), blobs::refs.eq(1)))
+                .on_conflict(blobs::hash)
+                .do_update()
+                .set(blobs::refs.eq(blobs::refs + 1))
+                .returning(blobs::storage_key)
+                .get_result(&self.conn)?;
+            let u: Upload = insert_into(uploads::table)
+                .values((
+                    uploads::fetch_code.eq(storage_key),
+                    uploads::owner.eq(ins.owner),
+                    uploads::mime.eq(ins.mime),
+                    uploads::size.eq(ins.size),
+                    uploads::hash.eq(ins.hash),
+                    uploads::media_type.eq(ins.media_type),
+                ))
+                .get_result(&self.conn)?;
+            Ok(u.into())
+        })
+    }
+
+    fn used_storage(&self, owner: i32) -> Result<i64, Error> {
+        upload_dao::used_storage(&self.conn, owner)
     }
 
     fn insert_eating_uploads(&self, ins: upload::EatingUploadsInsertion) -> Result<usize, Error> {
@@ -250,11 +293,6 @@ impl UploadPersister for PostgresPersister {
             .context("failed to insert eating and upload relation")
     }
 
-    fn get_upload(&self, id: i32) -> Result<upload::Upload, Error> {
-        let u: Upload = uploads::table.find(id).get_result(&self.conn)?;
-        Ok(u.into())
-    }
-
     fn query_upload_by_ids(&self, ids: &Vec<i32>) -> Result<Vec<upload::Upload>, Error> {
         uploads::table
             .filter(uploads::id.eq_any(ids))
@@ -0,0 +1,204 @@
+use crate::domain::notification::{Notification, NotificationSink};
+use crate::geo::h3;
+use anyhow::Error;
+use futures::future::select_all;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// 推送地点和回忆时使用的 H3 分辨率, 每个单元约 5 平方公里
+pub const CELL_RESOLUTION: i32 = 7;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Notification(Notification),
+    Location { id: i32, name: String, latitude: f64, longitude: f64, discoverer: i32 },
+    Memory { id: i32, title: String, location: i32, owner: i32 },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Notification(_) => "notification",
+            Self::Location { .. } => "location",
+            Self::Memory { .. } => "memory",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Envelope {
+    pub event: Event,
+}
+
+pub enum Message {
+    Event(Arc<Envelope>),
+    // 接收太慢, 丢掉了若干条消息, 客户端需要重新拉取
+    Lagged(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Topic {
+    User(i32),
+    Cell(u64),
+}
+
+// 进程内的广播中心, 按接收用户和 H3 单元分别使用有界通道: 订阅者只接收自己的通知和订阅单元内的事件,
+// 一个热闹的单元只会让订阅了它的连接丢消息, 不影响其他订阅者; 代价是每个在线用户和被订阅的单元各占一个通道,
+// 发布和订阅时需要加锁查找通道. 慢的订阅者会丢消息而不会阻塞发布者
+pub struct Hub {
+    // 每个通道的容量
+    capacity: usize,
+    topics: Mutex<HashMap<Topic, broadcast::Sender<Arc<Envelope>>>>,
+}
+
+impl Hub {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity,
+            topics: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 没有订阅者的事件直接丢弃, 订阅者都断开的通道在发送失败时移除
+    fn publish(&self, topic: Topic, envelope: Envelope) {
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(sender) = topics.get(&topic) {
+            if sender.send(Arc::new(envelope)).is_err() {
+                topics.remove(&topic);
+            }
+        }
+    }
+
+    pub fn publish_at(&self, latitude: f64, longitude: f64, event: Event) {
+        let cell = h3::index(latitude, longitude, CELL_RESOLUTION);
+        self.publish(Topic::Cell(cell), Envelope { event: event })
+    }
+
+    pub fn subscribe(&self, uid: i32, cells: HashSet<u64>) -> Subscription {
+        let mut topics = self.topics.lock().unwrap();
+        // 顺便清理之后没有再发布过事件的空通道
+        topics.retain(|_, sender| sender.receiver_count() > 0);
+        let receivers = std::iter::once(Topic::User(uid))
+            .chain(cells.into_iter().map(Topic::Cell))
+            .map(|topic| topics.entry(topic).or_insert_with(|| broadcast::channel(self.capacity).0).subscribe())
+            .collect();
+        Subscription { receivers: receivers }
+    }
+}
+
+impl NotificationSink for Hub {
+    fn deliver(&self, notification: &Notification) -> Result<(), Error> {
+        self.publish(
+            Topic::User(notification.recipient),
+            Envelope {
+                event: Event::Notification(notification.clone()),
+            },
+        );
+        Ok(())
+    }
+}
+
+// 当前用户的通道以及各个订阅单元的通道
+pub struct Subscription {
+    receivers: Vec<broadcast::Receiver<Arc<Envelope>>>,
+}
+
+impl Subscription {
+    // 返回 None 表示广播中心已关闭
+    pub async fn recv(&mut self) -> Option<Message> {
+        let (received, _, _) = select_all(self.receivers.iter_mut().map(|r| Box::pin(r.recv()))).await;
+        match received {
+            Ok(envelope) => Some(Message::Event(envelope)),
+            Err(RecvError::Lagged(n)) => Some(Message::Lagged(n)),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::notification::Kind;
+
+    fn notification_to(recipient: i32) -> Notification {
+        Notification {
+            id: 1,
+            recipient: recipient,
+            actor: Some(3),
+            kind: Kind::Comment,
+            location: Some(1),
+            target: Some(1),
+            content: "".into(),
+            is_read: false,
+            create_on: chrono::NaiveDate::from_ymd(2022, 7, 30).and_hms(0, 0, 0),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_notification_only_to_recipient() {
+        let hub = Hub::new(8);
+        let mut mine = hub.subscribe(1, HashSet::new());
+        let mut others = hub.subscribe(2, HashSet::new());
+        hub.deliver(&notification_to(1)).unwrap();
+        hub.deliver(&notification_to(2)).unwrap();
+        match mine.recv().await {
+            Some(Message::Event(env)) => assert!(matches!(&env.event, Event::Notification(n) if n.recipient == 1)),
+            _ => panic!("expect notification"),
+        }
+        match others.recv().await {
+            Some(Message::Event(env)) => assert!(matches!(&env.event, Event::Notification(n) if n.recipient == 2)),
+            _ => panic!("expect notification"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_cell_filter() {
+        let hub = Hub::new(8);
+        let cell = h3::index(36.1, 117.1, CELL_RESOLUTION);
+        let mut near = hub.subscribe(1, h3::k_ring(cell, 1).into_iter().filter(|c| *c > 0).collect());
+        let mut far = hub.subscribe(2, [h3::index(31.2, 121.5, CELL_RESOLUTION)].into_iter().collect());
+        let event = Event::Memory {
+            id: 1,
+            title: "".into(),
+            location: 1,
+            owner: 3,
+        };
+        hub.publish_at(36.1, 117.1, event);
+        hub.deliver(&notification_to(2)).unwrap();
+        assert!(matches!(near.recv().await, Some(Message::Event(env)) if env.event.name() == "memory"));
+        assert!(matches!(far.recv().await, Some(Message::Event(env)) if env.event.name() == "notification"));
+    }
+
+    #[actix_web::test]
+    async fn test_lagged() {
+        let hub = Hub::new(2);
+        let mut sub = hub.subscribe(1, HashSet::new());
+        for _ in 0..4 {
+            hub.deliver(&notification_to(1)).unwrap();
+        }
+        assert!(matches!(sub.recv().await, Some(Message::Lagged(2))));
+        assert!(matches!(sub.recv().await, Some(Message::Event(_))));
+    }
+
+    #[actix_web::test]
+    async fn test_busy_cell_not_lag_others() {
+        let hub = Hub::new(2);
+        let mut busy = hub.subscribe(1, [h3::index(36.1, 117.1, CELL_RESOLUTION)].into_iter().collect());
+        let mut quiet = hub.subscribe(2, [h3::index(31.2, 121.5, CELL_RESOLUTION)].into_iter().collect());
+        for id in 0..4 {
+            let event = Event::Memory {
+                id: id,
+                title: "".into(),
+                location: 1,
+                owner: 3,
+            };
+            hub.publish_at(36.1, 117.1, event);
+        }
+        hub.deliver(&notification_to(2)).unwrap();
+        assert!(matches!(busy.recv().await, Some(Message::Lagged(2))));
+        assert!(matches!(quiet.recv().await, Some(Message::Event(env)) if env.event.name() == "notification"));
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// EventSource 不能设置请求头, token 只能放在地址中, 而地址会被写入访问日志;
+// 所以先用 token 换取一次性的短期票据, 连接时只在地址中传递票据
+pub struct Tickets {
+    ttl: Duration,
+    tickets: Mutex<HashMap<String, Ticket>>,
+}
+
+struct Ticket {
+    uid: i32,
+    // 换取票据的 token 的过期时间 (unix 时间戳), 推送连接到期时断开
+    until: i64,
+    expires: Instant,
+}
+
+impl Tickets {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl: ttl,
+            tickets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 顺便清理过期未使用的票据
+    pub fn issue(&self, uid: i32, until: i64) -> String {
+        let now = Instant::now();
+        let ticket = Uuid::new_v4().to_string();
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, t| t.expires > now);
+        tickets.insert(
+            ticket.clone(),
+            Ticket {
+                uid: uid,
+                until: until,
+                expires: now + self.ttl,
+            },
+        );
+        ticket
+    }
+
+    // 票据只能使用一次, 返回用户和 token 的过期时间
+    pub fn redeem(&self, ticket: &str) -> Option<(i32, i64)> {
+        let t = self.tickets.lock().unwrap().remove(ticket)?;
+        if t.expires <= Instant::now() {
+            return None;
+        }
+        Some((t.uid, t.until))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redeem_once() {
+        let tickets = Tickets::new(Duration::from_secs(60));
+        let ticket = tickets.issue(1, 100);
+        assert_eq!(tickets.redeem(&ticket), Some((1, 100)));
+        assert_eq!(tickets.redeem(&ticket), None);
+        assert_eq!(tickets.redeem("unknown"), None);
+    }
+
+    #[test]
+    fn test_expired() {
+        let tickets = Tickets::new(Duration::from_millis(0));
+        let ticket = tickets.issue(1, 100);
+        assert_eq!(tickets.redeem(&ticket), None);
+    }
+}
@@ -11,3 +11,16 @@ impl<T> ListResponse<T> {
         Self { list, total }
     }
 }
+
+// 游标分页, next 为下一页的游标, 没有更多数据时为空
+#[derive(Debug, Serialize)]
+pub struct CursorResponse<T> {
+    list: Vec<T>,
+    next: Option<i32>,
+}
+
+impl<T> CursorResponse<T> {
+    pub fn new(list: Vec<T>, next: Option<i32>) -> Self {
+        Self { list, next }
+    }
+}
@@ -1,3 +1,76 @@
+table! {
+    activities (id) {
+        id -> Int4,
+        actor -> Int4,
+        kind -> Varchar,
+        location -> Int4,
+        target -> Int4,
+        create_on -> Timestamp,
+    }
+}
+
+table! {
+    blobs (id) {
+        id -> Int4,
+        hash -> Varchar,
+        storage_key -> Varchar,
+        size -> Int8,
+        refs -> Int4,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    categories (id) {
+        id -> Int4,
+        name -> Varchar,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    children (id) {
+        id -> Int4,
+        family -> Int4,
+        name -> Varchar,
+        birth_date -> Date,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    comment_replies (id) {
+        id -> Int4,
+        comment -> Int4,
+        user -> Int4,
+        parent -> Nullable<Int4>,
+        content -> Text,
+        is_official -> Bool,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    comment_upload_rels (id) {
+        id -> Int4,
+        comment -> Int4,
+        upload -> Int4,
+    }
+}
+
+table! {
+    comment_votes (id) {
+        id -> Int4,
+        comment -> Int4,
+        user -> Int4,
+        create_on -> Timestamp,
+    }
+}
+
 table! {
     comments (id) {
         id -> Int4,
@@ -7,6 +80,12 @@ table! {
         location -> Int4,
         create_on -> Timestamp,
         update_on -> Timestamp,
+        cleanliness -> Nullable<Int4>,
+        space -> Nullable<Int4>,
+        staff -> Nullable<Int4>,
+        accessibility -> Nullable<Int4>,
+        noise -> Nullable<Int4>,
+        helpful_count -> Int8,
     }
 }
 
@@ -42,6 +121,75 @@ table! {
     }
 }
 
+table! {
+    families (id) {
+        id -> Int4,
+        name -> Varchar,
+        owner -> Int4,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    family_invitations (id) {
+        id -> Int4,
+        family -> Int4,
+        inviter -> Int4,
+        invitee -> Int4,
+        status -> Varchar,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    family_members (id) {
+        id -> Int4,
+        family -> Int4,
+        user -> Int4,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    favorite_list_location_rels (id) {
+        id -> Int4,
+        list -> Int4,
+        location -> Int4,
+    }
+}
+
+table! {
+    favorite_lists (id) {
+        id -> Int4,
+        name -> Varchar,
+        owner -> Int4,
+        share_code -> Nullable<Varchar>,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    favorites (id) {
+        id -> Int4,
+        user -> Int4,
+        location -> Int4,
+        create_on -> Timestamp,
+    }
+}
+
+table! {
+    follows (id) {
+        id -> Int4,
+        follower -> Int4,
+        followee -> Int4,
+        create_on -> Timestamp,
+    }
+}
+
 table! {
     location_upload_rels (id) {
         id -> Int4,
@@ -62,6 +210,8 @@ table! {
         create_on -> Timestamp,
         update_on -> Timestamp,
         geo_index -> Bpchar,
+        min_age_months -> Nullable<Int4>,
+        max_age_months -> Nullable<Int4>,
     }
 }
 
@@ -74,6 +224,15 @@ table! {
         location -> Int4,
         create_on -> Timestamp,
         update_on -> Timestamp,
+        visibility -> Varchar,
+    }
+}
+
+table! {
+    memory_child_rels (id) {
+        id -> Int4,
+        memory -> Int4,
+        child -> Int4,
     }
 }
 
@@ -85,6 +244,21 @@ table! {
     }
 }
 
+table! {
+    notifications (id) {
+        id -> Int4,
+        recipient -> Int4,
+        actor -> Nullable<Int4>,
+        kind -> Varchar,
+        location -> Nullable<Int4>,
+        target -> Nullable<Int4>,
+        content -> Text,
+        is_read -> Bool,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
 table! {
     playings (id) {
         id -> Int4,
@@ -113,6 +287,61 @@ table! {
         location_id -> Int4,
         create_on -> Timestamp,
         update_on -> Timestamp,
+        rank_1 -> Int8,
+        rank_2 -> Int8,
+        rank_3 -> Int8,
+        rank_4 -> Int8,
+        rank_5 -> Int8,
+        score -> Float8,
+        cleanliness_total -> Int8,
+        cleanliness_count -> Int8,
+        space_total -> Int8,
+        space_count -> Int8,
+        staff_total -> Int8,
+        staff_count -> Int8,
+        accessibility_total -> Int8,
+        accessibility_count -> Int8,
+        noise_total -> Int8,
+        noise_count -> Int8,
+    }
+}
+
+table! {
+    upload_renditions (id) {
+        id -> Int4,
+        upload -> Int4,
+        variant -> Varchar,
+        fetch_code -> Varchar,
+        mime -> Varchar,
+        width -> Int4,
+        height -> Int4,
+        size -> Int8,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    upload_session_parts (id) {
+        id -> Int4,
+        session -> Int4,
+        position -> Int8,
+        size -> Int8,
+        fetch_code -> Varchar,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
+    }
+}
+
+table! {
+    upload_sessions (id) {
+        id -> Int4,
+        owner -> Int4,
+        size -> Int8,
+        received -> Int8,
+        upload -> Nullable<Int4>,
+        create_on -> Timestamp,
+        update_on -> Timestamp,
     }
 }
 
@@ -123,6 +352,14 @@ table! {
         owner -> Int4,
         create_on -> Timestamp,
         update_on -> Timestamp,
+        mime -> Varchar,
+        size -> Int8,
+        status -> Varchar,
+        hash -> Nullable<Varchar>,
+        media_type -> Varchar,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+        duration -> Nullable<Float8>,
     }
 }
 
@@ -136,39 +373,84 @@ table! {
         create_on -> Timestamp,
         update_on -> Timestamp,
         avatar -> Nullable<Int4>,
+        is_admin -> Bool,
     }
 }
 
+joinable!(activities -> locations (location));
+joinable!(activities -> users (actor));
+joinable!(children -> families (family));
+joinable!(comment_replies -> comments (comment));
+joinable!(comment_replies -> users (user));
+joinable!(comment_upload_rels -> comments (comment));
+joinable!(comment_upload_rels -> uploads (upload));
+joinable!(comment_votes -> comments (comment));
+joinable!(comment_votes -> users (user));
 joinable!(comments -> locations (location));
 joinable!(comments -> users (user));
 joinable!(eatings -> users (discoverer));
 joinable!(eatings_uploads -> eatings (eating_id));
 joinable!(eatings_uploads -> uploads (upload_id));
 joinable!(equipments -> locations (location));
+joinable!(families -> users (owner));
+joinable!(family_invitations -> families (family));
+joinable!(family_members -> families (family));
+joinable!(family_members -> users (user));
+joinable!(favorite_list_location_rels -> favorite_lists (list));
+joinable!(favorite_list_location_rels -> locations (location));
+joinable!(favorite_lists -> users (owner));
+joinable!(favorites -> locations (location));
+joinable!(favorites -> users (user));
 joinable!(location_upload_rels -> locations (location_id));
 joinable!(location_upload_rels -> uploads (upload_id));
 joinable!(locations -> users (discoverer));
 joinable!(memories -> locations (location));
 joinable!(memories -> users (owner));
+joinable!(memory_child_rels -> children (child));
+joinable!(memory_child_rels -> memories (memory));
 joinable!(memory_upload_rels -> memories (memory));
 joinable!(memory_upload_rels -> uploads (upload));
+joinable!(notifications -> locations (location));
 joinable!(playings -> users (discoverer));
 joinable!(playings_uploads -> playings (playing_id));
 joinable!(playings_uploads -> uploads (upload_id));
 joinable!(rank_aggregations -> locations (location_id));
+joinable!(upload_renditions -> uploads (upload));
+joinable!(upload_session_parts -> upload_sessions (session));
+joinable!(upload_sessions -> uploads (upload));
+joinable!(upload_sessions -> users (owner));
 
 allow_tables_to_appear_in_same_query!(
+    activities,
+    blobs,
+    categories,
+    children,
+    comment_replies,
+    comment_upload_rels,
+    comment_votes,
     comments,
     eatings,
     eatings_uploads,
     equipments,
+    families,
+    family_invitations,
+    family_members,
+    favorite_list_location_rels,
+    favorite_lists,
+    favorites,
+    follows,
     location_upload_rels,
     locations,
     memories,
+    memory_child_rels,
     memory_upload_rels,
+    notifications,
     playings,
     playings_uploads,
     rank_aggregations,
+    upload_renditions,
+    upload_session_parts,
+    upload_sessions,
     uploads,
     users,
 );
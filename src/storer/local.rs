@@ -1,92 +1,293 @@
-use crate::domain::upload::UploadStorer;
-use anyhow::Error;
-use bytes::Bytes;
-use futures::{Sink, Stream};
-use infer::get_from_path;
-use std::path;
+use crate::domain::upload::{ByteSink, ByteStream, UploadStorer};
+use anyhow::{Context as _, Error};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::{ready, Future, Sink, TryStreamExt};
+use std::io::{ErrorKind, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::{
-    fs::File,
-    io::{Read, Write},
-    task::{Context, Poll},
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
 };
+use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
-pub struct AsyncFile {
-    file: File,
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 64 * 1024;
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+// 写完之前的临时文件, 与正式文件在同一目录下以保证 rename 是原子的
+const TEMP_SUFFIX: &str = ".part";
+
+type Pending = Pin<Box<dyn Future<Output = Result<fs::File, Error>>>>;
+
+// 先写入临时文件, 关闭时 fsync 之后再改名, 进程崩溃时不会在有效的 fetch code 下留下不完整的文件
+pub struct LocalFile {
+    file: Option<fs::File>,
+    pending: Option<Pending>,
+    buffer: BytesMut,
+    buffer_size: usize,
+    temp: PathBuf,
+    target: PathBuf,
+    closed: bool,
+    // 写入或改名失败之后的错误, 之后的操作都返回这个错误
+    failed: Option<String>,
 }
 
-impl AsyncFile {
-    pub fn new(file: File) -> Self {
-        Self { file }
+impl LocalFile {
+    // 当前的写入, fsync 等操作持有文件, 完成之后归还
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if let Some(failed) = &self.failed {
+            return Poll::Ready(Err(Error::msg(failed.clone())));
+        }
+        if let Some(pending) = self.pending.as_mut() {
+            let file = ready!(pending.as_mut().poll(cx));
+            self.pending = None;
+            match file {
+                Ok(file) => self.file = Some(file),
+                Err(e) => {
+                    self.failed = Some(format!("{:#}", e));
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_write(&mut self) -> Result<(), Error> {
+        let mut file = self.file.take().context("file is not writable after a failed operation")?;
+        let data = self.buffer.split().freeze();
+        self.pending = Some(Box::pin(async move {
+            file.write_all(&data).await?;
+            Ok(file)
+        }));
+        Ok(())
     }
 }
 
-impl Sink<Bytes> for AsyncFile {
+impl Sink<Bytes> for LocalFile {
     type Error = Error;
-    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if this.buffer.len() >= this.buffer_size {
+            this.start_write()?;
+            ready!(this.poll_pending(cx))?;
+        }
         Poll::Ready(Ok(()))
     }
 
-    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
-        self.file.write_all(&item)?;
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        self.get_mut().buffer.extend_from_slice(&item);
         Ok(())
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(self.file.flush().map_err(|e| Error::from(e)))
-    }
-
-    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if !this.buffer.is_empty() {
+            this.start_write()?;
+            ready!(this.poll_pending(cx))?;
+        }
         Poll::Ready(Ok(()))
     }
-}
 
-impl Stream for AsyncFile {
-    type Item = Result<Bytes, Error>;
-
-    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut buffer = vec![0u8; 1024];
-        match self.file.read(&mut buffer) {
-            Err(e) => return Poll::Ready(Some(Err(Error::from(e)))),
-            Ok(n) => {
-                if n == 0 {
-                    return Poll::Ready(None);
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        let this = self.get_mut();
+        if !this.closed {
+            this.closed = true;
+            let file = this.file.take().context("file is not writable after a failed operation")?;
+            let (temp, target) = (this.temp.clone(), this.target.clone());
+            this.pending = Some(Box::pin(async move {
+                file.sync_all().await?;
+                fs::rename(&temp, &target).await?;
+                // 改名本身也需要落盘
+                if let Some(dir) = target.parent() {
+                    fs::File::open(dir).await?.sync_all().await?;
                 }
-                buffer.resize(n, 0);
-                return Poll::Ready(Some(Ok(buffer.into())));
-            }
+                Ok(file)
+            }));
         }
+        this.poll_pending(cx)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct LocalStore {
     path: String,
+    read_buffer_size: usize,
+    write_buffer_size: usize,
 }
 
 impl LocalStore {
     pub fn new(path: &str) -> Self {
-        Self { path: path.to_owned() }
+        Self {
+            path: path.to_owned(),
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+        }
+    }
+
+    pub fn with_buffer_sizes(self, read_buffer_size: usize, write_buffer_size: usize) -> Self {
+        Self {
+            read_buffer_size: read_buffer_size,
+            write_buffer_size: write_buffer_size,
+            ..self
+        }
+    }
+
+    fn target(&self, fetch_code: &str) -> PathBuf {
+        Path::new(&self.path).join(fetch_code)
+    }
+
+    fn temp(&self, fetch_code: &str) -> PathBuf {
+        Path::new(&self.path).join(format!("{}{}", fetch_code, TEMP_SUFFIX))
     }
 }
-impl UploadStorer<AsyncFile, AsyncFile> for LocalStore {
-    fn store(&self) -> Result<(AsyncFile, String), Error> {
+
+// 打开文件只涉及元数据, 直接同步完成, 以便尽早返回错误; 读写都在 tokio 的阻塞线程池中进行
+#[async_trait(?Send)]
+impl UploadStorer<ByteStream, ByteSink> for LocalStore {
+    fn store(&self) -> Result<(ByteSink, String), Error> {
         let name = Uuid::new_v4().to_string();
-        let file = File::create(path::Path::new(&self.path).join(&name))?;
-        Ok((AsyncFile::new(file), name))
+        let temp = self.temp(&name);
+        let file = std::fs::File::create(&temp)?;
+        Ok((
+            Box::pin(LocalFile {
+                file: Some(fs::File::from_std(file)),
+                pending: None,
+                buffer: BytesMut::with_capacity(self.write_buffer_size),
+                buffer_size: self.write_buffer_size,
+                temp: temp,
+                target: self.target(&name),
+                closed: false,
+                failed: None,
+            }),
+            name,
+        ))
+    }
+
+    fn get(&self, fetch_code: &str) -> Result<ByteStream, Error> {
+        let file = fs::File::from_std(std::fs::File::open(self.target(fetch_code))?);
+        Ok(Box::pin(ReaderStream::with_capacity(file, self.read_buffer_size).map_err(Error::from)))
     }
 
-    fn get(&self, fetch_code: &str) -> Result<AsyncFile, Error> {
-        let file = File::open(path::Path::new(&self.path).join(fetch_code))?;
-        Ok(AsyncFile::new(file))
+    fn get_range(&self, fetch_code: &str, start: u64, end: u64) -> Result<ByteStream, Error> {
+        let mut file = std::fs::File::open(self.target(fetch_code))?;
+        file.seek(SeekFrom::Start(start))?;
+        let reader = fs::File::from_std(file).take(end - start + 1);
+        Ok(Box::pin(ReaderStream::with_capacity(reader, self.read_buffer_size).map_err(Error::from)))
     }
 
-    fn mime(&self, fetch_code: &str) -> Result<String, Error> {
-        let mime = get_from_path(path::Path::new(&self.path).join(fetch_code))?;
-        if let Some(t) = mime {
-            return Ok(t.to_string());
+    // 没有写完的文件只有临时文件
+    async fn delete(&self, fetch_code: &str) -> Result<(), Error> {
+        for path in [self.target(fetch_code), self.temp(fetch_code)] {
+            match fs::remove_file(&path).await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
         }
-        Ok("".into())
+        Err(Error::msg(format!("upload {} not found", fetch_code)))
+    }
+
+    // 正在写入的临时文件也以 .part 结尾, 只清理超过 older_than 没有修改的
+    async fn sweep_temp(&self, older_than: Duration, dry_run: bool) -> Result<usize, Error> {
+        let mut entries = fs::read_dir(&self.path).await.context("failed to list upload directory")?;
+        let mut count = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_name().to_string_lossy().ends_with(TEMP_SUFFIX) {
+                continue;
+            }
+            let modified = entry.metadata().await?.modified()?;
+            if SystemTime::now().duration_since(modified).unwrap_or_default() < older_than {
+                continue;
+            }
+            if !dry_run {
+                match fs::remove_file(entry.path()).await {
+                    Ok(()) => {}
+                    // 期间写完改名或者被删除
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::SinkExt;
+
+    fn temp_dir() -> String {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir).unwrap();
+        dir.to_str().unwrap().to_owned()
+    }
+
+    #[actix_web::test]
+    async fn test_store_and_get() {
+        let store = LocalStore::new(&temp_dir()).with_buffer_sizes(4, 5);
+        let (mut sink, code) = store.store().unwrap();
+        for chunk in ["hello", " ", "async", " world"] {
+            sink.send(Bytes::from(chunk)).await.unwrap();
+        }
+        // 关闭之前只有临时文件
+        assert!(!store.target(&code).exists());
+        assert!(store.temp(&code).exists());
+        sink.close().await.unwrap();
+        assert!(!store.temp(&code).exists());
+        let chunks: Vec<Bytes> = store.get(&code).unwrap().try_collect().await.unwrap();
+        assert!(chunks.iter().all(|c| c.len() <= 4));
+        assert_eq!(chunks.concat(), b"hello async world");
+        let range: Vec<Bytes> = store.get_range(&code, 6, 10).unwrap().try_collect().await.unwrap();
+        assert_eq!(range.concat(), b"async");
+        store.delete(&code).await.unwrap();
+        assert!(store.get(&code).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_delete_unfinished() {
+        let store = LocalStore::new(&temp_dir());
+        let (mut sink, code) = store.store().unwrap();
+        sink.send(Bytes::from("partial")).await.unwrap();
+        store.delete(&code).await.unwrap();
+        assert!(!store.temp(&code).exists());
+        assert!(store.delete(&code).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_close_error_sticky() {
+        let dir = temp_dir();
+        let store = LocalStore::new(&dir);
+        let (mut sink, _) = store.store().unwrap();
+        sink.send(Bytes::from("data")).await.unwrap();
+        // 目录不存在时改名失败
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(sink.close().await.is_err());
+        assert!(sink.close().await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_sweep_temp() {
+        let store = LocalStore::new(&temp_dir());
+        let (mut done, code) = store.store().unwrap();
+        done.send(Bytes::from("done")).await.unwrap();
+        done.close().await.unwrap();
+        let (mut stale, stale_code) = store.store().unwrap();
+        stale.send(Bytes::from("stale")).await.unwrap();
+        assert_eq!(store.sweep_temp(Duration::from_secs(3600), false).await.unwrap(), 0);
+        assert_eq!(store.sweep_temp(Duration::ZERO, true).await.unwrap(), 1);
+        assert!(store.temp(&stale_code).exists());
+        assert_eq!(store.sweep_temp(Duration::ZERO, false).await.unwrap(), 1);
+        assert!(!store.temp(&stale_code).exists());
+        assert!(store.target(&code).exists());
     }
 }
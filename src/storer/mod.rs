@@ -1 +1,33 @@
 pub mod local;
+pub mod s3;
+
+use crate::domain::upload::DynStorer;
+use chrono::Duration;
+use local::LocalStore;
+use s3::{S3Config, S3Store};
+use std::sync::Arc;
+
+// STORAGE=s3 时使用对象存储, 否则保存在 UPLOAD_DIR 目录下, 读写缓冲区大小可以分别配置
+pub fn from_env() -> Arc<DynStorer> {
+    match dotenv::var("STORAGE").as_deref() {
+        Ok("s3") => Arc::new(
+            S3Store::new(S3Config {
+                endpoint: dotenv::var("S3_ENDPOINT").expect("S3_ENDPOINT environment variable not exists"),
+                bucket: dotenv::var("S3_BUCKET").expect("S3_BUCKET environment variable not exists"),
+                region: dotenv::var("S3_REGION").unwrap_or("us-east-1".into()),
+                access_key: dotenv::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY environment variable not exists"),
+                secret_key: dotenv::var("S3_SECRET_KEY").expect("S3_SECRET_KEY environment variable not exists"),
+                presign_expires: Duration::seconds(dotenv::var("S3_PRESIGN_EXPIRES").map_or(3600, |v| v.parse::<i64>().expect("S3_PRESIGN_EXPIRES environment variable must be integer"))),
+            })
+            .expect("failed to create s3 storage"),
+        ),
+        _ => Arc::new(LocalStore::new(&dotenv::var("UPLOAD_DIR").expect("UPLOAD_DIR environment variable not exists")).with_buffer_sizes(
+            dotenv::var("UPLOAD_READ_BUFFER_SIZE").map_or(local::DEFAULT_READ_BUFFER_SIZE, |v| {
+                v.parse::<usize>().expect("UPLOAD_READ_BUFFER_SIZE environment variable must be integer")
+            }),
+            dotenv::var("UPLOAD_WRITE_BUFFER_SIZE").map_or(local::DEFAULT_WRITE_BUFFER_SIZE, |v| {
+                v.parse::<usize>().expect("UPLOAD_WRITE_BUFFER_SIZE environment variable must be integer")
+            }),
+        )),
+    }
+}
@@ -47,12 +47,15 @@ impl Tokener for JWT {
         Ok(s)
     }
     fn validate(&self, token: &str) -> Result<i32, anyhow::Error> {
+        Ok(self.validate_until(token)?.0)
+    }
+    fn validate_until(&self, token: &str) -> Result<(i32, i64), anyhow::Error> {
         let TokenData { header, claims } =
             decode::<Claims>(token, &DecodingKey::from_secret(&self.secret.as_bytes()), &Validation::new(jsonwebtoken::Algorithm::HS256)).context("failed to valid jwt token")?;
         if claims.exp < chrono::Local::now().timestamp() as usize {
             return Err(anyhow::Error::msg("expired token").context("failed to valid jwt token"));
         }
-        Ok(claims.uid)
+        Ok((claims.uid, claims.exp as i64))
     }
 }
 
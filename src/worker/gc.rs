@@ -0,0 +1,385 @@
+use crate::dao::upload as upload_dao;
+use crate::domain::upload::UploadStorer;
+use crate::models::{Upload, UploadRendition, UploadSession};
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use diesel::{
+    r2d2::{ConnectionManager, Pool},
+    PgConnection,
+};
+use futures::{Sink, Stream};
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const BATCH_SIZE: i64 = 100;
+
+// 回收用到的记录操作, 与存储对象的删除分开, 测试时可以替换为内存实现
+pub trait Catalog {
+    // 超过 grace 没有被引用的上传, 按 id 分页
+    fn orphans(&self, grace: chrono::Duration, after: i32, limit: i64) -> Result<Vec<Upload>, Error>;
+    fn renditions_of(&self, ids: &Vec<i32>) -> Result<Vec<UploadRendition>, Error>;
+    // 再次检查引用后删除, 返回实际删除的 id 以及不再被引用的原始文件和各个尺寸
+    fn delete_orphans(&self, ids: &Vec<i32>) -> Result<(Vec<i32>, Vec<String>), Error>;
+    fn blob_refs(&self, hashes: &Vec<String>) -> Result<Vec<(String, i32)>, Error>;
+    fn stale_sessions(&self, grace: chrono::Duration, limit: i64) -> Result<Vec<UploadSession>, Error>;
+    // 会话已经收到的分段的 fetch code 和大小
+    fn session_parts(&self, session: i32) -> Result<Vec<(String, i64)>, Error>;
+    fn delete_session(&self, session: i32) -> Result<Vec<(String, i64)>, Error>;
+}
+
+// 每次操作单独取连接, 删除存储对象期间不占用连接
+impl Catalog for Pool<ConnectionManager<PgConnection>> {
+    fn orphans(&self, grace: chrono::Duration, after: i32, limit: i64) -> Result<Vec<Upload>, Error> {
+        upload_dao::orphans(&self.get().context("failed to collect orphan uploads")?, grace, after, limit)
+    }
+
+    fn renditions_of(&self, ids: &Vec<i32>) -> Result<Vec<UploadRendition>, Error> {
+        upload_dao::renditions_of(&self.get().context("failed to collect orphan uploads")?, ids)
+    }
+
+    fn delete_orphans(&self, ids: &Vec<i32>) -> Result<(Vec<i32>, Vec<String>), Error> {
+        upload_dao::delete_orphans(&self.get().context("failed to collect orphan uploads")?, ids)
+    }
+
+    fn blob_refs(&self, hashes: &Vec<String>) -> Result<Vec<(String, i32)>, Error> {
+        upload_dao::blob_refs(&self.get().context("failed to collect orphan uploads")?, hashes)
+    }
+
+    fn stale_sessions(&self, grace: chrono::Duration, limit: i64) -> Result<Vec<UploadSession>, Error> {
+        upload_dao::stale_sessions(&self.get().context("failed to collect upload sessions")?, grace, limit)
+    }
+
+    fn session_parts(&self, session: i32) -> Result<Vec<(String, i64)>, Error> {
+        upload_dao::session_parts(&self.get().context("failed to collect upload sessions")?, session)
+    }
+
+    fn delete_session(&self, session: i32) -> Result<Vec<(String, i64)>, Error> {
+        upload_dao::delete_session(&self.get().context("failed to collect upload sessions")?, session)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub dry_run: bool,
+    // 删除的 (试运行时为将要删除的) 上传
+    pub uploads: Vec<i32>,
+    // 原始文件和各个尺寸的存储对象
+    pub files: usize,
+    pub bytes: i64,
+    // 超过宽限期没有更新的可续传上传会话, 以及其中已经收到的分段
+    pub sessions: Vec<i32>,
+    // 进程崩溃时留下的临时文件, 没有对应的记录
+    pub temp_files: usize,
+    // 记录已经删除但存储对象删除失败的 fetch code, 需要人工清理
+    pub failed: Vec<String>,
+}
+
+// 上传先于引用它的地点或回忆创建, 放弃填写的表单会留下没有引用的上传; 超过宽限期之后连同存储对象一起删除
+#[derive(Clone)]
+pub struct GarbageCollector {
+    grace: chrono::Duration,
+}
+
+impl GarbageCollector {
+    pub fn new(grace: chrono::Duration) -> Self {
+        Self { grace: grace }
+    }
+
+    pub async fn run<S, SM, SK>(self, pool: Pool<ConnectionManager<PgConnection>>, storer: S, interval: Duration)
+    where
+        S: UploadStorer<SM, SK>,
+        SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+        SK: Sink<Bytes, Error = Error> + Unpin,
+    {
+        loop {
+            tokio::time::sleep(interval).await;
+            match self.collect(&pool, &storer, false).await {
+                Ok(report) if !report.uploads.is_empty() || !report.sessions.is_empty() || report.temp_files > 0 => log::info!("collected orphan uploads: {:?}", report),
+                Ok(_) => {}
+                Err(e) => log::error!("failed to collect orphan uploads: {:?}", e),
+            }
+        }
+    }
+
+    pub async fn collect<C, S, SM, SK>(&self, catalog: &C, storer: &S, dry_run: bool) -> Result<Report, Error>
+    where
+        C: Catalog + ?Sized,
+        S: UploadStorer<SM, SK> + ?Sized,
+        SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+        SK: Sink<Bytes, Error = Error> + Unpin,
+    {
+        let mut report = Report {
+            dry_run: dry_run,
+            ..Default::default()
+        };
+        let mut after = 0;
+        loop {
+            let orphans = catalog.orphans(self.grace, after, BATCH_SIZE)?;
+            let last = match orphans.last() {
+                Some(u) => u.id,
+                None => break,
+            };
+            after = last;
+            let ids: Vec<i32> = orphans.iter().map(|u| u.id).collect();
+            let renditions = catalog.renditions_of(&ids)?;
+            // 先删除记录, 期间被引用的上传会被跳过, 只删除实际删除了记录的存储对象
+            let (deleted, released) = if dry_run {
+                (ids, releasable(catalog, &orphans, &renditions)?)
+            } else {
+                catalog.delete_orphans(&ids)?
+            };
+            // 内容相同的上传共享原始文件和各个尺寸, 只在不再被引用时删除
+            let objects: Vec<(String, i64)> = orphans
+                .iter()
+                .map(|u| (u.fetch_code.clone(), u.size))
+                .chain(renditions.into_iter().map(|r| (r.fetch_code, r.size)))
+                .filter(|(code, _)| released.contains(code))
+                .unique_by(|(code, _)| code.clone())
+                .collect();
+            delete_objects(storer, objects, &mut report).await;
+            report.uploads.extend(deleted);
+        }
+        self.collect_sessions(catalog, storer, &mut report).await?;
+        report.temp_files = storer.sweep_temp(self.grace.to_std().unwrap_or_default(), dry_run).await?;
+        Ok(report)
+    }
+
+    async fn collect_sessions<C, S, SM, SK>(&self, catalog: &C, storer: &S, report: &mut Report) -> Result<(), Error>
+    where
+        C: Catalog + ?Sized,
+        S: UploadStorer<SM, SK> + ?Sized,
+        SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+        SK: Sink<Bytes, Error = Error> + Unpin,
+    {
+        loop {
+            // 试运行时不删除, 一次取出全部; 否则删除之后剩下的会话在下一批中
+            let sessions = catalog.stale_sessions(self.grace, if report.dry_run { i64::MAX } else { BATCH_SIZE })?;
+            if sessions.is_empty() {
+                return Ok(());
+            }
+            for session in sessions.iter() {
+                let parts = if report.dry_run {
+                    catalog.session_parts(session.id)?
+                } else {
+                    catalog.delete_session(session.id)?
+                };
+                report.sessions.push(session.id);
+                delete_objects(storer, parts, report).await;
+            }
+            if report.dry_run {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// 计入报告, 试运行时不删除; 删除失败的记录在报告中
+async fn delete_objects<S, SM, SK>(storer: &S, objects: Vec<(String, i64)>, report: &mut Report)
+where
+    S: UploadStorer<SM, SK> + ?Sized,
+    SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+    SK: Sink<Bytes, Error = Error> + Unpin,
+{
+    for (fetch_code, size) in objects {
+        report.files += 1;
+        report.bytes += size;
+        if report.dry_run {
+            continue;
+        }
+        if let Err(e) = storer.delete(&fetch_code).await {
+            log::warn!("failed to delete stored object {}: {:?}", fetch_code, e);
+            report.failed.push(fetch_code);
+        }
+    }
+}
+
+// 试运行时按当前的引用计数估算会被删除的原始文件, 原始文件会被删除的上传的各个尺寸也会被删除
+fn releasable<C: Catalog + ?Sized>(catalog: &C, orphans: &Vec<Upload>, renditions: &Vec<UploadRendition>) -> Result<Vec<String>, Error> {
+    let hashes: Vec<String> = orphans.iter().filter_map(|u| u.hash.clone()).collect();
+    let refs: HashMap<String, i32> = catalog.blob_refs(&hashes)?.into_iter().collect();
+    let released: Vec<&Upload> = orphans
+        .iter()
+        .filter(|u| match &u.hash {
+            Some(hash) => refs.get(hash).map_or(true, |r| *r as usize <= hashes.iter().filter(|h| *h == hash).count()),
+            None => true,
+        })
+        .collect();
+    let codes = renditions.iter().filter(|r| released.iter().any(|u| u.id == r.upload)).map(|r| r.fetch_code.clone());
+    Ok(released.iter().map(|u| u.fetch_code.clone()).chain(codes).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::upload::test::MemoryStore;
+    use crate::models::{MediaType, UploadStatus, Variant};
+    use std::cell::RefCell;
+
+    // uploads 中的上传都已经超过宽限期且没有引用, referenced 中的上传在回收期间被引用; renditions 包括仍在使用的上传的尺寸
+    #[derive(Default)]
+    struct MemoryCatalog {
+        uploads: RefCell<Vec<Upload>>,
+        renditions: RefCell<Vec<UploadRendition>>,
+        // 摘要到引用计数
+        blobs: RefCell<HashMap<String, i32>>,
+        sessions: RefCell<Vec<(UploadSession, Vec<(String, i64)>)>>,
+        referenced: Vec<i32>,
+    }
+
+    impl Catalog for MemoryCatalog {
+        fn orphans(&self, _: chrono::Duration, after: i32, limit: i64) -> Result<Vec<Upload>, Error> {
+            Ok(self.uploads.borrow().iter().filter(|u| u.id > after).take(limit as usize).cloned().collect())
+        }
+
+        fn renditions_of(&self, ids: &Vec<i32>) -> Result<Vec<UploadRendition>, Error> {
+            Ok(self.renditions.borrow().iter().filter(|r| ids.contains(&r.upload)).cloned().collect())
+        }
+
+        fn delete_orphans(&self, ids: &Vec<i32>) -> Result<(Vec<i32>, Vec<String>), Error> {
+            let mut uploads = self.uploads.borrow_mut();
+            let (deleted, kept): (Vec<Upload>, Vec<Upload>) = uploads.drain(..).partition(|u| ids.contains(&u.id) && !self.referenced.contains(&u.id));
+            *uploads = kept;
+            let mut released = Vec::new();
+            for u in deleted.iter() {
+                let hash = u.hash.clone().unwrap();
+                let mut blobs = self.blobs.borrow_mut();
+                let refs = blobs.get_mut(&hash).unwrap();
+                *refs -= 1;
+                if *refs == 0 {
+                    blobs.remove(&hash);
+                    released.push(u.fetch_code.clone());
+                }
+            }
+            let ids: Vec<i32> = deleted.iter().map(|u| u.id).collect();
+            let mut renditions = self.renditions.borrow_mut();
+            let (removed, kept): (Vec<UploadRendition>, Vec<UploadRendition>) = renditions.drain(..).partition(|r| ids.contains(&r.upload));
+            *renditions = kept;
+            released.extend(removed.into_iter().map(|r| r.fetch_code).filter(|code| !renditions.iter().any(|r| &r.fetch_code == code)));
+            Ok((ids, released))
+        }
+
+        fn blob_refs(&self, hashes: &Vec<String>) -> Result<Vec<(String, i32)>, Error> {
+            Ok(self.blobs.borrow().iter().filter(|(h, _)| hashes.contains(h)).map(|(h, r)| (h.clone(), *r)).collect())
+        }
+
+        fn stale_sessions(&self, _: chrono::Duration, limit: i64) -> Result<Vec<UploadSession>, Error> {
+            Ok(self.sessions.borrow().iter().take(limit as usize).map(|(s, _)| s.clone()).collect())
+        }
+
+        fn session_parts(&self, session: i32) -> Result<Vec<(String, i64)>, Error> {
+            Ok(self.sessions.borrow().iter().find(|(s, _)| s.id == session).map(|(_, parts)| parts.clone()).unwrap_or_default())
+        }
+
+        fn delete_session(&self, session: i32) -> Result<Vec<(String, i64)>, Error> {
+            let parts = self.session_parts(session)?;
+            self.sessions.borrow_mut().retain(|(s, _)| s.id != session);
+            Ok(parts)
+        }
+    }
+
+    fn now() -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd(2022, 8, 10).and_hms(0, 0, 0)
+    }
+
+    fn upload(id: i32, fetch_code: &str, hash: &str, size: i64) -> Upload {
+        Upload {
+            id: id,
+            fetch_code: fetch_code.into(),
+            owner: 1,
+            create_on: now(),
+            update_on: now(),
+            mime: "image/png".into(),
+            size: size,
+            status: UploadStatus::Ready,
+            hash: Some(hash.into()),
+            media_type: MediaType::Image,
+            width: None,
+            height: None,
+            duration: None,
+        }
+    }
+
+    fn rendition(upload: i32, fetch_code: &str, size: i64) -> UploadRendition {
+        UploadRendition {
+            id: upload,
+            upload: upload,
+            variant: Variant::Thumbnail,
+            fetch_code: fetch_code.into(),
+            mime: "image/webp".into(),
+            width: 10,
+            height: 10,
+            size: size,
+            create_on: now(),
+            update_on: now(),
+        }
+    }
+
+    fn catalog() -> MemoryCatalog {
+        MemoryCatalog {
+            // 1 与仍在使用的 4 内容相同, 共享原始文件和尺寸; 3 在回收期间被引用
+            uploads: RefCell::new(vec![upload(1, "a", "h1", 100), upload(2, "b", "h2", 200), upload(3, "c", "h3", 300)]),
+            renditions: RefCell::new(vec![rendition(1, "a-t", 10), rendition(2, "b-t", 20), rendition(4, "a-t", 10)]),
+            blobs: RefCell::new([("h1".to_owned(), 2), ("h2".to_owned(), 1), ("h3".to_owned(), 1)].into_iter().collect()),
+            sessions: RefCell::new(vec![(
+                UploadSession {
+                    id: 1,
+                    owner: 1,
+                    size: 10,
+                    received: 10,
+                    upload: None,
+                    create_on: now(),
+                    update_on: now(),
+                },
+                vec![("p1".to_owned(), 5), ("p2".to_owned(), 5)],
+            )]),
+            referenced: vec![3],
+        }
+    }
+
+    fn store(codes: &[&str]) -> MemoryStore {
+        let store = MemoryStore::default();
+        store.files.lock().unwrap().extend(codes.iter().map(|c| (c.to_string(), Vec::new())));
+        store
+    }
+
+    fn stored(store: &MemoryStore) -> Vec<String> {
+        store.files.lock().unwrap().keys().cloned().sorted().collect()
+    }
+
+    #[actix_web::test]
+    async fn test_collect_dry_run() {
+        let (catalog, store) = (catalog(), store(&["a", "b", "c", "a-t", "b-t", "p1", "p2"]));
+        let gc = GarbageCollector::new(chrono::Duration::zero());
+        let report = gc.collect(&catalog, &store, true).await.unwrap();
+        // 试运行不知道期间被引用的上传, 按当前的引用计数估算
+        assert!(report.dry_run);
+        assert_eq!(report.uploads, vec![1, 2, 3]);
+        assert_eq!(report.sessions, vec![1]);
+        assert_eq!(report.files, 5);
+        assert_eq!(report.bytes, 200 + 300 + 20 + 5 + 5);
+        assert!(report.failed.is_empty());
+        assert_eq!(stored(&store).len(), 7);
+        assert_eq!(catalog.uploads.borrow().len(), 3);
+        assert_eq!(catalog.sessions.borrow().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_collect() {
+        // p2 已经不在存储中
+        let (catalog, store) = (catalog(), store(&["a", "b", "c", "a-t", "b-t", "p1"]));
+        let gc = GarbageCollector::new(chrono::Duration::zero());
+        let report = gc.collect(&catalog, &store, false).await.unwrap();
+        assert!(!report.dry_run);
+        assert_eq!(report.uploads, vec![1, 2]);
+        assert_eq!(report.sessions, vec![1]);
+        assert_eq!(report.files, 4);
+        assert_eq!(report.bytes, 200 + 20 + 5 + 5);
+        assert_eq!(report.failed, vec!["p2".to_owned()]);
+        assert_eq!(stored(&store), vec!["a".to_owned(), "a-t".to_owned(), "c".to_owned()]);
+        assert_eq!(*catalog.blobs.borrow(), [("h1".to_owned(), 1), ("h3".to_owned(), 1)].into_iter().collect());
+        let report = gc.collect(&catalog, &store, false).await.unwrap();
+        assert!(report.uploads.is_empty() && report.sessions.is_empty() && report.files == 0);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::dao::upload as upload_dao;
+use crate::domain::{
+    media::{self, MediaProcessor, TempFile},
+    rendition::{self, Rendition},
+    upload::UploadStorer,
+};
+use crate::models::{MediaType, Upload, UploadRenditionInsertion, Variant};
+use actix_web::rt::task::spawn_blocking;
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use diesel::{
+    r2d2::{ConnectionManager, Pool},
+    PgConnection,
+};
+use futures::{future, stream, Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{fs, io::AsyncWriteExt, sync::Notify};
+use tokio_util::io::ReaderStream;
+
+const BATCH_SIZE: i64 = 10;
+// 即使没有被唤醒也定期检查一次, 避免漏掉
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+// 在后台为上传的图片和视频去除元数据并生成各个尺寸, 待处理的上传记录在数据库中, 重启之后会继续处理
+#[derive(Clone)]
+pub struct RenditionWorker {
+    notify: Arc<Notify>,
+    processor: Arc<dyn MediaProcessor>,
+    // 视频的最大时长, 单位为秒
+    max_video_duration: f64,
+}
+
+// 只有文件本身无法处理时才标记为失败
+enum Failure {
+    Invalid(Error),
+    Storage(Error),
+}
+
+// 生成的文件, 视频在临时文件中
+enum Body {
+    Data(Vec<u8>),
+    File(PathBuf),
+}
+
+// 处理之后读取到的尺寸和时长
+struct Metadata {
+    width: i32,
+    height: i32,
+    duration: Option<f64>,
+}
+
+impl RenditionWorker {
+    pub fn new(processor: Arc<dyn MediaProcessor>, max_video_duration: f64) -> Self {
+        Self {
+            notify: Arc::default(),
+            processor: processor,
+            max_video_duration: max_video_duration,
+        }
+    }
+
+    // 有新的上传时调用
+    pub fn wake(&self) {
+        self.notify.notify_one();
+    }
+
+    pub async fn run<S, SM, SK>(self, pool: Pool<ConnectionManager<PgConnection>>, storer: S)
+    where
+        S: UploadStorer<SM, SK>,
+        SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+        SK: Sink<Bytes, Error = Error> + Unpin,
+    {
+        loop {
+            if let Err(e) = self.process_pending(&pool, &storer).await {
+                log::error!("failed to process uploads: {:?}", e);
+            }
+            let _ = tokio::time::timeout(POLL_INTERVAL, self.notify.notified()).await;
+        }
+    }
+}
+
+impl RenditionWorker {
+    async fn process_pending<S, SM, SK>(&self, pool: &Pool<ConnectionManager<PgConnection>>, storer: &S) -> Result<(), Error>
+    where
+        S: UploadStorer<SM, SK>,
+        SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+        SK: Sink<Bytes, Error = Error> + Unpin,
+    {
+        let mut after = 0;
+        loop {
+            let conn = pool.get().context("failed to process uploads")?;
+            let uploads = upload_dao::pending(&conn, after, BATCH_SIZE)?;
+            let last = match uploads.last() {
+                Some(u) => u.id,
+                None => return Ok(()),
+            };
+            after = last;
+            for upload in uploads {
+                // 内容相同的上传已经处理过时不再重复生成
+                if upload_dao::share_renditions(&conn, &upload)? {
+                    continue;
+                }
+                match self.process(&upload, storer).await {
+                    Ok((renditions, meta)) => {
+                        let raw = upload_dao::complete(&conn, &upload, renditions, meta.width, meta.height, meta.duration)?;
+                        if let Some(raw) = raw {
+                            if let Err(e) = storer.delete(&raw).await {
+                                log::warn!("failed to delete raw upload {}: {:?}", raw, e);
+                            }
+                        }
+                    }
+                    // 无法处理的文件不能读取, 也不再重试
+                    Err(Failure::Invalid(e)) => {
+                        log::warn!("failed to process upload {}: {:?}", upload.id, e);
+                        upload_dao::fail(&conn, upload.id)?;
+                    }
+                    // 存储暂时不可用, 留在 pending 中下次再处理
+                    Err(Failure::Storage(e)) => log::error!("failed to read or store upload {}: {:?}", upload.id, e),
+                }
+            }
+        }
+    }
+
+    async fn process<S, SM, SK>(&self, upload: &Upload, storer: &S) -> Result<(Vec<UploadRenditionInsertion>, Metadata), Failure>
+    where
+        S: UploadStorer<SM, SK>,
+        SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+        SK: Sink<Bytes, Error = Error> + Unpin,
+    {
+        // 解码和编码比较耗时, 放到阻塞线程池中执行; 视频可能很大, 通过临时文件交给 processor, 处理之后的视频也从文件写入存储
+        let (renditions, meta, _output) = match upload.media_type {
+            MediaType::Image => {
+                let mut data = Vec::new();
+                let mut stream = storer.get(&upload.fetch_code).map_err(Failure::Storage)?;
+                while let Some(bs) = stream.next().await {
+                    data.extend_from_slice(&bs.map_err(Failure::Storage)?);
+                }
+                let renditions = spawn_blocking(move || rendition::render(&data))
+                    .await
+                    .map_err(|e| Failure::Invalid(e.into()))?
+                    .map_err(Failure::Invalid)?;
+                let meta = metadata(&renditions).map_err(Failure::Invalid)?;
+                (renditions.into_iter().map(|r| (r.variant, r.mime, r.width, r.height, Body::Data(r.data))).collect(), meta, None)
+            }
+            MediaType::Video => {
+                let (input, output) = (TempFile::new(), TempFile::new());
+                download(storer, &upload.fetch_code, &input.0).await.map_err(Failure::Storage)?;
+                let (processor, mime, max_duration) = (self.processor.clone(), upload.mime.clone(), self.max_video_duration);
+                let (input_path, output_path) = (input.0.clone(), output.0.clone());
+                let (video, posters) = spawn_blocking(move || media::render_video(&*processor, &input_path, &output_path, &mime, max_duration))
+                    .await
+                    .map_err(|e| Failure::Invalid(e.into()))?
+                    .map_err(Failure::Invalid)?;
+                let meta = Metadata {
+                    width: video.width as i32,
+                    height: video.height as i32,
+                    duration: Some(video.duration),
+                };
+                let mut renditions: Vec<(Variant, &'static str, u32, u32, Body)> = posters.into_iter().map(|r| (r.variant, r.mime, r.width, r.height, Body::Data(r.data))).collect();
+                renditions.push((Variant::Original, video.mime, video.width, video.height, Body::File(output.0.clone())));
+                (renditions, meta, Some(output))
+            }
+        };
+        let mut insertions: Vec<UploadRenditionInsertion> = Vec::new();
+        for (variant, mime, width, height, body) in renditions {
+            match store(storer, body).await {
+                Ok((fetch_code, size)) => insertions.push(UploadRenditionInsertion {
+                    upload: upload.id,
+                    variant: variant,
+                    fetch_code: fetch_code,
+                    mime: mime.to_owned(),
+                    width: width as i32,
+                    height: height as i32,
+                    size: size,
+                }),
+                Err(e) => {
+                    for ins in insertions {
+                        if let Err(e) = storer.delete(&ins.fetch_code).await {
+                            log::warn!("failed to delete rendition {}: {:?}", ins.fetch_code, e);
+                        }
+                    }
+                    return Err(Failure::Storage(e));
+                }
+            }
+        }
+        Ok((insertions, meta))
+    }
+}
+
+// 图片本身的尺寸以 original 为准
+fn metadata(renditions: &Vec<Rendition>) -> Result<Metadata, Error> {
+    let original = renditions.iter().find(|r| r.variant == Variant::Original).context("no original rendition")?;
+    Ok(Metadata {
+        width: original.width as i32,
+        height: original.height as i32,
+        duration: None,
+    })
+}
+
+async fn download<S, SM, SK>(storer: &S, fetch_code: &str, path: &Path) -> Result<(), Error>
+where
+    S: UploadStorer<SM, SK>,
+    SM: Stream<Item = Result<Bytes, Error>> + Unpin,
+    SK: Sink<Bytes, Error = Error> + Unpin,
+{
+    let mut file = fs::File::create(path).await.context("failed to create temporary video")?;
+    let mut stream = storer.get(fetch_code)?;
+    while let Some(bs) = stream.next().await {
+        file.write_all(&bs?).await.context("failed to write temporary video")?;
+    }
+    file.flush().await.context("failed to write temporary video")?;
+    Ok(())
+}
+
+async fn store<S, SM, SK>(storer: &S, body: Body) -> Result<(String, i64), Error>
+where
+    S: UploadStorer<SM, SK>,
+    SM: Stream,
+    SK: Sink<Bytes, Error = Error> + Unpin,
+{
+    let mut stream = match body {
+        Body::Data(data) => stream::once(future::ready(Ok(Bytes::from(data)))).boxed_local(),
+        Body::File(path) => ReaderStream::new(fs::File::open(&path).await.context("failed to open processed video")?)
+            .map_err(Error::from)
+            .boxed_local(),
+    };
+    let mut size = 0;
+    let (mut sink, fetch_code) = storer.store()?;
+    let written = async {
+        while let Some(bs) = stream.next().await {
+            let b = bs?;
+            size += b.len() as i64;
+            sink.send(b).await?;
+        }
+        sink.close().await
+    }
+    .await;
+    if let Err(e) = written {
+        if let Err(e) = storer.delete(&fetch_code).await {
+            log::warn!("failed to delete rendition {}: {:?}", fetch_code, e);
+        }
+        return Err(e.context("failed to store rendition"));
+    }
+    Ok((fetch_code, size))
+}